@@ -101,10 +101,19 @@ runButton.addEventListener('click', async () => {
   progress.style.width = '0%'
   text.textContent = 'Running...'
 
-  const { results, output } = await simulationRunner.runSimulation(runConfig, (launching, launched, total) => {
-    text.textContent = `Simulated ${launched} / ${total} photons`
-    progress.style.width = `${(launched / total) * 100}%`
-  })
+  let simulation
+  try {
+    simulation = await simulationRunner.runSimulation(runConfig, (launching, launched, total) => {
+      text.textContent = `Simulated ${launched} / ${total} photons`
+      progress.style.width = `${(launched / total) * 100}%`
+    })
+  } catch (e) {
+    console.error(e)
+    resetRunButton()
+    text.textContent = e.message
+    return
+  }
+  const { results, output } = simulation
 
   outputEditor.setValue(output, -1)
 
@@ -545,6 +554,12 @@ async function createVideo (results, container) {
           }
         }
       }
+
+      // Each frame is one 1 ps bin of the photon time of flight.
+      ctx.globalAlpha = 1
+      ctx.fillStyle = 'black'
+      ctx.font = '48px sans-serif'
+      ctx.fillText(`t = ${it} ps`, 24, 64)
     }
 
     render()
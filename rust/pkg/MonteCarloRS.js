@@ -1,298 +1,2201 @@
-let wasm;
+/**
+ * @enum {0 | 1}
+ */
+export const BeamKind = Object.freeze({
+    Gaussian: 0, "0": "Gaussian",
+    Flat: 1, "1": "Flat",
+});
 
-const heap = new Array(128).fill(undefined);
+export class ConvergenceReport {
+    static __wrap(ptr) {
+        const obj = Object.create(ConvergenceReport.prototype);
+        obj.__wbg_ptr = ptr;
+        ConvergenceReportFinalization.register(obj, obj.__wbg_ptr, obj);
+        return obj;
+    }
+    __destroy_into_raw() {
+        const ptr = this.__wbg_ptr;
+        this.__wbg_ptr = 0;
+        ConvergenceReportFinalization.unregister(this);
+        return ptr;
+    }
+    free() {
+        const ptr = this.__destroy_into_raw();
+        wasm.__wbg_convergencereport_free(ptr, 0);
+    }
+    /**
+     * @returns {number}
+     */
+    get a_error() {
+        const ret = wasm.__wbg_get_convergencereport_a_error(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    get photons() {
+        const ret = wasm.__wbg_get_convergencereport_photons(this.__wbg_ptr);
+        return ret >>> 0;
+    }
+    /**
+     * @returns {number}
+     */
+    get rd_error() {
+        const ret = wasm.__wbg_get_convergencereport_rd_error(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {StopReason}
+     */
+    get reason() {
+        const ret = wasm.__wbg_get_convergencereport_reason(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    get seconds() {
+        const ret = wasm.__wbg_get_convergencereport_seconds(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    get tt_error() {
+        const ret = wasm.__wbg_get_convergencereport_tt_error(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @param {number} arg0
+     */
+    set a_error(arg0) {
+        wasm.__wbg_set_convergencereport_a_error(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {number} arg0
+     */
+    set photons(arg0) {
+        wasm.__wbg_set_convergencereport_photons(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {number} arg0
+     */
+    set rd_error(arg0) {
+        wasm.__wbg_set_convergencereport_rd_error(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {StopReason} arg0
+     */
+    set reason(arg0) {
+        wasm.__wbg_set_convergencereport_reason(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {number} arg0
+     */
+    set seconds(arg0) {
+        wasm.__wbg_set_convergencereport_seconds(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {number} arg0
+     */
+    set tt_error(arg0) {
+        wasm.__wbg_set_convergencereport_tt_error(this.__wbg_ptr, arg0);
+    }
+}
+if (Symbol.dispose) ConvergenceReport.prototype[Symbol.dispose] = ConvergenceReport.prototype.free;
 
-heap.push(undefined, null, true, false);
+export class ConvolvedResults {
+    static __wrap(ptr) {
+        const obj = Object.create(ConvolvedResults.prototype);
+        obj.__wbg_ptr = ptr;
+        ConvolvedResultsFinalization.register(obj, obj.__wbg_ptr, obj);
+        return obj;
+    }
+    __destroy_into_raw() {
+        const ptr = this.__wbg_ptr;
+        this.__wbg_ptr = 0;
+        ConvolvedResultsFinalization.unregister(this);
+        return ptr;
+    }
+    free() {
+        const ptr = this.__destroy_into_raw();
+        wasm.__wbg_convolvedresults_free(ptr, 0);
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_a_rz() {
+        const ret = wasm.convolvedresults_get_a_rz(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_fluence_rz() {
+        const ret = wasm.convolvedresults_get_fluence_rz(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_rd_r() {
+        const ret = wasm.convolvedresults_get_rd_r(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_tt_r() {
+        const ret = wasm.convolvedresults_get_tt_r(this.__wbg_ptr);
+        return ret;
+    }
+}
+if (Symbol.dispose) ConvolvedResults.prototype[Symbol.dispose] = ConvolvedResults.prototype.free;
 
-let heap_next = heap.length;
+export class FinalResults {
+    static __wrap(ptr) {
+        const obj = Object.create(FinalResults.prototype);
+        obj.__wbg_ptr = ptr;
+        FinalResultsFinalization.register(obj, obj.__wbg_ptr, obj);
+        return obj;
+    }
+    __destroy_into_raw() {
+        const ptr = this.__wbg_ptr;
+        this.__wbg_ptr = 0;
+        FinalResultsFinalization.unregister(this);
+        return ptr;
+    }
+    free() {
+        const ptr = this.__destroy_into_raw();
+        wasm.__wbg_finalresults_free(ptr, 0);
+    }
+    /**
+     * @returns {number}
+     */
+    energy_balance() {
+        const ret = wasm.finalresults_energy_balance(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_a_l() {
+        const ret = wasm.finalresults_get_a_l(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_a_rz() {
+        const ret = wasm.finalresults_get_a_rz(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_a_rz_error() {
+        const ret = wasm.finalresults_get_a_rz_error(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_a_rz_fl() {
+        const ret = wasm.finalresults_get_a_rz_fl(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_a_z() {
+        const ret = wasm.finalresults_get_a_z(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_fluence() {
+        const ret = wasm.finalresults_get_fluence(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_jacobian() {
+        const ret = wasm.finalresults_get_jacobian(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_rd_a() {
+        const ret = wasm.finalresults_get_rd_a(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_rd_a_fl() {
+        const ret = wasm.finalresults_get_rd_a_fl(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_rd_path_mean() {
+        const ret = wasm.finalresults_get_rd_path_mean(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_rd_path_pdf() {
+        const ret = wasm.finalresults_get_rd_path_pdf(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_rd_r() {
+        const ret = wasm.finalresults_get_rd_r(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_rd_r_ac() {
+        const ret = wasm.finalresults_get_rd_r_ac(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_rd_r_fl() {
+        const ret = wasm.finalresults_get_rd_r_fl(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_rd_r_phase() {
+        const ret = wasm.finalresults_get_rd_r_phase(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_rd_ra() {
+        const ret = wasm.finalresults_get_rd_ra(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_rd_ra_error() {
+        const ret = wasm.finalresults_get_rd_ra_error(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_rd_ra_fl() {
+        const ret = wasm.finalresults_get_rd_ra_fl(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_rd_rt() {
+        const ret = wasm.finalresults_get_rd_rt(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_rd_t() {
+        const ret = wasm.finalresults_get_rd_t(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_rd_x() {
+        const ret = wasm.finalresults_get_rd_x(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_rd_x_error() {
+        const ret = wasm.finalresults_get_rd_x_error(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_stokes_xy() {
+        const ret = wasm.finalresults_get_stokes_xy(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_tt_a() {
+        const ret = wasm.finalresults_get_tt_a(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_tt_r() {
+        const ret = wasm.finalresults_get_tt_r(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_tt_ra() {
+        const ret = wasm.finalresults_get_tt_ra(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_tt_ra_error() {
+        const ret = wasm.finalresults_get_tt_ra_error(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_tt_rt() {
+        const ret = wasm.finalresults_get_tt_rt(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_tt_t() {
+        const ret = wasm.finalresults_get_tt_t(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_w_txz() {
+        const ret = wasm.finalresults_get_w_txz(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    total_reflectance() {
+        const ret = wasm.finalresults_total_reflectance(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    total_transmittance() {
+        const ret = wasm.finalresults_total_transmittance(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    get a_error() {
+        const ret = wasm.__wbg_get_finalresults_a_error(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    get a_fl() {
+        const ret = wasm.__wbg_get_finalresults_a_fl(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    get a() {
+        const ret = wasm.__wbg_get_finalresults_a(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    get rd_error() {
+        const ret = wasm.__wbg_get_finalresults_rd_error(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    get rd_fl() {
+        const ret = wasm.__wbg_get_finalresults_rd_fl(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    get rd_unscattered() {
+        const ret = wasm.__wbg_get_finalresults_rd_unscattered(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    get rd() {
+        const ret = wasm.__wbg_get_finalresults_rd(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    get rsp() {
+        const ret = wasm.__wbg_get_finalresults_rsp(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    get tt_error() {
+        const ret = wasm.__wbg_get_finalresults_tt_error(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    get tt_unscattered() {
+        const ret = wasm.__wbg_get_finalresults_tt_unscattered(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    get tt() {
+        const ret = wasm.__wbg_get_finalresults_tt(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @param {number} arg0
+     */
+    set a_error(arg0) {
+        wasm.__wbg_set_finalresults_a_error(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {number} arg0
+     */
+    set a_fl(arg0) {
+        wasm.__wbg_set_finalresults_a_fl(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {number} arg0
+     */
+    set a(arg0) {
+        wasm.__wbg_set_finalresults_a(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {number} arg0
+     */
+    set rd_error(arg0) {
+        wasm.__wbg_set_finalresults_rd_error(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {number} arg0
+     */
+    set rd_fl(arg0) {
+        wasm.__wbg_set_finalresults_rd_fl(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {number} arg0
+     */
+    set rd_unscattered(arg0) {
+        wasm.__wbg_set_finalresults_rd_unscattered(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {number} arg0
+     */
+    set rd(arg0) {
+        wasm.__wbg_set_finalresults_rd(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {number} arg0
+     */
+    set rsp(arg0) {
+        wasm.__wbg_set_finalresults_rsp(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {number} arg0
+     */
+    set tt_error(arg0) {
+        wasm.__wbg_set_finalresults_tt_error(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {number} arg0
+     */
+    set tt_unscattered(arg0) {
+        wasm.__wbg_set_finalresults_tt_unscattered(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {number} arg0
+     */
+    set tt(arg0) {
+        wasm.__wbg_set_finalresults_tt(this.__wbg_ptr, arg0);
+    }
+}
+if (Symbol.dispose) FinalResults.prototype[Symbol.dispose] = FinalResults.prototype.free;
 
-function addHeapObject(obj) {
-    if (heap_next === heap.length) heap.push(heap.length + 1);
-    const idx = heap_next;
-    heap_next = heap[idx];
+export class FitOptions {
+    __destroy_into_raw() {
+        const ptr = this.__wbg_ptr;
+        this.__wbg_ptr = 0;
+        FitOptionsFinalization.unregister(this);
+        return ptr;
+    }
+    free() {
+        const ptr = this.__destroy_into_raw();
+        wasm.__wbg_fitoptions_free(ptr, 0);
+    }
+    constructor() {
+        const ret = wasm.fitoptions_new();
+        this.__wbg_ptr = ret;
+        FitOptionsFinalization.register(this, this.__wbg_ptr, this);
+        return this;
+    }
+    /**
+     * @returns {number}
+     */
+    get max_iterations() {
+        const ret = wasm.__wbg_get_fitoptions_max_iterations(this.__wbg_ptr);
+        return ret >>> 0;
+    }
+    /**
+     * @returns {number}
+     */
+    get photons() {
+        const ret = wasm.__wbg_get_fitoptions_photons(this.__wbg_ptr);
+        return ret >>> 0;
+    }
+    /**
+     * @returns {number}
+     */
+    get rebase() {
+        const ret = wasm.__wbg_get_fitoptions_rebase(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {bigint}
+     */
+    get seed() {
+        const ret = wasm.__wbg_get_fitoptions_seed(this.__wbg_ptr);
+        return BigInt.asUintN(64, ret);
+    }
+    /**
+     * @returns {number}
+     */
+    get step() {
+        const ret = wasm.__wbg_get_fitoptions_step(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    get tolerance() {
+        const ret = wasm.__wbg_get_fitoptions_tolerance(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {boolean}
+     */
+    get use_perturbation() {
+        const ret = wasm.__wbg_get_fitoptions_use_perturbation(this.__wbg_ptr);
+        return ret !== 0;
+    }
+    /**
+     * @param {number} arg0
+     */
+    set max_iterations(arg0) {
+        wasm.__wbg_set_fitoptions_max_iterations(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {number} arg0
+     */
+    set photons(arg0) {
+        wasm.__wbg_set_fitoptions_photons(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {number} arg0
+     */
+    set rebase(arg0) {
+        wasm.__wbg_set_fitoptions_rebase(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {bigint} arg0
+     */
+    set seed(arg0) {
+        wasm.__wbg_set_fitoptions_seed(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {number} arg0
+     */
+    set step(arg0) {
+        wasm.__wbg_set_fitoptions_step(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {number} arg0
+     */
+    set tolerance(arg0) {
+        wasm.__wbg_set_fitoptions_tolerance(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {boolean} arg0
+     */
+    set use_perturbation(arg0) {
+        wasm.__wbg_set_fitoptions_use_perturbation(this.__wbg_ptr, arg0);
+    }
+}
+if (Symbol.dispose) FitOptions.prototype[Symbol.dispose] = FitOptions.prototype.free;
 
-    heap[idx] = obj;
-    return idx;
+export class FitResult {
+    static __wrap(ptr) {
+        const obj = Object.create(FitResult.prototype);
+        obj.__wbg_ptr = ptr;
+        FitResultFinalization.register(obj, obj.__wbg_ptr, obj);
+        return obj;
+    }
+    __destroy_into_raw() {
+        const ptr = this.__wbg_ptr;
+        this.__wbg_ptr = 0;
+        FitResultFinalization.unregister(this);
+        return ptr;
+    }
+    free() {
+        const ptr = this.__destroy_into_raw();
+        wasm.__wbg_fitresult_free(ptr, 0);
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_ci_lower() {
+        const ret = wasm.fitresult_get_ci_lower(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_ci_upper() {
+        const ret = wasm.fitresult_get_ci_upper(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_std_errors() {
+        const ret = wasm.fitresult_get_std_errors(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_values() {
+        const ret = wasm.fitresult_get_values(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    get chi2() {
+        const ret = wasm.__wbg_get_fitresult_chi2(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {boolean}
+     */
+    get converged() {
+        const ret = wasm.__wbg_get_fitresult_converged(this.__wbg_ptr);
+        return ret !== 0;
+    }
+    /**
+     * @returns {number}
+     */
+    get iterations() {
+        const ret = wasm.__wbg_get_fitresult_iterations(this.__wbg_ptr);
+        return ret >>> 0;
+    }
+    /**
+     * @returns {number}
+     */
+    get reduced_chi2() {
+        const ret = wasm.__wbg_get_fitresult_reduced_chi2(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    get simulations() {
+        const ret = wasm.__wbg_get_fitresult_simulations(this.__wbg_ptr);
+        return ret >>> 0;
+    }
+    /**
+     * @param {number} arg0
+     */
+    set chi2(arg0) {
+        wasm.__wbg_set_fitresult_chi2(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {boolean} arg0
+     */
+    set converged(arg0) {
+        wasm.__wbg_set_fitresult_converged(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {number} arg0
+     */
+    set iterations(arg0) {
+        wasm.__wbg_set_fitresult_iterations(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {number} arg0
+     */
+    set reduced_chi2(arg0) {
+        wasm.__wbg_set_fitresult_reduced_chi2(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {number} arg0
+     */
+    set simulations(arg0) {
+        wasm.__wbg_set_fitresult_simulations(this.__wbg_ptr, arg0);
+    }
+}
+if (Symbol.dispose) FitResult.prototype[Symbol.dispose] = FitResult.prototype.free;
+
+export class InverseProblem {
+    __destroy_into_raw() {
+        const ptr = this.__wbg_ptr;
+        this.__wbg_ptr = 0;
+        InverseProblemFinalization.unregister(this);
+        return ptr;
+    }
+    free() {
+        const ptr = this.__destroy_into_raw();
+        wasm.__wbg_inverseproblem_free(ptr, 0);
+    }
+    /**
+     * @param {number} layer
+     * @param {Property} property
+     * @param {number} initial
+     * @param {number} lower
+     * @param {number} upper
+     */
+    add_parameter(layer, property, initial, lower, upper) {
+        wasm.inverseproblem_add_parameter(this.__wbg_ptr, layer, property, initial, lower, upper);
+    }
+    clear_parameters() {
+        wasm.inverseproblem_clear_parameters(this.__wbg_ptr);
+    }
+    constructor() {
+        const ret = wasm.inverseproblem_new();
+        this.__wbg_ptr = ret;
+        InverseProblemFinalization.register(this, this.__wbg_ptr, this);
+        return this;
+    }
+    /**
+     * @param {number} rd
+     * @param {number} sigma
+     */
+    set_rd(rd, sigma) {
+        wasm.inverseproblem_set_rd(this.__wbg_ptr, rd, sigma);
+    }
+    /**
+     * @param {Float64Array} rd_r
+     * @param {Float64Array} sigma
+     */
+    set_rd_r(rd_r, sigma) {
+        const ptr0 = passArrayF64ToWasm0(rd_r, wasm.__wbindgen_malloc);
+        const len0 = WASM_VECTOR_LEN;
+        const ptr1 = passArrayF64ToWasm0(sigma, wasm.__wbindgen_malloc);
+        const len1 = WASM_VECTOR_LEN;
+        wasm.inverseproblem_set_rd_r(this.__wbg_ptr, ptr0, len0, ptr1, len1);
+    }
+    /**
+     * @param {number} tt
+     * @param {number} sigma
+     */
+    set_tt(tt, sigma) {
+        wasm.inverseproblem_set_tt(this.__wbg_ptr, tt, sigma);
+    }
 }
+if (Symbol.dispose) InverseProblem.prototype[Symbol.dispose] = InverseProblem.prototype.free;
 
-function getObject(idx) { return heap[idx]; }
-
-function dropObject(idx) {
-    if (idx < 132) return;
-    heap[idx] = heap_next;
-    heap_next = idx;
+export class MieResult {
+    static __wrap(ptr) {
+        const obj = Object.create(MieResult.prototype);
+        obj.__wbg_ptr = ptr;
+        MieResultFinalization.register(obj, obj.__wbg_ptr, obj);
+        return obj;
+    }
+    __destroy_into_raw() {
+        const ptr = this.__wbg_ptr;
+        this.__wbg_ptr = 0;
+        MieResultFinalization.unregister(this);
+        return ptr;
+    }
+    free() {
+        const ptr = this.__destroy_into_raw();
+        wasm.__wbg_mieresult_free(ptr, 0);
+    }
+    /**
+     * @returns {number}
+     */
+    get cabs() {
+        const ret = wasm.__wbg_get_mieresult_cabs(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    get csca() {
+        const ret = wasm.__wbg_get_mieresult_csca(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    get g() {
+        const ret = wasm.__wbg_get_mieresult_g(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    get mua() {
+        const ret = wasm.__wbg_get_mieresult_mua(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    get mus() {
+        const ret = wasm.__wbg_get_mieresult_mus(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    get qabs() {
+        const ret = wasm.__wbg_get_mieresult_qabs(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    get qext() {
+        const ret = wasm.__wbg_get_mieresult_qext(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    get qsca() {
+        const ret = wasm.__wbg_get_mieresult_qsca(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_mu() {
+        const ret = wasm.mieresult_get_mu(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_p() {
+        const ret = wasm.mieresult_get_p(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @param {number} arg0
+     */
+    set cabs(arg0) {
+        wasm.__wbg_set_mieresult_cabs(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {number} arg0
+     */
+    set csca(arg0) {
+        wasm.__wbg_set_mieresult_csca(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {number} arg0
+     */
+    set g(arg0) {
+        wasm.__wbg_set_mieresult_g(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {number} arg0
+     */
+    set mua(arg0) {
+        wasm.__wbg_set_mieresult_mua(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {number} arg0
+     */
+    set mus(arg0) {
+        wasm.__wbg_set_mieresult_mus(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {number} arg0
+     */
+    set qabs(arg0) {
+        wasm.__wbg_set_mieresult_qabs(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {number} arg0
+     */
+    set qext(arg0) {
+        wasm.__wbg_set_mieresult_qext(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {number} arg0
+     */
+    set qsca(arg0) {
+        wasm.__wbg_set_mieresult_qsca(this.__wbg_ptr, arg0);
+    }
 }
+if (Symbol.dispose) MieResult.prototype[Symbol.dispose] = MieResult.prototype.free;
 
-function takeObject(idx) {
-    const ret = getObject(idx);
-    dropObject(idx);
-    return ret;
+export class PerturbedResults {
+    static __wrap(ptr) {
+        const obj = Object.create(PerturbedResults.prototype);
+        obj.__wbg_ptr = ptr;
+        PerturbedResultsFinalization.register(obj, obj.__wbg_ptr, obj);
+        return obj;
+    }
+    __destroy_into_raw() {
+        const ptr = this.__wbg_ptr;
+        this.__wbg_ptr = 0;
+        PerturbedResultsFinalization.unregister(this);
+        return ptr;
+    }
+    free() {
+        const ptr = this.__destroy_into_raw();
+        wasm.__wbg_perturbedresults_free(ptr, 0);
+    }
+    /**
+     * @returns {number}
+     */
+    get rd_unscattered() {
+        const ret = wasm.__wbg_get_perturbedresults_rd_unscattered(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    get rd() {
+        const ret = wasm.__wbg_get_perturbedresults_rd(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    get tt_unscattered() {
+        const ret = wasm.__wbg_get_perturbedresults_tt_unscattered(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    get tt() {
+        const ret = wasm.__wbg_get_perturbedresults_tt(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_rd_a() {
+        const ret = wasm.perturbedresults_get_rd_a(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_rd_r() {
+        const ret = wasm.perturbedresults_get_rd_r(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_rd_ra() {
+        const ret = wasm.perturbedresults_get_rd_ra(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_tt_a() {
+        const ret = wasm.perturbedresults_get_tt_a(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_tt_r() {
+        const ret = wasm.perturbedresults_get_tt_r(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_tt_ra() {
+        const ret = wasm.perturbedresults_get_tt_ra(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @param {number} arg0
+     */
+    set rd_unscattered(arg0) {
+        wasm.__wbg_set_perturbedresults_rd_unscattered(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {number} arg0
+     */
+    set rd(arg0) {
+        wasm.__wbg_set_perturbedresults_rd(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {number} arg0
+     */
+    set tt_unscattered(arg0) {
+        wasm.__wbg_set_perturbedresults_tt_unscattered(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {number} arg0
+     */
+    set tt(arg0) {
+        wasm.__wbg_set_perturbedresults_tt(this.__wbg_ptr, arg0);
+    }
 }
+if (Symbol.dispose) PerturbedResults.prototype[Symbol.dispose] = PerturbedResults.prototype.free;
 
-const cachedTextDecoder = (typeof TextDecoder !== 'undefined' ? new TextDecoder('utf-8', { ignoreBOM: true, fatal: true }) : { decode: () => { throw Error('TextDecoder not available') } } );
-
-if (typeof TextDecoder !== 'undefined') { cachedTextDecoder.decode(); };
+/**
+ * @enum {0 | 1 | 2 | 3 | 4 | 5}
+ */
+export const PhaseKind = Object.freeze({
+    HenyeyGreenstein: 0, "0": "HenyeyGreenstein",
+    ModifiedHenyeyGreenstein: 1, "1": "ModifiedHenyeyGreenstein",
+    TwoTermHenyeyGreenstein: 2, "2": "TwoTermHenyeyGreenstein",
+    Gegenbauer: 3, "3": "Gegenbauer",
+    Rayleigh: 4, "4": "Rayleigh",
+    Mie: 5, "5": "Mie",
+});
 
-let cachedUint8Memory0 = null;
+/**
+ * @enum {0 | 1 | 2}
+ */
+export const Property = Object.freeze({
+    Mua: 0, "0": "Mua",
+    Mus: 1, "1": "Mus",
+    G: 2, "2": "G",
+});
 
-function getUint8Memory0() {
-    if (cachedUint8Memory0 === null || cachedUint8Memory0.byteLength === 0) {
-        cachedUint8Memory0 = new Uint8Array(wasm.memory.buffer);
+export class Simulation {
+    static __wrap(ptr) {
+        const obj = Object.create(Simulation.prototype);
+        obj.__wbg_ptr = ptr;
+        SimulationFinalization.register(obj, obj.__wbg_ptr, obj);
+        return obj;
+    }
+    __destroy_into_raw() {
+        const ptr = this.__wbg_ptr;
+        this.__wbg_ptr = 0;
+        SimulationFinalization.unregister(this);
+        return ptr;
+    }
+    free() {
+        const ptr = this.__destroy_into_raw();
+        wasm.__wbg_simulation_free(ptr, 0);
+    }
+    /**
+     * @param {number} ir
+     */
+    add_detector(ir) {
+        wasm.simulation_add_detector(this.__wbg_ptr, ir);
+    }
+    /**
+     * @param {number} frequency
+     */
+    add_frequency(frequency) {
+        wasm.simulation_add_frequency(this.__wbg_ptr, frequency);
+    }
+    /**
+     * @param {number} n
+     * @param {number} mua
+     * @param {number} mus
+     * @param {number} g
+     * @param {number} d
+     */
+    add_layer(n, mua, mus, g, d) {
+        wasm.simulation_add_layer(this.__wbg_ptr, n, mua, mus, g, d);
+    }
+    /**
+     * @param {number} n
+     * @param {number} mua
+     * @param {number} mus
+     * @param {number} d
+     * @param {PhaseKind} kind
+     * @param {Float64Array} parameters
+     */
+    add_layer_phase(n, mua, mus, d, kind, parameters) {
+        const ptr0 = passArrayF64ToWasm0(parameters, wasm.__wbindgen_malloc);
+        const len0 = WASM_VECTOR_LEN;
+        const ret = wasm.simulation_add_layer_phase(this.__wbg_ptr, n, mua, mus, d, kind, ptr0, len0);
+        if (ret[1]) {
+            throw takeFromExternrefTable0(ret[0]);
+        }
+    }
+    /**
+     * @param {number} n
+     * @param {number} mua
+     * @param {number} d
+     * @param {Spheres} spheres
+     * @param {boolean} tabulated
+     * @returns {MieResult}
+     */
+    add_layer_spheres(n, mua, d, spheres, tabulated) {
+        _assertClass(spheres, Spheres);
+        const ret = wasm.simulation_add_layer_spheres(this.__wbg_ptr, n, mua, d, spheres.__wbg_ptr, tabulated);
+        if (ret[2]) {
+            throw takeFromExternrefTable0(ret[1]);
+        }
+        return MieResult.__wrap(ret[0]);
+    }
+    /**
+     * @param {number} n
+     * @param {number} mua
+     * @param {number} mus
+     * @param {number} d
+     * @param {Float64Array} mu
+     * @param {Float64Array} p
+     */
+    add_layer_tabulated(n, mua, mus, d, mu, p) {
+        const ptr0 = passArrayF64ToWasm0(mu, wasm.__wbindgen_malloc);
+        const len0 = WASM_VECTOR_LEN;
+        const ptr1 = passArrayF64ToWasm0(p, wasm.__wbindgen_malloc);
+        const len1 = WASM_VECTOR_LEN;
+        const ret = wasm.simulation_add_layer_tabulated(this.__wbg_ptr, n, mua, mus, d, ptr0, len0, ptr1, len1);
+        if (ret[1]) {
+            throw takeFromExternrefTable0(ret[0]);
+        }
+    }
+    clear_detectors() {
+        wasm.simulation_clear_detectors(this.__wbg_ptr);
+    }
+    clear_frequencies() {
+        wasm.simulation_clear_frequencies(this.__wbg_ptr);
+    }
+    clear_layers() {
+        wasm.simulation_clear_layers(this.__wbg_ptr);
+    }
+    /**
+     * @param {number} alpha
+     * @param {number} dz
+     * @param {number} dr
+     * @param {number} da
+     * @param {number} nz
+     * @param {number} nr
+     * @param {number} na
+     * @param {number} nt
+     * @param {number} wth
+     * @param {number} chance
+     */
+    configure_run(alpha, dz, dr, da, nz, nr, na, nt, wth, chance) {
+        wasm.simulation_configure_run(this.__wbg_ptr, alpha, dz, dr, da, nz, nr, na, nt, wth, chance);
+    }
+    /**
+     * @param {BeamKind} kind
+     * @param {number} radius
+     * @param {number} power
+     * @returns {ConvolvedResults}
+     */
+    convolve(kind, radius, power) {
+        const ret = wasm.simulation_convolve(this.__wbg_ptr, kind, radius, power);
+        if (ret[2]) {
+            throw takeFromExternrefTable0(ret[1]);
+        }
+        return ConvolvedResults.__wrap(ret[0]);
+    }
+    /**
+     * @param {number} n_photons
+     * @returns {FinalResults}
+     */
+    finalize(n_photons) {
+        const ret = wasm.simulation_finalize(this.__wbg_ptr, n_photons);
+        return FinalResults.__wrap(ret);
+    }
+    /**
+     * @param {InverseProblem} problem
+     * @param {FitOptions} options
+     * @returns {FitResult}
+     */
+    fit_layers(problem, options) {
+        _assertClass(problem, InverseProblem);
+        _assertClass(options, FitOptions);
+        const ret = wasm.simulation_fit_layers(this.__wbg_ptr, problem.__wbg_ptr, options.__wbg_ptr);
+        if (ret[2]) {
+            throw takeFromExternrefTable0(ret[1]);
+        }
+        return FitResult.__wrap(ret[0]);
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_a_rz() {
+        const ret = wasm.simulation_get_a_rz(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_a_rz_fl() {
+        const ret = wasm.simulation_get_a_rz_fl(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_jacobian() {
+        const ret = wasm.simulation_get_jacobian(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    get_number_of_photons() {
+        const ret = wasm.simulation_get_number_of_photons(this.__wbg_ptr);
+        return ret >>> 0;
+    }
+    /**
+     * @returns {number}
+     */
+    get_photons_launched() {
+        const ret = wasm.simulation_get_photons_launched(this.__wbg_ptr);
+        return ret >>> 0;
+    }
+    /**
+     * @returns {number}
+     */
+    get_r_specular() {
+        const ret = wasm.simulation_get_r_specular(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_rd_path() {
+        const ret = wasm.simulation_get_rd_path(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_rd_path_hist() {
+        const ret = wasm.simulation_get_rd_path_hist(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_rd_r_fd() {
+        const ret = wasm.simulation_get_rd_r_fd(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_rd_ra() {
+        const ret = wasm.simulation_get_rd_ra(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_rd_ra_fl() {
+        const ret = wasm.simulation_get_rd_ra_fl(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_rd_rt() {
+        const ret = wasm.simulation_get_rd_rt(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    get_rd_unscattered() {
+        const ret = wasm.simulation_get_rd_unscattered(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_rd_x() {
+        const ret = wasm.simulation_get_rd_x(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_stokes_xy() {
+        const ret = wasm.simulation_get_stokes_xy(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_tt_ra() {
+        const ret = wasm.simulation_get_tt_ra(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_tt_rt() {
+        const ret = wasm.simulation_get_tt_rt(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    get_tt_unscattered() {
+        const ret = wasm.simulation_get_tt_unscattered(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_w_txz() {
+        const ret = wasm.simulation_get_w_txz(this.__wbg_ptr);
+        return ret;
+    }
+    initialize() {
+        const ret = wasm.simulation_initialize(this.__wbg_ptr);
+        if (ret[1]) {
+            throw takeFromExternrefTable0(ret[0]);
+        }
+    }
+    launch_photon() {
+        wasm.simulation_launch_photon(this.__wbg_ptr);
+    }
+    /**
+     * @param {number} n
+     */
+    launch_photons(n) {
+        wasm.simulation_launch_photons(this.__wbg_ptr, n);
+    }
+    /**
+     * @param {StopCriteria} criteria
+     * @returns {ConvergenceReport}
+     */
+    launch_until(criteria) {
+        _assertClass(criteria, StopCriteria);
+        const ret = wasm.simulation_launch_until(this.__wbg_ptr, criteria.__wbg_ptr);
+        if (ret[2]) {
+            throw takeFromExternrefTable0(ret[1]);
+        }
+        return ConvergenceReport.__wrap(ret[0]);
+    }
+    /**
+     * @param {string} body
+     * @param {number} run
+     */
+    load_mci(body, run) {
+        const ptr0 = passStringToWasm0(body, wasm.__wbindgen_malloc, wasm.__wbindgen_realloc);
+        const len0 = WASM_VECTOR_LEN;
+        const ret = wasm.simulation_load_mci(this.__wbg_ptr, ptr0, len0, run);
+        if (ret[1]) {
+            throw takeFromExternrefTable0(ret[0]);
+        }
+    }
+    /**
+     * @param {Uint8Array} bytes
+     */
+    merge_results(bytes) {
+        const ptr0 = passArray8ToWasm0(bytes, wasm.__wbindgen_malloc);
+        const len0 = WASM_VECTOR_LEN;
+        const ret = wasm.simulation_merge_results(this.__wbg_ptr, ptr0, len0);
+        if (ret[1]) {
+            throw takeFromExternrefTable0(ret[0]);
+        }
+    }
+    /**
+     * @returns {Simulation}
+     */
+    static new() {
+        const ret = wasm.simulation_new();
+        return Simulation.__wrap(ret);
+    }
+    /**
+     * @param {Float64Array} mua
+     * @param {Float64Array} mus
+     * @returns {PerturbedResults}
+     */
+    perturb(mua, mus) {
+        const ptr0 = passArrayF64ToWasm0(mua, wasm.__wbindgen_malloc);
+        const len0 = WASM_VECTOR_LEN;
+        const ptr1 = passArrayF64ToWasm0(mus, wasm.__wbindgen_malloc);
+        const len1 = WASM_VECTOR_LEN;
+        const ret = wasm.simulation_perturb(this.__wbg_ptr, ptr0, len0, ptr1, len1);
+        if (ret[2]) {
+            throw takeFromExternrefTable0(ret[1]);
+        }
+        return PerturbedResults.__wrap(ret[0]);
+    }
+    /**
+     * @param {Uint8Array} bytes
+     * @returns {Simulation}
+     */
+    static restore(bytes) {
+        const ptr0 = passArray8ToWasm0(bytes, wasm.__wbindgen_malloc);
+        const len0 = WASM_VECTOR_LEN;
+        const ret = wasm.simulation_restore(ptr0, len0);
+        if (ret[2]) {
+            throw takeFromExternrefTable0(ret[1]);
+        }
+        return Simulation.__wrap(ret[0]);
+    }
+    /**
+     * @param {number} mua
+     * @param {number} mus
+     * @returns {WhiteResults}
+     */
+    scale_white(mua, mus) {
+        const ret = wasm.simulation_scale_white(this.__wbg_ptr, mua, mus);
+        if (ret[2]) {
+            throw takeFromExternrefTable0(ret[1]);
+        }
+        return WhiteResults.__wrap(ret[0]);
+    }
+    /**
+     * @returns {Uint8Array}
+     */
+    serialize_results() {
+        const ret = wasm.simulation_serialize_results(this.__wbg_ptr);
+        var v1 = getArrayU8FromWasm0(ret[0], ret[1]).slice();
+        wasm.__wbindgen_free(ret[0], ret[1] * 1, 1);
+        return v1;
+    }
+    /**
+     * @param {number} layer
+     * @param {number} mua_f
+     * @param {number} quantum_yield
+     * @param {number} mua_em
+     * @param {number} mus_em
+     * @param {number} g_em
+     */
+    set_layer_fluorescence(layer, mua_f, quantum_yield, mua_em, mus_em, g_em) {
+        const ret = wasm.simulation_set_layer_fluorescence(this.__wbg_ptr, layer, mua_f, quantum_yield, mua_em, mus_em, g_em);
+        if (ret[1]) {
+            throw takeFromExternrefTable0(ret[0]);
+        }
+    }
+    /**
+     * @param {number} layer
+     * @param {number} radius
+     * @param {number} n_sphere
+     */
+    set_layer_scatterer(layer, radius, n_sphere) {
+        const ret = wasm.simulation_set_layer_scatterer(this.__wbg_ptr, layer, radius, n_sphere);
+        if (ret[1]) {
+            throw takeFromExternrefTable0(ret[0]);
+        }
+    }
+    /**
+     * @param {number} dl
+     * @param {number} npath
+     */
+    set_path_bins(dl, npath) {
+        wasm.simulation_set_path_bins(this.__wbg_ptr, dl, npath);
+    }
+    /**
+     * @param {boolean} polarized
+     * @param {number} wavelength
+     * @param {number} i
+     * @param {number} q
+     * @param {number} u
+     * @param {number} v
+     */
+    set_polarization(polarized, wavelength, i, q, u, v) {
+        wasm.simulation_set_polarization(this.__wbg_ptr, polarized, wavelength, i, q, u, v);
+    }
+    /**
+     * @param {boolean} record
+     */
+    set_record_histories(record) {
+        wasm.simulation_set_record_histories(this.__wbg_ptr, record);
+    }
+    /**
+     * @param {bigint} seed
+     */
+    set_seed(seed) {
+        wasm.simulation_set_seed(this.__wbg_ptr, seed);
+    }
+    /**
+     * @param {SourceKind} kind
+     * @param {Float64Array} parameters
+     */
+    set_source(kind, parameters) {
+        const ptr0 = passArrayF64ToWasm0(parameters, wasm.__wbindgen_malloc);
+        const len0 = WASM_VECTOR_LEN;
+        const ret = wasm.simulation_set_source(this.__wbg_ptr, kind, ptr0, len0);
+        if (ret[1]) {
+            throw takeFromExternrefTable0(ret[0]);
+        }
+    }
+    /**
+     * @param {number} dt
+     * @param {number} t0
+     */
+    set_time_bins(dt, t0) {
+        wasm.simulation_set_time_bins(this.__wbg_ptr, dt, t0);
+    }
+    /**
+     * @param {boolean} track
+     */
+    set_track_uncertainty(track) {
+        wasm.simulation_set_track_uncertainty(this.__wbg_ptr, track);
+    }
+    /**
+     * @param {boolean} white
+     * @param {number} max_path
+     */
+    set_white(white, max_path) {
+        wasm.simulation_set_white(this.__wbg_ptr, white, max_path);
+    }
+    /**
+     * @returns {Uint8Array}
+     */
+    snapshot() {
+        const ret = wasm.simulation_snapshot(this.__wbg_ptr);
+        var v1 = getArrayU8FromWasm0(ret[0], ret[1]).slice();
+        wasm.__wbindgen_free(ret[0], ret[1] * 1, 1);
+        return v1;
     }
-    return cachedUint8Memory0;
 }
+if (Symbol.dispose) Simulation.prototype[Symbol.dispose] = Simulation.prototype.free;
 
-function getStringFromWasm0(ptr, len) {
-    ptr = ptr >>> 0;
-    return cachedTextDecoder.decode(getUint8Memory0().subarray(ptr, ptr + len));
-}
 /**
-*/
-export class Simulation {
-
-    static __wrap(ptr) {
-        ptr = ptr >>> 0;
-        const obj = Object.create(Simulation.prototype);
-        obj.__wbg_ptr = ptr;
+ * @enum {0 | 1 | 2 | 3 | 4 | 5 | 6 | 7 | 8 | 9}
+ */
+export const SourceKind = Object.freeze({
+    Pencil: 0, "0": "Pencil",
+    Gaussian: 1, "1": "Gaussian",
+    Flat: 2, "2": "Flat",
+    Rectangular: 3, "3": "Rectangular",
+    Annular: 4, "4": "Annular",
+    Line: 5, "5": "Line",
+    Fiber: 6, "6": "Fiber",
+    Focused: 7, "7": "Focused",
+    Isotropic: 8, "8": "Isotropic",
+    Lambertian: 9, "9": "Lambertian",
+});
 
-        return obj;
+export class Spheres {
+    __destroy_into_raw() {
+        const ptr = this.__wbg_ptr;
+        this.__wbg_ptr = 0;
+        SpheresFinalization.unregister(this);
+        return ptr;
     }
+    free() {
+        const ptr = this.__destroy_into_raw();
+        wasm.__wbg_spheres_free(ptr, 0);
+    }
+    /**
+     * @returns {number}
+     */
+    get n_im() {
+        const ret = wasm.__wbg_get_spheres_n_im(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    get n_re() {
+        const ret = wasm.__wbg_get_spheres_n_re(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    get volume_fraction() {
+        const ret = wasm.__wbg_get_spheres_volume_fraction(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    get wavelength() {
+        const ret = wasm.__wbg_get_spheres_wavelength(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @param {number} arg0
+     */
+    set n_im(arg0) {
+        wasm.__wbg_set_spheres_n_im(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {number} arg0
+     */
+    set n_re(arg0) {
+        wasm.__wbg_set_spheres_n_re(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {number} arg0
+     */
+    set volume_fraction(arg0) {
+        wasm.__wbg_set_spheres_volume_fraction(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {number} arg0
+     */
+    set wavelength(arg0) {
+        wasm.__wbg_set_spheres_wavelength(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @param {number} wavelength
+     * @param {number} n_re
+     * @param {number} n_im
+     * @param {number} volume_fraction
+     * @param {number} radius
+     */
+    constructor(wavelength, n_re, n_im, volume_fraction, radius) {
+        const ret = wasm.spheres_new(wavelength, n_re, n_im, volume_fraction, radius);
+        this.__wbg_ptr = ret;
+        SpheresFinalization.register(this, this.__wbg_ptr, this);
+        return this;
+    }
+    /**
+     * @param {number} median
+     * @param {number} sigma
+     */
+    set_log_normal(median, sigma) {
+        wasm.spheres_set_log_normal(this.__wbg_ptr, median, sigma);
+    }
+    /**
+     * @param {number} radius
+     */
+    set_monodisperse(radius) {
+        wasm.spheres_set_monodisperse(this.__wbg_ptr, radius);
+    }
+    /**
+     * @param {number} mean
+     * @param {number} sd
+     */
+    set_normal(mean, sd) {
+        wasm.spheres_set_normal(this.__wbg_ptr, mean, sd);
+    }
+    /**
+     * @param {Float64Array} radii
+     * @param {Float64Array} fractions
+     */
+    set_sizes(radii, fractions) {
+        const ptr0 = passArrayF64ToWasm0(radii, wasm.__wbindgen_malloc);
+        const len0 = WASM_VECTOR_LEN;
+        const ptr1 = passArrayF64ToWasm0(fractions, wasm.__wbindgen_malloc);
+        const len1 = WASM_VECTOR_LEN;
+        wasm.spheres_set_sizes(this.__wbg_ptr, ptr0, len0, ptr1, len1);
+    }
+}
+if (Symbol.dispose) Spheres.prototype[Symbol.dispose] = Spheres.prototype.free;
 
+export class StopCriteria {
     __destroy_into_raw() {
         const ptr = this.__wbg_ptr;
         this.__wbg_ptr = 0;
-
+        StopCriteriaFinalization.unregister(this);
         return ptr;
     }
-
     free() {
         const ptr = this.__destroy_into_raw();
-        wasm.__wbg_simulation_free(ptr);
+        wasm.__wbg_stopcriteria_free(ptr, 0);
     }
     /**
-    * @returns {Simulation}
-    */
-    static new() {
-        const ret = wasm.simulation_new();
-        return Simulation.__wrap(ret);
+     * @returns {number}
+     */
+    get a_error() {
+        const ret = wasm.__wbg_get_stopcriteria_a_error(this.__wbg_ptr);
+        return ret;
     }
     /**
-    * @param {number} alpha
-    * @param {number} dz
-    * @param {number} dr
-    * @param {number} da
-    * @param {number} nz
-    * @param {number} nr
-    * @param {number} na
-    * @param {number} nt
-    * @param {number} wth
-    * @param {number} chance
-    */
-    configure_run(alpha, dz, dr, da, nz, nr, na, nt, wth, chance) {
-        wasm.simulation_configure_run(this.__wbg_ptr, alpha, dz, dr, da, nz, nr, na, nt, wth, chance);
+     * @returns {number}
+     */
+    get batch_size() {
+        const ret = wasm.__wbg_get_stopcriteria_batch_size(this.__wbg_ptr);
+        return ret >>> 0;
     }
     /**
-    * @param {bigint} seed
-    */
-    set_seed(seed) {
-        wasm.simulation_set_seed(this.__wbg_ptr, seed);
+     * @returns {number}
+     */
+    get max_photons() {
+        const ret = wasm.__wbg_get_stopcriteria_max_photons(this.__wbg_ptr);
+        return ret >>> 0;
     }
     /**
-    */
-    clear_layers() {
-        wasm.simulation_clear_layers(this.__wbg_ptr);
+     * @returns {number}
+     */
+    get max_seconds() {
+        const ret = wasm.__wbg_get_stopcriteria_max_seconds(this.__wbg_ptr);
+        return ret;
     }
     /**
-    * @param {number} n
-    * @param {number} mua
-    * @param {number} mus
-    * @param {number} g
-    * @param {number} d
-    */
-    add_layer(n, mua, mus, g, d) {
-        wasm.simulation_add_layer(this.__wbg_ptr, n, mua, mus, g, d);
+     * @returns {number}
+     */
+    get rd_error() {
+        const ret = wasm.__wbg_get_stopcriteria_rd_error(this.__wbg_ptr);
+        return ret;
     }
     /**
-    */
-    initialize() {
-        wasm.simulation_initialize(this.__wbg_ptr);
+     * @returns {number}
+     */
+    get tt_error() {
+        const ret = wasm.__wbg_get_stopcriteria_tt_error(this.__wbg_ptr);
+        return ret;
     }
     /**
-    */
-    launch_photon() {
-        wasm.simulation_launch_photon(this.__wbg_ptr);
+     * @param {number} arg0
+     */
+    set a_error(arg0) {
+        wasm.__wbg_set_stopcriteria_a_error(this.__wbg_ptr, arg0);
     }
     /**
-    * @param {number} n
-    */
-    launch_photons(n) {
-        wasm.simulation_launch_photons(this.__wbg_ptr, n);
+     * @param {number} arg0
+     */
+    set batch_size(arg0) {
+        wasm.__wbg_set_stopcriteria_batch_size(this.__wbg_ptr, arg0);
     }
     /**
-    * @returns {Float64Array}
-    */
-    get_tt_ra() {
-        const ret = wasm.simulation_get_tt_ra(this.__wbg_ptr);
-        return takeObject(ret);
+     * @param {number} arg0
+     */
+    set max_photons(arg0) {
+        wasm.__wbg_set_stopcriteria_max_photons(this.__wbg_ptr, arg0);
     }
     /**
-    * @returns {Float64Array}
-    */
-    get_rd_ra() {
-        const ret = wasm.simulation_get_rd_ra(this.__wbg_ptr);
-        return takeObject(ret);
+     * @param {number} arg0
+     */
+    set max_seconds(arg0) {
+        wasm.__wbg_set_stopcriteria_max_seconds(this.__wbg_ptr, arg0);
     }
     /**
-    * @returns {Float64Array}
-    */
-    get_rd_x() {
-        const ret = wasm.simulation_get_rd_x(this.__wbg_ptr);
-        return takeObject(ret);
+     * @param {number} arg0
+     */
+    set rd_error(arg0) {
+        wasm.__wbg_set_stopcriteria_rd_error(this.__wbg_ptr, arg0);
     }
     /**
-    * @returns {Float64Array}
-    */
-    get_a_rz() {
-        const ret = wasm.simulation_get_a_rz(this.__wbg_ptr);
-        return takeObject(ret);
+     * @param {number} arg0
+     */
+    set tt_error(arg0) {
+        wasm.__wbg_set_stopcriteria_tt_error(this.__wbg_ptr, arg0);
+    }
+    constructor() {
+        const ret = wasm.stopcriteria_new();
+        this.__wbg_ptr = ret;
+        StopCriteriaFinalization.register(this, this.__wbg_ptr, this);
+        return this;
+    }
+}
+if (Symbol.dispose) StopCriteria.prototype[Symbol.dispose] = StopCriteria.prototype.free;
+
+/**
+ * @enum {0 | 1 | 2}
+ */
+export const StopReason = Object.freeze({
+    Converged: 0, "0": "Converged",
+    PhotonBudget: 1, "1": "PhotonBudget",
+    TimeBudget: 2, "2": "TimeBudget",
+});
+
+export class WhiteResults {
+    static __wrap(ptr) {
+        const obj = Object.create(WhiteResults.prototype);
+        obj.__wbg_ptr = ptr;
+        WhiteResultsFinalization.register(obj, obj.__wbg_ptr, obj);
+        return obj;
+    }
+    __destroy_into_raw() {
+        const ptr = this.__wbg_ptr;
+        this.__wbg_ptr = 0;
+        WhiteResultsFinalization.unregister(this);
+        return ptr;
+    }
+    free() {
+        const ptr = this.__destroy_into_raw();
+        wasm.__wbg_whiteresults_free(ptr, 0);
     }
     /**
-    * @returns {Float64Array}
-    */
-    get_w_txz() {
-        const ret = wasm.simulation_get_w_txz(this.__wbg_ptr);
-        return takeObject(ret);
+     * @returns {number}
+     */
+    get rd() {
+        const ret = wasm.__wbg_get_whiteresults_rd(this.__wbg_ptr);
+        return ret;
     }
     /**
-    * @returns {number}
-    */
-    get_r_specular() {
-        const ret = wasm.simulation_get_r_specular(this.__wbg_ptr);
+     * @param {number} arg0
+     */
+    set rd(arg0) {
+        wasm.__wbg_set_whiteresults_rd(this.__wbg_ptr, arg0);
+    }
+    /**
+     * @returns {Float64Array}
+     */
+    get_rd_r() {
+        const ret = wasm.whiteresults_get_rd_r(this.__wbg_ptr);
         return ret;
     }
     /**
-    * @returns {number}
-    */
-    get_rd_unscattered() {
-        const ret = wasm.simulation_get_rd_unscattered(this.__wbg_ptr);
+     * @returns {Float64Array}
+     */
+    get_rd_rt() {
+        const ret = wasm.whiteresults_get_rd_rt(this.__wbg_ptr);
         return ret;
     }
     /**
-    * @returns {number}
-    */
-    get_tt_unscattered() {
-        const ret = wasm.simulation_get_tt_unscattered(this.__wbg_ptr);
+     * @returns {Float64Array}
+     */
+    get_rd_t() {
+        const ret = wasm.whiteresults_get_rd_t(this.__wbg_ptr);
         return ret;
     }
 }
+if (Symbol.dispose) WhiteResults.prototype[Symbol.dispose] = WhiteResults.prototype.free;
+
+/**
+ * @param {Spheres} spheres
+ * @param {number} n_medium
+ * @returns {MieResult}
+ */
+export function compute_mie(spheres, n_medium) {
+    _assertClass(spheres, Spheres);
+    const ret = wasm.compute_mie(spheres.__wbg_ptr, n_medium);
+    if (ret[2]) {
+        throw takeFromExternrefTable0(ret[1]);
+    }
+    return MieResult.__wrap(ret[0]);
+}
+
+/**
+ * @param {string} body
+ * @returns {number}
+ */
+export function mci_run_count(body) {
+    const ptr0 = passStringToWasm0(body, wasm.__wbindgen_malloc, wasm.__wbindgen_realloc);
+    const len0 = WASM_VECTOR_LEN;
+    const ret = wasm.mci_run_count(ptr0, len0);
+    if (ret[2]) {
+        throw takeFromExternrefTable0(ret[1]);
+    }
+    return ret[0] >>> 0;
+}
+function __wbg_get_imports() {
+    const import0 = {
+        __proto__: null,
+        __wbg_Error_30c8987f7c2ed4e2: function(arg0, arg1) {
+            const ret = Error(getStringFromWasm0(arg0, arg1));
+            return ret;
+        },
+        __wbg___wbindgen_memory_caa4a6165639c8b5: function() {
+            const ret = wasm.memory;
+            return ret;
+        },
+        __wbg___wbindgen_throw_41e9ee4f547fc59a: function(arg0, arg1) {
+            throw new Error(getStringFromWasm0(arg0, arg1));
+        },
+        __wbg_buffer_6bea8b9c82ca6793: function(arg0) {
+            const ret = arg0.buffer;
+            return ret;
+        },
+        __wbg_new_ca73b6a4e3747e02: function(arg0) {
+            const ret = new Float64Array(arg0);
+            return ret;
+        },
+        __wbg_new_with_byte_offset_and_length_fb17a2ed92402c99: function(arg0, arg1, arg2) {
+            const ret = new Float64Array(arg0, arg1 >>> 0, arg2 >>> 0);
+            return ret;
+        },
+        __wbg_now_09297098ebcedb6d: function() {
+            const ret = Date.now();
+            return ret;
+        },
+        __wbindgen_init_externref_table: function() {
+            const table = wasm.__wbindgen_externrefs;
+            const offset = table.grow(4);
+            table.set(0, undefined);
+            table.set(offset + 0, undefined);
+            table.set(offset + 1, null);
+            table.set(offset + 2, true);
+            table.set(offset + 3, false);
+        },
+    };
+    return {
+        __proto__: null,
+        "./MonteCarloRS_bg.js": import0,
+    };
+}
+
+const ConvergenceReportFinalization = (typeof FinalizationRegistry === 'undefined')
+    ? { register: () => {}, unregister: () => {} }
+    : new FinalizationRegistry(ptr => wasm.__wbg_convergencereport_free(ptr, 1));
+const ConvolvedResultsFinalization = (typeof FinalizationRegistry === 'undefined')
+    ? { register: () => {}, unregister: () => {} }
+    : new FinalizationRegistry(ptr => wasm.__wbg_convolvedresults_free(ptr, 1));
+const FinalResultsFinalization = (typeof FinalizationRegistry === 'undefined')
+    ? { register: () => {}, unregister: () => {} }
+    : new FinalizationRegistry(ptr => wasm.__wbg_finalresults_free(ptr, 1));
+const FitOptionsFinalization = (typeof FinalizationRegistry === 'undefined')
+    ? { register: () => {}, unregister: () => {} }
+    : new FinalizationRegistry(ptr => wasm.__wbg_fitoptions_free(ptr, 1));
+const FitResultFinalization = (typeof FinalizationRegistry === 'undefined')
+    ? { register: () => {}, unregister: () => {} }
+    : new FinalizationRegistry(ptr => wasm.__wbg_fitresult_free(ptr, 1));
+const InverseProblemFinalization = (typeof FinalizationRegistry === 'undefined')
+    ? { register: () => {}, unregister: () => {} }
+    : new FinalizationRegistry(ptr => wasm.__wbg_inverseproblem_free(ptr, 1));
+const MieResultFinalization = (typeof FinalizationRegistry === 'undefined')
+    ? { register: () => {}, unregister: () => {} }
+    : new FinalizationRegistry(ptr => wasm.__wbg_mieresult_free(ptr, 1));
+const PerturbedResultsFinalization = (typeof FinalizationRegistry === 'undefined')
+    ? { register: () => {}, unregister: () => {} }
+    : new FinalizationRegistry(ptr => wasm.__wbg_perturbedresults_free(ptr, 1));
+const SimulationFinalization = (typeof FinalizationRegistry === 'undefined')
+    ? { register: () => {}, unregister: () => {} }
+    : new FinalizationRegistry(ptr => wasm.__wbg_simulation_free(ptr, 1));
+const SpheresFinalization = (typeof FinalizationRegistry === 'undefined')
+    ? { register: () => {}, unregister: () => {} }
+    : new FinalizationRegistry(ptr => wasm.__wbg_spheres_free(ptr, 1));
+const StopCriteriaFinalization = (typeof FinalizationRegistry === 'undefined')
+    ? { register: () => {}, unregister: () => {} }
+    : new FinalizationRegistry(ptr => wasm.__wbg_stopcriteria_free(ptr, 1));
+const WhiteResultsFinalization = (typeof FinalizationRegistry === 'undefined')
+    ? { register: () => {}, unregister: () => {} }
+    : new FinalizationRegistry(ptr => wasm.__wbg_whiteresults_free(ptr, 1));
+
+function _assertClass(instance, klass) {
+    if (!(instance instanceof klass)) {
+        throw new Error(`expected instance of ${klass.name}`);
+    }
+}
+
+function getArrayU8FromWasm0(ptr, len) {
+    ptr = ptr >>> 0;
+    return getUint8ArrayMemory0().subarray(ptr / 1, ptr / 1 + len);
+}
+
+let cachedFloat64ArrayMemory0 = null;
+function getFloat64ArrayMemory0() {
+    if (cachedFloat64ArrayMemory0 === null || cachedFloat64ArrayMemory0.byteLength === 0) {
+        cachedFloat64ArrayMemory0 = new Float64Array(wasm.memory.buffer);
+    }
+    return cachedFloat64ArrayMemory0;
+}
+
+function getStringFromWasm0(ptr, len) {
+    return decodeText(ptr >>> 0, len);
+}
+
+let cachedUint8ArrayMemory0 = null;
+function getUint8ArrayMemory0() {
+    if (cachedUint8ArrayMemory0 === null || cachedUint8ArrayMemory0.byteLength === 0) {
+        cachedUint8ArrayMemory0 = new Uint8Array(wasm.memory.buffer);
+    }
+    return cachedUint8ArrayMemory0;
+}
+
+function passArray8ToWasm0(arg, malloc) {
+    const ptr = malloc(arg.length * 1, 1) >>> 0;
+    getUint8ArrayMemory0().set(arg, ptr / 1);
+    WASM_VECTOR_LEN = arg.length;
+    return ptr;
+}
+
+function passArrayF64ToWasm0(arg, malloc) {
+    const ptr = malloc(arg.length * 8, 8) >>> 0;
+    getFloat64ArrayMemory0().set(arg, ptr / 8);
+    WASM_VECTOR_LEN = arg.length;
+    return ptr;
+}
+
+function passStringToWasm0(arg, malloc, realloc) {
+    if (realloc === undefined) {
+        const buf = cachedTextEncoder.encode(arg);
+        const ptr = malloc(buf.length, 1) >>> 0;
+        getUint8ArrayMemory0().subarray(ptr, ptr + buf.length).set(buf);
+        WASM_VECTOR_LEN = buf.length;
+        return ptr;
+    }
+
+    let len = arg.length;
+    let ptr = malloc(len, 1) >>> 0;
+
+    const mem = getUint8ArrayMemory0();
+
+    let offset = 0;
+
+    for (; offset < len; offset++) {
+        const code = arg.charCodeAt(offset);
+        if (code > 0x7F) break;
+        mem[ptr + offset] = code;
+    }
+    if (offset !== len) {
+        if (offset !== 0) {
+            arg = arg.slice(offset);
+        }
+        ptr = realloc(ptr, len, len = offset + arg.length * 3, 1) >>> 0;
+        const view = getUint8ArrayMemory0().subarray(ptr + offset, ptr + len);
+        const ret = cachedTextEncoder.encodeInto(arg, view);
+
+        offset += ret.written;
+        ptr = realloc(ptr, len, offset, 1) >>> 0;
+    }
+
+    WASM_VECTOR_LEN = offset;
+    return ptr;
+}
+
+function takeFromExternrefTable0(idx) {
+    const value = wasm.__wbindgen_externrefs.get(idx);
+    wasm.__externref_table_dealloc(idx);
+    return value;
+}
+
+let cachedTextDecoder = new TextDecoder('utf-8', { ignoreBOM: true, fatal: true });
+cachedTextDecoder.decode();
+const MAX_SAFARI_DECODE_BYTES = 2146435072;
+let numBytesDecoded = 0;
+function decodeText(ptr, len) {
+    numBytesDecoded += len;
+    if (numBytesDecoded >= MAX_SAFARI_DECODE_BYTES) {
+        cachedTextDecoder = new TextDecoder('utf-8', { ignoreBOM: true, fatal: true });
+        cachedTextDecoder.decode();
+        numBytesDecoded = len;
+    }
+    return cachedTextDecoder.decode(getUint8ArrayMemory0().subarray(ptr, ptr + len));
+}
+
+const cachedTextEncoder = new TextEncoder();
+
+if (!('encodeInto' in cachedTextEncoder)) {
+    cachedTextEncoder.encodeInto = function (arg, view) {
+        const buf = cachedTextEncoder.encode(arg);
+        view.set(buf);
+        return {
+            read: arg.length,
+            written: buf.length
+        };
+    };
+}
+
+let WASM_VECTOR_LEN = 0;
+
+let wasmModule, wasmInstance, wasm;
+function __wbg_finalize_init(instance, module) {
+    wasmInstance = instance;
+    wasm = instance.exports;
+    wasmModule = module;
+    cachedFloat64ArrayMemory0 = null;
+    cachedUint8ArrayMemory0 = null;
+    wasm.__wbindgen_start();
+    return wasm;
+}
 
 async function __wbg_load(module, imports) {
     if (typeof Response === 'function' && module instanceof Response) {
+        if (!module.ok) {
+            throw new Error(`failed to fetch Wasm: ${module.status} ${module.statusText} fetching '${module.url}'`);
+        }
+
         if (typeof WebAssembly.instantiateStreaming === 'function') {
             try {
                 return await WebAssembly.instantiateStreaming(module, imports);
-
             } catch (e) {
-                if (module.headers.get('Content-Type') != 'application/wasm') {
-                    console.warn("`WebAssembly.instantiateStreaming` failed because your server does not serve wasm with `application/wasm` MIME type. Falling back to `WebAssembly.instantiate` which is slower. Original error:\n", e);
+                const validResponse = expectedResponseType(module.type);
+
+                if (validResponse && module.headers.get('Content-Type') !== 'application/wasm') {
+                    console.warn("`WebAssembly.instantiateStreaming` failed because your server does not serve Wasm with `application/wasm` MIME type. Falling back to `WebAssembly.instantiate` which is slower. Original error:\n", e);
 
-                } else {
-                    throw e;
-                }
+                } else { throw e; }
             }
         }
 
         const bytes = await module.arrayBuffer();
         return await WebAssembly.instantiate(bytes, imports);
-
     } else {
         const instance = await WebAssembly.instantiate(module, imports);
 
         if (instance instanceof WebAssembly.Instance) {
             return { instance, module };
-
         } else {
             return instance;
         }
     }
-}
-
-function __wbg_get_imports() {
-    const imports = {};
-    imports.wbg = {};
-    imports.wbg.__wbindgen_memory = function() {
-        const ret = wasm.memory;
-        return addHeapObject(ret);
-    };
-    imports.wbg.__wbg_buffer_085ec1f694018c4f = function(arg0) {
-        const ret = getObject(arg0).buffer;
-        return addHeapObject(ret);
-    };
-    imports.wbg.__wbg_newwithbyteoffsetandlength_b8047c68e84e60be = function(arg0, arg1, arg2) {
-        const ret = new Float64Array(getObject(arg0), arg1 >>> 0, arg2 >>> 0);
-        return addHeapObject(ret);
-    };
-    imports.wbg.__wbindgen_object_drop_ref = function(arg0) {
-        takeObject(arg0);
-    };
-    imports.wbg.__wbg_new_c13cb67c90ba8a3b = function(arg0) {
-        const ret = new Float64Array(getObject(arg0));
-        return addHeapObject(ret);
-    };
-    imports.wbg.__wbindgen_throw = function(arg0, arg1) {
-        throw new Error(getStringFromWasm0(arg0, arg1));
-    };
-
-    return imports;
-}
-
-function __wbg_init_memory(imports, maybe_memory) {
-
-}
-
-function __wbg_finalize_init(instance, module) {
-    wasm = instance.exports;
-    __wbg_init.__wbindgen_wasm_module = module;
-    cachedUint8Memory0 = null;
-
 
-    return wasm;
+    function expectedResponseType(type) {
+        switch (type) {
+            case 'basic': case 'cors': case 'default': return true;
+        }
+        return false;
+    }
 }
 
 function initSync(module) {
     if (wasm !== undefined) return wasm;
 
-    const imports = __wbg_get_imports();
 
-    __wbg_init_memory(imports);
+    if (module !== undefined) {
+        if (Object.getPrototypeOf(module) === Object.prototype) {
+            ({module} = module)
+        } else {
+            console.warn('using deprecated parameters for `initSync()`; pass a single object instead')
+        }
+    }
 
+    const imports = __wbg_get_imports();
     if (!(module instanceof WebAssembly.Module)) {
         module = new WebAssembly.Module(module);
     }
-
     const instance = new WebAssembly.Instance(module, imports);
-
     return __wbg_finalize_init(instance, module);
 }
 
-async function __wbg_init(input) {
+async function __wbg_init(module_or_path) {
     if (wasm !== undefined) return wasm;
 
-    if (typeof input === 'undefined') {
-        input = new URL('MonteCarloRS_bg.wasm', import.meta.url);
+
+    if (module_or_path !== undefined) {
+        if (Object.getPrototypeOf(module_or_path) === Object.prototype) {
+            ({module_or_path} = module_or_path)
+        } else {
+            console.warn('using deprecated parameters for the initialization function; pass a single object instead')
+        }
+    }
+
+    if (module_or_path === undefined) {
+        module_or_path = new URL('MonteCarloRS_bg.wasm', import.meta.url);
     }
     const imports = __wbg_get_imports();
 
-    if (typeof input === 'string' || (typeof Request === 'function' && input instanceof Request) || (typeof URL === 'function' && input instanceof URL)) {
-        input = fetch(input);
+    if (typeof module_or_path === 'string' || (typeof Request === 'function' && module_or_path instanceof Request) || (typeof URL === 'function' && module_or_path instanceof URL)) {
+        module_or_path = fetch(module_or_path);
     }
 
-    __wbg_init_memory(imports);
-
-    const { instance, module } = await __wbg_load(await input, imports);
+    const { instance, module } = await __wbg_load(await module_or_path, imports);
 
     return __wbg_finalize_init(instance, module);
 }
 
-export { initSync }
-export default __wbg_init;
+export { initSync, __wbg_init as default };
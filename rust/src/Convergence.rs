@@ -0,0 +1,293 @@
+// Launching photons until the results are precise enough or a budget runs out
+
+use wasm_bindgen::prelude::*;
+
+use crate::Results;
+use crate::Simulation;
+use crate::Uncertainty;
+
+// Photons launched between checks of the stopping criteria.
+pub const DEFAULT_BATCH_SIZE: usize = 10000;
+
+/***********************************************************
+ * When to stop launching photons. The run stops as soon as
+ * every error target is met, or when one of the budgets is
+ * used up. A target or budget of 0 is not used.
+ *
+ * The relative errors are the relative standard errors of
+ * the totals, so second moments must be tracked for them.
+ ****/
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub struct StopCriteria {
+    pub rd_error: f64,          // Target relative error of the diffuse reflectance
+    pub a_error: f64,           // Target relative error of the absorbed fraction
+    pub tt_error: f64,          // Target relative error of the diffuse transmittance
+    pub max_photons: usize,     // Photon budget, counting photons launched before
+    pub max_seconds: f64,       // Wall-clock budget
+    pub batch_size: usize       // Photons between checks
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StopReason {
+    Converged,
+    PhotonBudget,
+    TimeBudget
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug)]
+pub struct ConvergenceReport {
+    pub reason: StopReason,
+    pub photons: usize,         // Photons launched in total
+    pub seconds: f64,           // Time spent launching photons
+    pub rd_error: f64,          // Achieved relative errors, NaN if not known
+    pub a_error: f64,
+    pub tt_error: f64
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CriteriaError {
+    NoCriterion,
+    InvalidTarget { name: &'static str, value: f64 },
+    NoSecondMoments
+}
+
+impl std::fmt::Display for CriteriaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CriteriaError::NoCriterion => write!(f, "at least one error target or budget is required"),
+            CriteriaError::InvalidTarget { name, value } => write!(f, "{} = {} must not be negative", name, value),
+            CriteriaError::NoSecondMoments => write!(f, "error targets need second moments, enable uncertainty tracking before initializing")
+        }
+    }
+}
+
+impl std::error::Error for CriteriaError {}
+
+#[wasm_bindgen]
+impl StopCriteria {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> StopCriteria {
+        StopCriteria {
+            rd_error: 0.0,
+            a_error: 0.0,
+            tt_error: 0.0,
+            max_photons: 0,
+            max_seconds: 0.0,
+            batch_size: DEFAULT_BATCH_SIZE
+        }
+    }
+}
+
+impl Default for StopCriteria {
+    fn default() -> StopCriteria {
+        StopCriteria::new()
+    }
+}
+
+impl StopCriteria {
+    pub fn has_error_target(&self) -> bool {
+        self.rd_error > 0.0 || self.a_error > 0.0 || self.tt_error > 0.0
+    }
+
+    pub fn validate(&self) -> Result<(), CriteriaError> {
+        for (name, value) in [("rd_error", self.rd_error), ("a_error", self.a_error), ("tt_error", self.tt_error), ("max_seconds", self.max_seconds)] {
+            if value.is_nan() || value < 0.0 {
+                return Err(CriteriaError::InvalidTarget { name, value });
+            }
+        }
+
+        if !self.has_error_target() && self.max_photons == 0 && self.max_seconds == 0.0 {
+            return Err(CriteriaError::NoCriterion);
+        }
+        Ok(())
+    }
+
+    // Photons left in the budget, unlimited if there is none.
+    pub fn photons_left(&self, launched: usize) -> usize {
+        if self.max_photons == 0 {
+            usize::MAX
+        } else {
+            self.max_photons.saturating_sub(launched)
+        }
+    }
+
+    // Why to stop after launching photons in seconds with the given errors, if at all.
+    pub fn check(&self, photons: usize, seconds: f64, errors: &TotalErrors) -> Option<StopReason> {
+        // A NaN error is never below the target.
+        let met = |target: f64, error: f64| target == 0.0 || error <= target;
+        if self.has_error_target() && met(self.rd_error, errors.rd) && met(self.a_error, errors.a) && met(self.tt_error, errors.tt) {
+            Some(StopReason::Converged)
+        } else if self.max_photons != 0 && photons >= self.max_photons {
+            Some(StopReason::PhotonBudget)
+        } else if self.max_seconds != 0.0 && seconds >= self.max_seconds {
+            Some(StopReason::TimeBudget)
+        } else {
+            None
+        }
+    }
+}
+
+// Sums and sums of squares of the totals, which can be added over simulations.
+#[derive(Clone, Copy, Default)]
+pub struct TotalSums {
+    pub n_photons: usize,
+    pub rd: (f64, f64),
+    pub a: (f64, f64),
+    pub tt: (f64, f64)
+}
+
+impl TotalSums {
+    pub fn of(results: &Results) -> TotalSums {
+        let moments = results.moments();
+        TotalSums {
+            n_photons: results.n_photons,
+            rd: (results.rd_ra.iter().sum(), moments.map_or(f64::NAN, |m| m.rd)),
+            a: (results.a_rz.iter().sum(), moments.map_or(f64::NAN, |m| m.a)),
+            tt: (results.tt_ra.iter().sum(), moments.map_or(f64::NAN, |m| m.tt))
+        }
+    }
+
+    pub fn add(&mut self, other: &TotalSums) {
+        self.n_photons += other.n_photons;
+        for (x, y) in [(&mut self.rd, other.rd), (&mut self.a, other.a), (&mut self.tt, other.tt)] {
+            x.0 += y.0;
+            x.1 += y.1;
+        }
+    }
+
+    pub fn errors(&self) -> TotalErrors {
+        let error = |(sum, sum_sq): (f64, f64)| Uncertainty::relative_error(sum, sum_sq, self.n_photons);
+        TotalErrors {
+            rd: error(self.rd),
+            a: error(self.a),
+            tt: error(self.tt)
+        }
+    }
+}
+
+// Relative standard errors of the totals.
+#[derive(Clone, Copy)]
+pub struct TotalErrors {
+    pub rd: f64,
+    pub a: f64,
+    pub tt: f64
+}
+
+impl ConvergenceReport {
+    pub fn new(reason: StopReason, photons: usize, seconds: f64, errors: &TotalErrors) -> ConvergenceReport {
+        ConvergenceReport {
+            reason,
+            photons,
+            seconds,
+            rd_error: errors.rd,
+            a_error: errors.a,
+            tt_error: errors.tt
+        }
+    }
+}
+
+impl Simulation {
+    /***********************************************************
+     * Launch batches of photons until the stopping criteria are
+     * met. Results that are already precise enough are returned
+     * without launching any photons.
+     ****/
+    pub fn try_launch_until(&mut self, criteria: &StopCriteria) -> Result<ConvergenceReport, CriteriaError> {
+        criteria.validate()?;
+        if criteria.has_error_target() && self.results.moments.is_none() {
+            return Err(CriteriaError::NoSecondMoments);
+        }
+
+        let clock = Clock::start();
+        loop {
+            let photons = self.results.n_photons;
+            let errors = TotalSums::of(&self.results).errors();
+            if let Some(reason) = criteria.check(photons, clock.seconds(), &errors) {
+                return Ok(ConvergenceReport::new(reason, photons, clock.seconds(), &errors));
+            }
+
+            self.launch_photons(criteria.batch_size.max(1).min(criteria.photons_left(photons)));
+        }
+    }
+}
+
+// Wall-clock time, std::time::Instant is not available in the browser.
+pub struct Clock {
+    #[cfg(not(target_arch = "wasm32"))]
+    start: std::time::Instant,
+    #[cfg(target_arch = "wasm32")]
+    start: f64
+}
+
+impl Clock {
+    pub fn start() -> Clock {
+        Clock {
+            #[cfg(not(target_arch = "wasm32"))]
+            start: std::time::Instant::now(),
+            #[cfg(target_arch = "wasm32")]
+            start: js_sys::Date::now()
+        }
+    }
+
+    pub fn seconds(&self) -> f64 {
+        #[cfg(not(target_arch = "wasm32"))]
+        return self.start.elapsed().as_secs_f64();
+        #[cfg(target_arch = "wasm32")]
+        return (js_sys::Date::now() - self.start) / 1000.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MonteCarloConfig::sample_run;
+    use crate::SimulationRunner::ParallelRun;
+
+    fn simulation(track_uncertainty: bool) -> Simulation {
+        let mut run_config = sample_run();
+        run_config.track_uncertainty = track_uncertainty;
+        let mut simulation = Simulation::new();
+        simulation.configure(run_config).unwrap();
+        simulation
+    }
+
+    #[test]
+    fn rejects_invalid_criteria() {
+        let mut criteria = StopCriteria::new();
+        assert_eq!(criteria.validate(), Err(CriteriaError::NoCriterion));
+        criteria.a_error = -0.1;
+        assert_eq!(criteria.validate(), Err(CriteriaError::InvalidTarget { name: "a_error", value: -0.1 }));
+
+        criteria.a_error = 0.1;
+        assert!(matches!(simulation(false).try_launch_until(&criteria), Err(CriteriaError::NoSecondMoments)));
+    }
+
+    #[test]
+    fn stops_at_photon_budget() {
+        let criteria = StopCriteria { max_photons: 1000, batch_size: 300, ..StopCriteria::new() };
+        let mut simulation = simulation(false);
+        let report = simulation.try_launch_until(&criteria).unwrap();
+        assert_eq!(report.reason, StopReason::PhotonBudget);
+        assert_eq!(report.photons, 1000);
+        assert_eq!(simulation.results().n_photons(), 1000);
+    }
+
+    #[test]
+    fn stops_when_converged() {
+        let criteria = StopCriteria { rd_error: 0.05, max_photons: 1000000, batch_size: 500, ..StopCriteria::new() };
+        let report = simulation(true).try_launch_until(&criteria).unwrap();
+        assert_eq!(report.reason, StopReason::Converged);
+        assert!(report.rd_error <= 0.05 && report.photons < 1000000);
+
+        let mut run_config = sample_run();
+        run_config.track_uncertainty = true;
+        let mut run = ParallelRun::new(&run_config, 1000000, 1, 2).unwrap();
+        let report = run.advance_until(&criteria, &|_| {}).unwrap();
+        assert_eq!(report.reason, StopReason::Converged);
+        assert!(report.rd_error <= 0.05);
+        assert_eq!(run.photons_launched(), report.photons);
+    }
+}
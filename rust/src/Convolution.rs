@@ -0,0 +1,351 @@
+// Convolution of pencil beam results over beams of finite size
+//
+// The radial tallies of a pencil beam are the response G(r) of the tissue to one
+// photon. A round beam with irradiance S(r) gives, at distance r from its centre,
+//     C(r) = ∫ G(r') [∫ S(|r - r'|) dφ] r' dr'
+// where the inner integral runs over the circle of radius r' around the point.
+// G is the mean of each bin across the bin, which keeps the weight of the steep
+// peak near the axis, and beyond the last bin that is not the overflow bin it is
+// extrapolated with the exponential decay of the bins before, so beams that reach
+// past the grid still see the tail. The layers must not change under a shift
+// along the surface, so the results hold for a normally incident beam, or an
+// oblique one away from its edge.
+
+use std::fmt;
+
+use js_sys::Float64Array;
+use wasm_bindgen::prelude::*;
+
+use crate::OutputCalc::{iz_to_layer, FinalResults};
+use crate::RunConfig::RunConfig;
+use crate::Source::Source;
+
+// Beam radii to each side of the point past which a Gaussian beam is ignored.
+const GAUSSIAN_EXTENT: f64 = 4.0;
+
+// Integration steps per beam radius, on top of the steps between bin edges.
+const STEPS_PER_RADIUS: f64 = 16.0;
+
+// Bins before the overflow bin that the exponential tail is fitted to.
+const TAIL_BINS: usize = 5;
+
+// 4-point Gauss-Legendre nodes on [-1, 1] and their weights.
+const GAUSS_LEGENDRE: [(f64, f64); 4] = [
+    (-0.8611363115940526, 0.3478548451374538),
+    (-0.3399810435848563, 0.6521451548625461),
+    (0.3399810435848563, 0.6521451548625461),
+    (0.8611363115940526, 0.3478548451374538)
+];
+
+// Irradiance profiles for Simulation.convolve.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BeamKind {
+    Gaussian,   // exp(-2 r^2 / radius^2), radius at 1/e^2
+    Flat        // Uniform over a disk
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Beam {
+    pub kind: BeamKind,
+    pub radius: f64,    // [cm]
+    pub power: f64      // Total power [W]
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConvolutionError {
+    NotPencil,
+    NoPhotons,
+    NoRadialBins,
+    InvalidRadius(f64),
+    InvalidPower(f64)
+}
+
+impl fmt::Display for ConvolutionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConvolutionError::NotPencil => write!(f, "only the results of a pencil beam can be convolved"),
+            ConvolutionError::NoPhotons => write!(f, "no photons have been launched"),
+            ConvolutionError::NoRadialBins => write!(f, "the radial grid needs a bin besides the overflow bin"),
+            ConvolutionError::InvalidRadius(radius) => write!(f, "beam radius = {} must be positive", radius),
+            ConvolutionError::InvalidPower(power) => write!(f, "beam power = {} must be positive", power)
+        }
+    }
+}
+
+impl std::error::Error for ConvolutionError {}
+
+// Responses to the beam on the radial grid of the run, at the bin centres.
+#[wasm_bindgen]
+pub struct ConvolvedResults {
+    #[wasm_bindgen(skip)]
+    pub rd_r: Vec<f64>,         // [ir], [W/cm2]
+    #[wasm_bindgen(skip)]
+    pub tt_r: Vec<f64>,         // [ir], [W/cm2]
+    #[wasm_bindgen(skip)]
+    pub a_rz: Vec<f64>,         // [ir * nz + iz], [W/cm3]
+    #[wasm_bindgen(skip)]
+    pub fluence_rz: Vec<f64>    // [ir * nz + iz], [W/cm2]
+}
+
+#[wasm_bindgen]
+impl ConvolvedResults {
+    pub fn get_rd_r(&self) -> Float64Array {
+        Float64Array::from(self.rd_r.as_slice())
+    }
+
+    pub fn get_tt_r(&self) -> Float64Array {
+        Float64Array::from(self.tt_r.as_slice())
+    }
+
+    pub fn get_a_rz(&self) -> Float64Array {
+        Float64Array::from(self.a_rz.as_slice())
+    }
+
+    pub fn get_fluence_rz(&self) -> Float64Array {
+        Float64Array::from(self.fluence_rz.as_slice())
+    }
+}
+
+impl Beam {
+    pub fn new(kind: BeamKind, radius: f64, power: f64) -> Beam {
+        Beam { kind, radius, power }
+    }
+
+    pub fn check(&self) -> Result<(), ConvolutionError> {
+        if !positive(self.radius) {
+            return Err(ConvolutionError::InvalidRadius(self.radius));
+        }
+        if !positive(self.power) {
+            return Err(ConvolutionError::InvalidPower(self.power));
+        }
+        Ok(())
+    }
+
+    // Range of r' that reaches into the beam from a point at r.
+    fn reach(&self, r: f64) -> (f64, f64) {
+        let extent = match self.kind {
+            BeamKind::Gaussian => GAUSSIAN_EXTENT * self.radius,
+            BeamKind::Flat => self.radius
+        };
+        ((r - extent).max(0.0), r + extent)
+    }
+
+    // Irradiance integrated over the circle of radius rp around a point at r [W/cm2].
+    fn circle_integral(&self, r: f64, rp: f64) -> f64 {
+        let pi = std::f64::consts::PI;
+        let big_r = self.radius;
+        match self.kind {
+            BeamKind::Gaussian => {
+                let peak = 2.0 * self.power / (pi * big_r * big_r);
+                let d = r - rp;
+                peak * 2.0 * pi * (-2.0 * d * d / (big_r * big_r)).exp() * bessel_i0_scaled(4.0 * r * rp / (big_r * big_r))
+            }
+            BeamKind::Flat => {
+                let irradiance = self.power / (pi * big_r * big_r);
+                let half_angle = if r + rp <= big_r {
+                    pi
+                } else if rp >= r + big_r || rp <= r - big_r {
+                    0.0
+                } else {
+                    ((r * r + rp * rp - big_r * big_r) / (2.0 * r * rp)).clamp(-1.0, 1.0).acos()
+                };
+                irradiance * 2.0 * half_angle
+            }
+        }
+    }
+
+    /***********************************************************
+     * Quadrature nodes r' and weights for the point at r, so
+     * that C(r) is the sum of weight * G(r'). The nodes break
+     * at the bin edges, where G jumps, and at the edge of a
+     * flat beam.
+     ****/
+    fn nodes(&self, r: f64, dr: f64, n_bins: usize) -> Vec<(f64, f64)> {
+        let (lo, hi) = self.reach(r);
+        let mut breaks = vec![lo, hi];
+        if self.kind == BeamKind::Flat && self.radius - r > lo {
+            breaks.push(self.radius - r);
+        }
+        breaks.extend((1..=n_bins).map(|i| i as f64 * dr).filter(|&edge| edge > lo && edge < hi));
+        breaks.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        let step = self.radius / STEPS_PER_RADIUS;
+        let mut nodes = Vec::new();
+        for pair in breaks.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            if b <= a {
+                continue;
+            }
+            let pieces = ((b - a) / step).ceil().max(1.0) as usize;
+            let h = (b - a) / pieces as f64;
+            for k in 0..pieces {
+                let mid = a + (k as f64 + 0.5) * h;
+                for &(x, w) in GAUSS_LEGENDRE.iter() {
+                    let rp = mid + 0.5 * h * x;
+                    nodes.push((rp, 0.5 * h * w * rp * self.circle_integral(r, rp)));
+                }
+            }
+        }
+        nodes
+    }
+}
+
+fn positive(value: f64) -> bool {
+    value > 0.0 && value.is_finite()
+}
+
+/***********************************************************
+ * Modified Bessel function I0(x) times exp(-x), for x >= 0,
+ * from the polynomial approximations of Abramowitz and
+ * Stegun 9.8.1 and 9.8.2, without overflow for large x.
+ ****/
+fn bessel_i0_scaled(x: f64) -> f64 {
+    if x < 3.75 {
+        let y = (x / 3.75) * (x / 3.75);
+        let i0 = 1.0 + y * (3.5156229 + y * (3.0899424 + y * (1.2067492 + y * (0.2659732 + y * (0.0360768 + y * 0.0045813)))));
+        i0 * (-x).exp()
+    } else {
+        let y = 3.75 / x;
+        (0.39894228 + y * (0.01328592 + y * (0.00225319 + y * (-0.00157565 + y * (0.00916281
+            + y * (-0.02057706 + y * (0.02635537 + y * (-0.01647633 + y * 0.00392377)))))))) / x.sqrt()
+    }
+}
+
+// Pencil beam response on the radial grid, without the overflow bin.
+struct Profile {
+    values: Vec<f64>,
+    dr: f64,
+    decay: Option<f64>      // Exponential decay past the last bin [1/cm]
+}
+
+impl Profile {
+    fn new(values: Vec<f64>, dr: f64) -> Profile {
+        let n = values.len();
+        let tail = &values[n.saturating_sub(TAIL_BINS)..];
+
+        // Least squares fit of ln G to the bin centres, only where every bin has weight.
+        let decay = if tail.len() >= 2 && tail.iter().all(|&v| v > 0.0) {
+            let m = tail.len() as f64;
+            let x_mean = (m - 1.0) / 2.0;
+            let y_mean = tail.iter().map(|v| v.ln()).sum::<f64>() / m;
+            let (mut sxy, mut sxx) = (0.0, 0.0);
+            for (i, v) in tail.iter().enumerate() {
+                let x = i as f64 - x_mean;
+                sxy += x * (v.ln() - y_mean);
+                sxx += x * x;
+            }
+            let k = -sxy / sxx / dr;
+            if k > 0.0 { Some(k) } else { None }
+        } else {
+            None
+        };
+
+        Profile { values, dr, decay }
+    }
+
+    fn at(&self, r: f64) -> f64 {
+        let n = self.values.len();
+        let i = (r / self.dr) as usize;
+        if i < n {
+            return self.values[i];
+        }
+
+        // The fit runs through the bin centres.
+        match self.decay {
+            Some(k) => self.values[n - 1] * (-k * (r - (n as f64 - 0.5) * self.dr)).exp(),
+            None => 0.0
+        }
+    }
+
+    fn convolve(&self, nodes: &[(f64, f64)]) -> f64 {
+        nodes.iter().map(|&(rp, w)| w * self.at(rp)).sum()
+    }
+}
+
+/***********************************************************
+ * Reflectance, transmittance, absorption and fluence for
+ * the beam, from the results of a pencil beam run finalized
+ * for n_photons launched photons. The last radial bin
+ * collects the photons beyond the grid, so it is not used
+ * as a value of G, but the response is still given there
+ * from the extrapolated tail.
+ ****/
+pub fn convolve(run_config: &RunConfig, results: &FinalResults, n_photons: usize, beam: &Beam) -> Result<ConvolvedResults, ConvolutionError> {
+    if run_config.source != Source::Pencil {
+        return Err(ConvolutionError::NotPencil);
+    }
+    if n_photons == 0 {
+        return Err(ConvolutionError::NoPhotons);
+    }
+    beam.check()?;
+
+    let nr = run_config.nr;
+    let nz = run_config.nz;
+    let dr = run_config.dr;
+    if nr < 2 {
+        return Err(ConvolutionError::NoRadialBins);
+    }
+    let n_bins = nr - 1;
+
+    let nodes: Vec<Vec<(f64, f64)>> = (0..nr).map(|ir| beam.nodes((ir as f64 + 0.5) * dr, dr, n_bins)).collect();
+    let convolve_profile = |values: Vec<f64>| {
+        let profile = Profile::new(values, dr);
+        nodes.iter().map(|nodes| profile.convolve(nodes)).collect::<Vec<f64>>()
+    };
+
+    let rd_r = convolve_profile(results.rd_r[..n_bins].to_vec());
+    let tt_r = convolve_profile(results.tt_r[..n_bins].to_vec());
+
+    let mut a_rz = vec![0.0; nr * nz];
+    let mut fluence_rz = vec![0.0; nr * nz];
+    for iz in 0..nz {
+        let column = convolve_profile((0..n_bins).map(|ir| results.a_rz[ir * nz + iz]).collect());
+        let mua = run_config.layers[iz_to_layer(iz, run_config)].mua;
+        for (ir, val) in column.into_iter().enumerate() {
+            a_rz[ir * nz + iz] = val;
+            fluence_rz[ir * nz + iz] = if mua == 0.0 { 0.0 } else { val / mua };
+        }
+    }
+
+    Ok(ConvolvedResults { rd_r, tt_r, a_rz, fluence_rz })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_results(n_photons: usize) -> (RunConfig, FinalResults) {
+        let mut run_config = crate::MonteCarloConfig::sample_run();
+        run_config.nr = 200;
+        let results = crate::SimulationRunner::run_parallel(&run_config, n_photons, 1, 2, &|_| {}).unwrap().finalize(&run_config, n_photons);
+        (run_config, results)
+    }
+
+    #[test]
+    fn rejects_results_without_photons() {
+        let (run_config, results) = sample_results(0);
+        let beam = Beam::new(BeamKind::Gaussian, 0.1, 1.0);
+        assert_eq!(convolve(&run_config, &results, 0, &beam).err(), Some(ConvolutionError::NoPhotons));
+        assert_eq!(convolve(&run_config, &results, 1, &Beam::new(BeamKind::Flat, 0.0, 1.0)).err(), Some(ConvolutionError::InvalidRadius(0.0)));
+    }
+
+    // Integral of a radial profile over the surface, by the bin centres.
+    fn power(values: &[f64], dr: f64) -> f64 {
+        values.iter().enumerate().map(|(ir, v)| v * 2.0 * std::f64::consts::PI * (ir as f64 + 0.5) * dr * dr).sum()
+    }
+
+    #[test]
+    fn beams_conserve_power() {
+        let n_photons = 20000;
+        let (run_config, results) = sample_results(n_photons);
+
+        for kind in [BeamKind::Gaussian, BeamKind::Flat] {
+            let convolved = convolve(&run_config, &results, n_photons, &Beam::new(kind, 0.2, 2.0)).unwrap();
+            let rd = power(&convolved.rd_r, run_config.dr);
+            let tt = power(&convolved.tt_r, run_config.dr);
+            assert!((rd / (2.0 * results.rd) - 1.0).abs() < 0.01, "{:?}: {} W of {} W", kind, rd, 2.0 * results.rd);
+            assert!((tt / (2.0 * results.tt) - 1.0).abs() < 0.01, "{:?}: {} W of {} W", kind, tt, 2.0 * results.tt);
+        }
+    }
+}
@@ -0,0 +1,166 @@
+// Fluorescence: light re-emitted at a second wavelength by fluorophores
+//
+// At the excitation wavelength the fluorophore of a layer accounts for mua_f of
+// its absorption mua. When an excitation photon of weight w drops
+// dw = w * mua / mut, the fluorophore emits dw * (mua_f / mua) * yield. Instead
+// of a packet for every drop, an emission packet of weight w is started with
+// probability (mua_f / mut) * yield, which emits the same weight on average from
+// far fewer packets. Emission packets leave the drop isotropically, travel with
+// the emission properties of the layers and only add to the fluorescence tallies.
+
+use crate::Go;
+use crate::PhotonPacket::PhotonPacket;
+use crate::RunConfig::RunConfig;
+use crate::Simulation;
+
+// Fluorophore at the excitation wavelength and the optical properties at the emission wavelength.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LayerFluorescence {
+    pub mua_f: f64,             // Fluorophore part of the absorption coefficient
+    pub quantum_yield: f64,     // Emitted over absorbed photons
+    pub mua_em: f64,            // Absorption coefficient at the emission wavelength
+    pub mus_em: f64,            // Scattering coefficient at the emission wavelength
+    pub g_em: f64               // Anisotropy at the emission wavelength
+}
+
+// Where an emission packet starts.
+pub(crate) struct Emission {
+    x: f64,
+    y: f64,
+    z: f64,
+    layer: usize,
+    weight: f64,
+    time: f64
+}
+
+/***********************************************************
+ * Maybe start an emission packet where the excitation photon
+ * is about to drop weight, before the drop.
+ *
+ * No random number is drawn in layers without fluorophore,
+ * so runs without fluorescence are not changed.
+ ****/
+pub fn excite(main: &mut Simulation, photon: &PhotonPacket) {
+    let Some(f) = main.run_config.layers[photon.layer].fluorescence else {
+        return;
+    };
+    if f.mua_f == 0.0 || f.quantum_yield == 0.0 {
+        return;
+    }
+
+    let probability = f.mua_f / (photon.layer_mua + photon.layer_mus) * f.quantum_yield;
+    if Go::gen_rand_float(main) < probability {
+        main.emissions.push(Emission {
+            x: photon.x,
+            y: photon.y,
+            z: photon.z,
+            layer: photon.layer,
+            weight: photon.weight,
+            time: photon.time
+        });
+    }
+}
+
+// Initialize an emission packet in a random direction.
+pub(crate) fn launch_emission(main: &mut Simulation, photon: &mut PhotonPacket, emission: &Emission) {
+    photon.reset(main.run_config.layers.len());
+    photon.x = emission.x;
+    photon.y = emission.y;
+    photon.z = emission.z;
+    photon.r = (emission.x * emission.x + emission.y * emission.y).sqrt();
+    photon.layer = emission.layer;
+    photon.weight = emission.weight;
+    photon.time = emission.time;
+    photon.emission = true;
+    // Counted as scattered, so that every exit is tallied.
+    photon.scatters = 1;
+
+    let cost = 2.0 * Go::gen_rand_float(main) - 1.0;
+    let sint = (1.0 - cost * cost).sqrt();
+    let psi = 2.0 * std::f64::consts::PI * Go::gen_rand_float(main);
+    photon.ux = sint * psi.cos();
+    photon.uy = sint * psi.sin();
+    photon.uz = cost;
+
+    Go::update_layer(main, photon);
+}
+
+// Escaping and absorbed fluorescence weight, like rd_ra and a_rz.
+#[derive(Clone)]
+pub struct FluorescenceTallies {
+    pub rd_ra: Vec<f64>,        // [ir * na + ia]
+    pub a_rz: Vec<f64>          // [ir * nz + iz]
+}
+
+impl FluorescenceTallies {
+    pub fn new(run_config: &RunConfig) -> FluorescenceTallies {
+        FluorescenceTallies {
+            rd_ra: vec![0.0; run_config.nr * run_config.na],
+            a_rz: vec![0.0; run_config.nr * run_config.nz]
+        }
+    }
+
+    pub fn accumulate(&mut self, other: &FluorescenceTallies) {
+        for (x, y) in self.rd_ra.iter_mut().zip(&other.rd_ra) {
+            *x += y;
+        }
+        for (x, y) in self.a_rz.iter_mut().zip(&other.a_rz) {
+            *x += y;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // All the absorption of the second layer of sample.mci is by the fluorophore, above a
+    // thick third layer that keeps the fluorescence from leaving through the bottom.
+    fn fluorescent_run(quantum_yield: f64) -> RunConfig {
+        let mut run_config = crate::MonteCarloConfig::sample_run();
+        run_config.layers[3].d = 10.0;
+        run_config.update_layer_boundaries();
+        for layer in 1..=3 {
+            let mua_f = if layer == 2 { 1.0 } else { 0.0 };
+            run_config.layers[layer].fluorescence = Some(LayerFluorescence { mua_f, quantum_yield, mua_em: 0.5, mus_em: 50.0, g_em: 0.8 });
+        }
+        run_config
+    }
+
+    fn simulate(run_config: RunConfig, n_photons: usize) -> crate::OutputCalc::FinalResults {
+        let mut simulation = Simulation::new();
+        simulation.set_seed(5);
+        simulation.configure(run_config).unwrap();
+        simulation.launch_photons(n_photons);
+        simulation.finalize(n_photons)
+    }
+
+    #[test]
+    fn emission_balances_fluorophore_absorption() {
+        let result = simulate(fluorescent_run(0.8), 5000);
+
+        // The fluorophore re-emits the quantum yield of what it absorbs.
+        let emitted = 0.8 * result.a_l[1];
+        assert!(result.rd_fl > 0.0 && result.a_fl > 0.0);
+        assert!((result.rd_fl + result.a_fl - emitted).abs() < 0.05 * emitted, "{} + {} vs {}", result.rd_fl, result.a_fl, emitted);
+        assert!((result.rd_r_fl.iter().zip(0..).map(|(rd, ir)| rd * 2.0 * std::f64::consts::PI * (ir as f64 + 0.5) * 0.01 * 0.01).sum::<f64>() - result.rd_fl).abs() < 1E-9);
+
+        // The excitation light is tallied as without fluorescence.
+        assert!((result.energy_balance() - 1.0).abs() < 0.01, "balance {}", result.energy_balance());
+    }
+
+    #[test]
+    fn no_yield_changes_nothing() {
+        let mut run_config = fluorescent_run(0.0);
+        let result = simulate(run_config.clone(), 500);
+        assert_eq!((result.rd_fl, result.a_fl), (0.0, 0.0));
+
+        for layer in run_config.layers.iter_mut() {
+            layer.fluorescence = None;
+        }
+        let expected = simulate(run_config, 500);
+        assert_eq!(result.rd_r, expected.rd_r);
+        assert_eq!(result.a_rz, expected.a_rz);
+        assert!(expected.rd_r_fl.is_empty());
+    }
+}
@@ -1,7 +1,14 @@
-use crate::{PhotonPacket::PhotonPacket, RunConfig::RunConfig, Simulation};
+use crate::{Fluorescence, Perturbation::Exit, PhaseFunction, PhotonPacket::PhotonPacket, Polarization, RunConfig::RunConfig, Sensitivity, Simulation, Source::Source, Twister::Twister, White::WhiteExit};
+
+// Speed of light in vacuum [cm/ps].
+pub const C_VACUUM: f64 = 0.0299792458;
 
 pub fn gen_rand_float(main: &mut Simulation) -> f64 {
-    let rand = main.rng.next_u32();
+    rand_float(&mut main.rng)
+}
+
+fn rand_float(rng: &mut Twister) -> f64 {
+    let rand = rng.next_u32();
     rand as f64 / std::u32::MAX as f64
 }
 
@@ -19,14 +26,24 @@ pub fn gen_rand_float(main: &mut Simulation) -> f64 {
  *
  * The subroutine assumes the Layerspecs array is correctly
  * initialized.
+ *
+ * Sources with directions of their own reflect the mean
+ * over their angles of incidence, and an isotropic source
+ * inside the tissue reflects nothing.
  ****/
 pub fn calculate_r_specular(run_config: &RunConfig) -> f64 {
+    let layers = &run_config.layers;
+    if let Source::Isotropic { .. } = run_config.source {
+        return 0.0;
+    }
+    if let Some(r) = run_config.source.mean_over_incidence(layers[0].n, layers[1].n, |ca| r_specular_at(run_config, ca).0) {
+        return r;
+    }
+
     let mut r1: f64;
     /* direct reflections from the 1st and 2nd layers. */
     let mut temp: f64;
 
-    let layers = &run_config.layers;
-
     temp = (layers[0].n - layers[1].n) / (layers[0].n + layers[1].n);
     r1 = temp * temp;
 
@@ -40,64 +57,112 @@ pub fn calculate_r_specular(run_config: &RunConfig) -> f64 {
     return r1;
 }
 
+/***********************************************************
+ * Specular reflectance for light incident at the angle with
+ * cosine ca, with the multiple reflections in a glass first
+ * layer, and the cosine of the angle refracted into the
+ * first layer.
+ ****/
+pub fn r_specular_at(run_config: &RunConfig, ca: f64) -> (f64, f64) {
+    let layers = &run_config.layers;
+    let (mut r1, ca1) = rfresnel(layers[0].n, layers[1].n, ca);
+
+    if layers[1].mua == 0.0 && layers[1].mus == 0.0 && r1 < 1.0 {
+        /* glass layer. */
+        let (r2, _) = rfresnel(layers[1].n, layers[2].n, ca1);
+        r1 += (1.0 - r1) * (1.0 - r1) * r2 / (1.0 - r1 * r2);
+    }
+    (r1, ca1)
+}
+
 // Cache layer properties in the photon for faster access.
 pub fn update_layer(main: &Simulation, photon: &mut PhotonPacket) {
     let layer = &main.run_config.layers[photon.layer];
+    photon.layer_n = layer.n;
     photon.layer_mua = layer.mua;
     photon.layer_mus = layer.mus;
     photon.layer_z0 = layer.z0;
     photon.layer_z1 = layer.z1;
     photon.layer_g = layer.g;
+
+    if let (true, Some(f)) = (photon.emission, layer.fluorescence) {
+        photon.layer_mua = f.mua_em;
+        photon.layer_mus = f.mus_em;
+        photon.layer_g = f.g_em;
+    }
 }
 
 /***********************************************************
-*   Initialize a photon packet.
+*   Initialize a photon packet, where the source puts it.
 ****/
-pub fn launch_photon(main: &Simulation, photon: &mut PhotonPacket) {
+pub fn launch_photon(main: &mut Simulation, photon: &mut PhotonPacket) {
+    photon.reset(main.run_config.layers.len());
     photon.weight = 1.0 - main.r_specular;
     photon.layer = 1;
 
+    let source = main.run_config.source;
+    let (x, y) = source.position(&mut || gen_rand_float(main));
+    photon.x = x;
+    photon.y = y;
+    photon.r = (x * x + y * y).sqrt();
+
     let n1 = main.run_config.layers[0].n;
     let n2 = main.run_config.layers[photon.layer].n;
-    let n_rel = n2 / n1;
-    let alphai = main.run_config.alpha;
-    
-    
-    // use snells law
-    let alphat = (alphai.to_radians().sin() / n_rel).asin();
-    photon.ux = alphat.sin();
-    photon.uz = alphat.cos();
+
+    if let Source::Isotropic { depth } = source {
+        launch_inside(main, photon, depth);
+    } else if let Some((ca, psi)) = source.incidence(x, y, n1, n2, &mut || gen_rand_float(main)) {
+        // Each photon loses the specular reflection at its own angle of incidence.
+        let (r, cat) = r_specular_at(&main.run_config, ca);
+        let sat = (1.0 - cat * cat).sqrt();
+        photon.weight = 1.0 - r;
+        photon.ux = sat * psi.cos();
+        photon.uy = sat * psi.sin();
+        photon.uz = cat;
+    } else {
+        let n_rel = n2 / n1;
+        let alphai = main.run_config.alpha;
+
+        // use snells law
+        let alphat = (alphai.to_radians().sin() / n_rel).asin();
+        photon.ux = alphat.sin();
+        photon.uz = alphat.cos();
+    }
 
     update_layer(main, photon);
+    if main.polarization.is_some() {
+        Polarization::launch(main, photon);
+    }
+}
+
+// Start a photon at depth on the z axis in a random direction, without crossing the surface.
+fn launch_inside(main: &mut Simulation, photon: &mut PhotonPacket, depth: f64) {
+    let layers = &main.run_config.layers;
+    photon.weight = 1.0;
+    photon.z = depth;
+    photon.layer = (1..layers.len() - 1).find(|&i| depth < layers[i].z1).unwrap_or(layers.len() - 2);
+    // Counted as scattered, as its direction is already diffuse, so that every exit is tallied.
+    photon.scatters = 1;
+
+    photon.uz = 2.0 * gen_rand_float(main) - 1.0;
+    let psi = 2.0 * std::f64::consts::PI * gen_rand_float(main);
+    let sint = (1.0 - photon.uz * photon.uz).sqrt();
+    photon.ux = sint * psi.cos();
+    photon.uy = sint * psi.sin();
 }
 
 /***********************************************************
- *  Choose (sample) a new theta angle for photon propagation
- *  according to the anisotropy.
- *
- *  If anisotropy g is 0, then
- *      cos(theta) = 2*rand-1.
- *  otherwise
- *  sample according to the Henyey-Greenstein function.
- *
- *  Returns the cosine of the polar deflection angle theta.
+ *  Sample the cosine of the deflection angle from the phase
+ *  function of the photon's layer, or by Henyey-Greenstein
+ *  with the layer's g if it has none. Emission photons
+ *  always use Henyey-Greenstein with the emission g.
  ****/
-pub fn spin_theta(main: &mut Simulation, g: f64) -> f64 {
-    let mut cost: f64;
-    let random = gen_rand_float(main);
-    
-    if g == 0.0 {
-        cost = 2.0 * random - 1.0;
-    } else {
-        let temp = (1.0 - g * g) / (1.0 - g + 2.0 * g * random);
-        cost = (1.0 + g * g - temp * temp) / (2.0 * g);
-        if cost < -1.0 {
-            cost = -1.0;
-        } else if cost > 1.0 {
-            cost = 1.0;
-        }
+pub fn sample_cost(main: &mut Simulation, photon: &PhotonPacket) -> f64 {
+    let Simulation { rng, run_config, .. } = main;
+    match &run_config.layers[photon.layer].phase {
+        Some(phase) if !photon.emission => phase.sample(&mut || rand_float(rng)),
+        _ => PhaseFunction::henyey_greenstein(photon.layer_g, rand_float(rng))
     }
-    return cost;
 }
 
 /***********************************************************
@@ -113,14 +178,14 @@ pub fn spin_theta(main: &mut Simulation, g: f64) -> f64 {
  *      for 0-pi  sin(psi) is +
  *      for pi-2pi sin(psi) is -
  ****/
-pub fn spin(main: &mut Simulation, g: f64, photon: &mut PhotonPacket) {
+pub fn spin(main: &mut Simulation, photon: &mut PhotonPacket) {
     let ux = photon.ux;
     let uy = photon.uy;
     let uz = photon.uz;
 
     /* cosine and sine of the */
     /* polar deflection angle theta. */
-    let cost = spin_theta(main, g);
+    let cost = sample_cost(main, photon);
     let sint = (1.0 - cost * cost).sqrt();
     /* sqrt() is faster than sin(). */
 
@@ -156,16 +221,124 @@ pub fn spin(main: &mut Simulation, g: f64, photon: &mut PhotonPacket) {
 
 /***********************************************************
  * Move the photon s away in the current layer of medium.
+ *
+ * The time of flight grows by s / (c / n) and the photon
+ * weight is recorded in the time-resolved W_txz tally.
  ****/
-pub fn hop(photon: &mut PhotonPacket) {
+pub fn hop(main: &mut Simulation, photon: &mut PhotonPacket) {
     let s = photon.step_size;
+    let x0 = photon.x;
+    let z0 = photon.z;
+    let t0 = photon.time;
 
     photon.x += s * photon.ux;
     photon.y += s * photon.uy;
     photon.z += s * photon.uz;
+    photon.time += s * photon.layer_n / C_VACUUM;
+    if main.track_paths {
+        photon.path_lengths[photon.layer] += s;
+    }
 
     // update the r hypotenuse
     photon.r = (photon.x * photon.x + photon.y * photon.y).sqrt();
+
+    // Fluorescence only adds to its own tallies.
+    if !photon.emission {
+        record_w_txz(main, photon, x0, z0, t0);
+        Sensitivity::record_voxel_path(main, photon);
+    }
+}
+
+// W_txz grid with the reciprocals of the spacings, so that steps need no divisions.
+pub(crate) struct TimeBins {
+    t0: f64,            // Start of the first bin [ps]
+    t_end: f64,         // End of the last bin [ps]
+    per_dt: f64,        // [1/ps]
+    per_dr: f64,        // [1/cm]
+    per_dz: f64,        // [1/cm]
+    nt: usize,
+    nx: usize,          // 2 * nr
+    nz: usize
+}
+
+impl TimeBins {
+    pub fn new(run_config: &RunConfig) -> TimeBins {
+        TimeBins {
+            t0: run_config.t0,
+            t_end: run_config.t0 + run_config.nt as f64 * run_config.dt,
+            per_dt: 1.0 / run_config.dt,
+            per_dr: 1.0 / run_config.dr,
+            per_dz: 1.0 / run_config.dz,
+            nt: run_config.nt,
+            nx: run_config.nr * 2,
+            nz: run_config.nz
+        }
+    }
+
+    // Index of (it, x, z) in W_txz, if the position is on the grid.
+    #[inline]
+    fn index(&self, it: usize, x: f64, z: f64) -> Option<usize> {
+        // x is rounded to the nearest bin by flooring the shifted value, which is not negative here.
+        let fx = x * self.per_dr + (self.nx / 2) as f64 + 0.5;
+        let fz = z * self.per_dz;
+        if fx >= 0.0 && fz >= 0.0 && fx < self.nx as f64 && fz < self.nz as f64 {
+            Some((it * self.nx + fx as usize) * self.nz + fz as usize)
+        } else {
+            None
+        }
+    }
+}
+
+/***********************************************************
+ * Record the photon weight in the W_txz time bins during
+ * the step from (x0, z0) at time t0 to the photon position.
+ *
+ * Each time bin overlapped by the step gets the weight
+ * times the fraction of the bin spent in the step, at the
+ * position in the middle of that part of the step, so the
+ * tally is the photon weight averaged over each time bin.
+ * Steps outside the time window return at once.
+ ****/
+pub fn record_w_txz(main: &mut Simulation, photon: &PhotonPacket, x0: f64, z0: f64, t0: f64) {
+    let Some(bins) = main.time_bins.as_ref() else {
+        return;
+    };
+    let t1 = photon.time;
+    if t1 <= t0 || t1 <= bins.t0 || t0 >= bins.t_end {
+        return;
+    }
+
+    // Times in units of bins from the start of the first bin.
+    let u0 = (t0 - bins.t0) * bins.per_dt;
+    let u1 = (t1 - bins.t0) * bins.per_dt;
+    let first = u0.max(0.0) as usize;
+    let last = (u1 as usize).min(bins.nt - 1);
+    let w_txz = &mut main.results.w_txz;
+
+    // Most steps stay within one bin.
+    if first == last {
+        let ua = u0.max(first as f64);
+        let ub = u1.min(first as f64 + 1.0);
+        let f = (0.5 * (ua + ub) - u0) / (u1 - u0);
+        if let Some(index) = bins.index(first, x0 + f * (photon.x - x0), z0 + f * (photon.z - z0)) {
+            w_txz[index] += photon.weight * (ub - ua);
+        }
+        return;
+    }
+
+    let per_step = 1.0 / (u1 - u0);
+    for it in first..=last {
+        let ua = u0.max(it as f64);
+        let ub = u1.min(it as f64 + 1.0);
+        if ub <= ua {
+            continue;
+        }
+
+        let f = (0.5 * (ua + ub) - u0) * per_step;
+        if let Some(index) = bins.index(it, x0 + f * (photon.x - x0), z0 + f * (photon.z - z0)) {
+            w_txz[index] += photon.weight * (ub - ua);
+        }
+    }
 }
 
 /***********************************************************
@@ -263,9 +436,24 @@ pub fn drop(main: &mut Simulation, photon: &mut PhotonPacket) {
     let mua = photon.layer_mua;
     let mus = photon.layer_mus;
     let dwa = (photon.weight * mua) / (mua + mus);
+
+    if photon.emission {
+        photon.weight -= dwa;
+        if let Some(fluorescence) = main.results.fluorescence.as_mut() {
+            fluorescence.a_rz[ir * main.run_config.nz + iz] += dwa;
+        }
+        return;
+    }
+
+    if main.results.fluorescence.is_some() {
+        Fluorescence::excite(main, photon);
+    }
     photon.weight -= dwa;
 
     main.results.a_rz[ir * main.run_config.nz + iz] += dwa;
+    if let Some(scores) = main.scores.as_mut() {
+        scores.add_a(ir * main.run_config.nz + iz, dwa);
+    }
 }
 
 /***********************************************************
@@ -326,6 +514,53 @@ pub fn rfresnel(n1: f64, n2: f64, ca1: f64) -> (f64, f64) {
     return (r, ca2);
 }
 
+/***********************************************************
+ * Time bin of a photon leaving the tissue at the given time
+ * of flight. Late photons are collected in the last bin,
+ * photons before t0 are not recorded.
+ ****/
+pub fn exit_time_bin(run_config: &RunConfig, time: f64) -> Option<usize> {
+    if run_config.nt == 0 || time < run_config.t0 {
+        return None;
+    }
+    Some((((time - run_config.t0) / run_config.dt) as usize).min(run_config.nt - 1))
+}
+
+/***********************************************************
+ * Record w * exp(-i * omega * t) of a reflected photon in
+ * radial bin ir for every modulation frequency, as the real
+ * and imaginary parts.
+ ****/
+pub fn record_rd_fd(main: &mut Simulation, ir: usize, weight: f64, time: f64) {
+    let nr = main.run_config.nr;
+    for (i, &frequency) in main.run_config.frequencies.iter().enumerate() {
+        // MHz times ps is 1E-6.
+        let omega_t = 2.0 * std::f64::consts::PI * frequency * 1E-6 * time;
+        let index = (i * nr + ir) * 2;
+        main.results.rd_r_fd[index] += weight * omega_t.cos();
+        main.results.rd_r_fd[index + 1] -= weight * omega_t.sin();
+    }
+}
+
+/***********************************************************
+ * Record the partial pathlengths of a reflected photon in
+ * each layer between the ambient media, weighted by the
+ * reflected weight, for radial bin ir: their sum for the
+ * mean and a histogram over the npath pathlength bins.
+ * Pathlengths beyond the last bin are collected in it.
+ ****/
+pub fn record_rd_path(main: &mut Simulation, ir: usize, weight: f64, path_lengths: &[f64]) {
+    let run_config = &main.run_config;
+    let nl = run_config.layers.len() - 2;
+    for il in 0..nl {
+        let path_length = path_lengths[il + 1];
+        main.results.rd_path[ir * nl + il] += weight * path_length;
+
+        let ip = ((path_length / run_config.dl) as usize).min(run_config.npath - 1);
+        main.results.rd_path_hist[(ir * nl + il) * run_config.npath + ip] += weight;
+    }
+}
+
 /***********************************************************
  * Record the photon weight exiting the first layer (uz < 0),
  * no matter whether the layer is glass or not, to the
@@ -354,12 +589,46 @@ pub fn record_r(main: &mut Simulation, refl: f64, photon: &mut PhotonPacket) {
     // clamp ix to 0 and nr*2
     let ix = (((photon.x / main.run_config.dr).round() as i64) + (main.run_config.nr as i64)).max(0).min((main.run_config.nr * 2 - 1) as i64) as usize;
 
+    let escaped = photon.weight * (1.0 - refl);
+    if photon.emission {
+        if let Some(fluorescence) = main.results.fluorescence.as_mut() {
+            fluorescence.rd_ra[ir * main.run_config.na + ia] += escaped;
+        }
+        photon.weight *= refl;
+        return;
+    }
+
+    if let Some(histories) = main.results.histories.as_mut() {
+        let exit = Exit { transmitted: false, scattered: photon.scatters > 0, ir, ia, weight: escaped };
+        histories.record(exit, &photon.interactions, &photon.path_lengths);
+    }
+
+    if let Some(white) = main.results.white.as_mut() {
+        if photon.scatters > 0 {
+            white.exits.push(WhiteExit { r: photon.r, path_length: photon.path_lengths[1], weight: escaped });
+        }
+    }
+
     if photon.scatters > 0 {
         // Assign photon to the reflection array element.
-        main.results.rd_ra[ir * main.run_config.na + ia] += photon.weight * (1.0 - refl);
-        main.results.rd_x[ix] += photon.weight * (1.0 - refl);
+        main.results.rd_ra[ir * main.run_config.na + ia] += escaped;
+        main.results.rd_x[ix] += escaped;
+        if let Some(it) = exit_time_bin(&main.run_config, photon.time) {
+            main.results.rd_rt[ir * main.run_config.nt + it] += escaped;
+        }
+        record_rd_fd(main, ir, escaped, photon.time);
+        if main.polarization.is_some() {
+            Polarization::record(main, photon, escaped);
+        }
+        if main.run_config.npath > 0 {
+            record_rd_path(main, ir, escaped, &photon.path_lengths);
+        }
+        Sensitivity::record_detected(main, ir, escaped);
+        if let Some(scores) = main.scores.as_mut() {
+            scores.add_rd(ir * main.run_config.na + ia, ix, escaped);
+        }
     } else {
-        main.results.rd_unscattered += photon.weight * (1.0 - refl);
+        main.results.rd_unscattered += escaped;
     }
 
     photon.weight *= refl;
@@ -390,11 +659,29 @@ pub fn record_t(main: &mut Simulation, refl: f64, photon: &mut PhotonPacket) {
         ia = iad;
     }
 
+    // Transmitted fluorescence is not tallied.
+    if photon.emission {
+        photon.weight *= refl;
+        return;
+    }
+
+    let escaped = photon.weight * (1.0 - refl);
+    if let Some(histories) = main.results.histories.as_mut() {
+        let exit = Exit { transmitted: true, scattered: photon.scatters > 0, ir, ia, weight: escaped };
+        histories.record(exit, &photon.interactions, &photon.path_lengths);
+    }
+
     if photon.scatters > 0 {
         // Assign photon to the transmittance array element.
-        main.results.tt_ra[ir * main.run_config.na + ia] += photon.weight * (1.0 - refl);
+        main.results.tt_ra[ir * main.run_config.na + ia] += escaped;
+        if let Some(it) = exit_time_bin(&main.run_config, photon.time) {
+            main.results.tt_rt[ir * main.run_config.nt + it] += escaped;
+        }
+        if let Some(scores) = main.scores.as_mut() {
+            scores.add_tt(ir * main.run_config.na + ia, escaped);
+        }
     } else {
-        main.results.tt_unscattered += photon.weight * (1.0 - refl);
+        main.results.tt_unscattered += escaped;
     }
 
     photon.weight *= refl;
@@ -510,13 +797,39 @@ pub fn cross_dn_or_not(main: &mut Simulation, photon: &mut PhotonPacket) {
 /***********************************************************
  ****/
 pub fn cross_or_not(main: &mut Simulation, photon: &mut PhotonPacket) {
-    if photon.uz < 0.0 {
+    if main.polarization.is_some() && !photon.emission {
+        cross_polarized(main, photon);
+    } else if photon.uz < 0.0 {
         cross_up_or_not(main, photon);
     } else {
         cross_dn_or_not(main, photon);
     }
 }
 
+/***********************************************************
+ * Reflect or transmit a polarized photon at the boundary it
+ * is heading for, with the Fresnel matrices.
+ ****/
+pub fn cross_polarized(main: &mut Simulation, photon: &mut PhotonPacket) {
+    let layer = photon.layer;
+    let next = if photon.uz < 0.0 { layer - 1 } else { layer + 1 };
+    let ni = main.run_config.layers[layer].n;
+    let nt = main.run_config.layers[next].n;
+
+    if Polarization::fresnel(main, photon, ni, nt) {
+        if next == 0 {
+            record_r(main, 0.0, photon);
+            photon.dead = true;
+        } else if next == main.run_config.layers.len() - 1 {
+            record_t(main, 0.0, photon);
+            photon.dead = true;
+        } else {
+            photon.layer = next;
+            update_layer(main, photon);
+        }
+    }
+}
+
 /***********************************************************
  *  Move the photon packet in glass layer.
  *  Horizontal photons are killed because they will
@@ -527,7 +840,7 @@ pub fn hop_in_glass(main: &mut Simulation, photon: &mut PhotonPacket) {
         photon.dead = true;
     } else {
         step_size_in_glass(photon);
-        hop(photon);
+        hop(main, photon);
         cross_or_not(main, photon);
     }
 }
@@ -550,12 +863,19 @@ pub fn hop_drop_spin_in_tissue(main: &mut Simulation, photon: &mut PhotonPacket)
     step_size_in_tissue(main, photon);
 
     if hit_boundary(photon) {
-        hop(photon);
+        hop(main, photon);
         cross_or_not(main, photon);
     } else {
-        hop(photon);
+        hop(main, photon);
         drop(main, photon);
-        spin(main, photon.layer_g, photon);
+        if main.polarization.is_some() && !photon.emission {
+            Polarization::spin(main, photon);
+        } else {
+            spin(main, photon);
+        }
+        if main.results.histories.is_some() {
+            photon.interactions[photon.layer] += 1;
+        }
     }
 }
 
@@ -569,4 +889,65 @@ pub fn hop_drop_spin(main: &mut Simulation, photon: &mut PhotonPacket) {
     if photon.weight < main.run_config.wth && !photon.dead {
         roulette(main, photon);
     }
+
+    // Without absorption some photons would travel for very long.
+    if main.run_config.white && photon.path_lengths[1] * main.run_config.layers[1].mus > main.run_config.white_max_path {
+        photon.dead = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Weight summed over x and z per time bin, for a photon crossing a clear 1 mm slab.
+    fn w_t_of_clear_slab(t0: f64) -> Vec<f64> {
+        let mut run_config = crate::MonteCarloConfig::sample_run();
+        run_config.layers.truncate(3);
+        run_config.layers[1] = crate::RunConfig::Layer::new(1.0, 0.0, 0.0, 0.0, 0.1);
+        run_config.layers[2].n = 1.0;
+        run_config.nt = 10;
+        run_config.t0 = t0;
+
+        let mut simulation = Simulation::new();
+        simulation.configure(run_config).unwrap();
+        simulation.launch_photons(1);
+
+        let bin = 2 * simulation.run_config.nr * simulation.run_config.nz;
+        simulation.results.w_txz.chunks(bin).map(|w| w.iter().sum()).collect()
+    }
+
+    #[test]
+    fn w_txz_follows_time_of_flight() {
+        // The photon leaves the slab after 0.1 cm / c.
+        let t_exit = 0.1 / C_VACUUM;
+        let expected = [1.0, 1.0, 1.0, t_exit - 3.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+        for (w, e) in w_t_of_clear_slab(0.0).iter().zip(expected) {
+            assert!((w - e).abs() < 1E-9, "{} {}", w, e);
+        }
+
+        let w_t = w_t_of_clear_slab(2.0);
+        assert!((w_t[0] - 1.0).abs() < 1E-9 && (w_t[1] - (t_exit - 3.0)).abs() < 1E-9);
+        assert!(w_t[2..].iter().all(|&w| w == 0.0));
+    }
+
+    #[test]
+    fn isotropic_source_escapes_are_diffuse() {
+        let mut run_config = crate::MonteCarloConfig::sample_run();
+        run_config.source = Source::Isotropic { depth: 0.05 };
+
+        let n_photons = 20000;
+        let results = crate::SimulationRunner::run_parallel(&run_config, n_photons, 1, 2, &|_| {}).unwrap().finalize(&run_config, n_photons);
+
+        assert_eq!(results.rsp, 0.0);
+        assert_eq!(results.rd_unscattered, 0.0);
+        assert_eq!(results.tt_unscattered, 0.0);
+        assert!((results.energy_balance() - 1.0).abs() < 0.01, "Rd + A + Tt = {}", results.energy_balance());
+
+        // The photons that escape at once are in Rd_r as well as in Rd.
+        let dr = run_config.dr;
+        let rd_r: f64 = results.rd_r.iter().enumerate().map(|(ir, v)| v * 2.0 * std::f64::consts::PI * (ir as f64 + 0.5) * dr * dr).sum();
+        assert!((rd_r - results.rd).abs() < 1E-12);
+        assert!(results.rd > 0.1 && results.tt > 0.01, "Rd = {}, Tt = {}", results.rd, results.tt);
+    }
 }
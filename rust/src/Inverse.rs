@@ -0,0 +1,564 @@
+// Fitting layer optical properties to a measured reflectance
+//
+// The free parameters are found with Levenberg-Marquardt on the weighted
+// residuals (model - measured) / sigma, where the model is a forward simulation
+// with a fixed seed so that the misfit changes smoothly with the parameters.
+// When only mua and mus are free, the model re-weights the photon histories of
+// a baseline simulation (see Perturbation) instead of launching new photons,
+// and the baseline is simulated again whenever the parameters move too far
+// from it.
+
+use std::fmt;
+
+use js_sys::Float64Array;
+use wasm_bindgen::prelude::*;
+
+use crate::Perturbation::PerturbationError;
+use crate::Results;
+use crate::RunConfig::{ConfigError, RunConfig};
+use crate::Simulation;
+
+// Two-sided 95% quantile of the normal distribution.
+const Z_95: f64 = 1.959964;
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Property {
+    Mua,
+    Mus,
+    G
+}
+
+// A free layer property, with the medium above counted as layer 0.
+#[derive(Clone, Copy, Debug)]
+pub struct Parameter {
+    pub layer: usize,
+    pub property: Property,
+    pub initial: f64,
+    pub lower: f64,
+    pub upper: f64
+}
+
+// Measured data with their standard deviations. Bins with a sigma that is not positive are left out.
+#[derive(Clone, Default)]
+pub struct Measurement {
+    pub rd_r: Vec<f64>,             // [ir], [1/cm2]
+    pub rd_r_sigma: Vec<f64>,
+    pub rd: Option<(f64, f64)>,     // Total diffuse reflectance and its sigma
+    pub tt: Option<(f64, f64)>      // Total diffuse transmittance and its sigma
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub struct FitOptions {
+    pub photons: usize,             // Photons per forward simulation
+    pub seed: u64,
+    pub max_iterations: usize,
+    pub tolerance: f64,             // Stop when chi2 improves by less than this fraction
+    pub step: f64,                  // Relative step for the derivatives, well above the simulation noise
+    pub use_perturbation: bool,     // Re-weight histories when only mua and mus are free
+    pub rebase: f64                 // Relative change of a parameter that calls for a new baseline
+}
+
+#[wasm_bindgen]
+impl FitOptions {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> FitOptions {
+        FitOptions {
+            photons: 100000,
+            seed: 1,
+            max_iterations: 50,
+            tolerance: 1E-4,
+            step: 0.05,
+            use_perturbation: true,
+            rebase: 0.2
+        }
+    }
+}
+
+impl Default for FitOptions {
+    fn default() -> FitOptions {
+        FitOptions::new()
+    }
+}
+
+// The free parameters and the data to fit them to.
+#[wasm_bindgen]
+#[derive(Clone, Default)]
+pub struct InverseProblem {
+    #[wasm_bindgen(skip)]
+    pub parameters: Vec<Parameter>,
+    #[wasm_bindgen(skip)]
+    pub measurement: Measurement
+}
+
+#[wasm_bindgen]
+impl InverseProblem {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> InverseProblem {
+        InverseProblem::default()
+    }
+
+    pub fn add_parameter(&mut self, layer: usize, property: Property, initial: f64, lower: f64, upper: f64) {
+        self.parameters.push(Parameter { layer, property, initial, lower, upper });
+    }
+
+    pub fn clear_parameters(&mut self) {
+        self.parameters.clear();
+    }
+
+    pub fn set_rd_r(&mut self, rd_r: &[f64], sigma: &[f64]) {
+        self.measurement.rd_r = rd_r.to_vec();
+        self.measurement.rd_r_sigma = sigma.to_vec();
+    }
+
+    pub fn set_rd(&mut self, rd: f64, sigma: f64) {
+        self.measurement.rd = Some((rd, sigma));
+    }
+
+    pub fn set_tt(&mut self, tt: f64, sigma: f64) {
+        self.measurement.tt = Some((tt, sigma));
+    }
+}
+
+#[wasm_bindgen]
+pub struct FitResult {
+    #[wasm_bindgen(skip)]
+    pub values: Vec<f64>,           // Best fit of each parameter
+    #[wasm_bindgen(skip)]
+    pub std_errors: Vec<f64>,       // Standard errors from the covariance matrix
+    #[wasm_bindgen(skip)]
+    pub ci_lower: Vec<f64>,         // 95% confidence intervals
+    #[wasm_bindgen(skip)]
+    pub ci_upper: Vec<f64>,
+    pub chi2: f64,
+    pub reduced_chi2: f64,          // chi2 per degree of freedom
+    pub iterations: usize,
+    pub simulations: usize,         // Forward simulations that launched photons
+    pub converged: bool
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FitError {
+    NoParameters,
+    InvalidParameter(usize),
+    InvalidMeasurement(&'static str),
+    NoData,
+    Config(Vec<ConfigError>),
+    Perturbation(PerturbationError),
+    Singular
+}
+
+impl fmt::Display for FitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FitError::NoParameters => write!(f, "at least one free parameter is required"),
+            FitError::InvalidParameter(i) => write!(f, "parameter {}: the layer does not exist, the bounds are empty, the initial value is not within them or g is free in a layer without a Henyey-Greenstein phase function", i),
+            FitError::InvalidMeasurement(reason) => write!(f, "invalid measurement: {}", reason),
+            FitError::NoData => write!(f, "the measurement has no data with a positive sigma"),
+            FitError::Config(errors) => {
+                let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "invalid run configuration: {}", messages.join("; "))
+            }
+            FitError::Perturbation(e) => write!(f, "{}", e),
+            FitError::Singular => write!(f, "the parameters cannot be determined from the data")
+        }
+    }
+}
+
+impl std::error::Error for FitError {}
+
+#[wasm_bindgen]
+impl FitResult {
+    pub fn get_values(&self) -> Float64Array {
+        Float64Array::from(self.values.as_slice())
+    }
+
+    pub fn get_std_errors(&self) -> Float64Array {
+        Float64Array::from(self.std_errors.as_slice())
+    }
+
+    pub fn get_ci_lower(&self) -> Float64Array {
+        Float64Array::from(self.ci_lower.as_slice())
+    }
+
+    pub fn get_ci_upper(&self) -> Float64Array {
+        Float64Array::from(self.ci_upper.as_slice())
+    }
+}
+
+// What the model predicts for the measured quantities.
+struct Prediction {
+    rd_r: Vec<f64>,
+    rd: f64,
+    tt: f64
+}
+
+struct Model<'a> {
+    run_config: &'a RunConfig,
+    parameters: &'a [Parameter],
+    measurement: &'a Measurement,
+    options: &'a FitOptions,
+    perturbation: bool,
+    baseline: Option<(Vec<f64>, Results)>,  // Parameters and results with histories
+    simulations: usize
+}
+
+impl<'a> Model<'a> {
+    fn config_for(&self, values: &[f64]) -> RunConfig {
+        let mut run_config = self.run_config.clone();
+        for (parameter, &value) in self.parameters.iter().zip(values) {
+            let layer = &mut run_config.layers[parameter.layer];
+            match parameter.property {
+                Property::Mua => layer.mua = value,
+                Property::Mus => layer.mus = value,
+                Property::G => layer.g = value
+            }
+        }
+        run_config
+    }
+
+    fn simulate(&mut self, run_config: RunConfig) -> Result<Results, FitError> {
+        let mut simulation = Simulation::new();
+        simulation.set_seed(self.options.seed);
+        simulation.configure(run_config).map_err(FitError::Config)?;
+        simulation.launch_photons(self.options.photons);
+        self.simulations += 1;
+        Ok(simulation.into_results())
+    }
+
+    // Simulate a new baseline if there is none or values are too far from it.
+    fn update_baseline(&mut self, values: &[f64]) -> Result<(), FitError> {
+        if !self.perturbation {
+            return Ok(());
+        }
+
+        let rebase = self.options.rebase;
+        // With the same floor as the derivative steps, so that a baseline at 0 still covers its neighbourhood.
+        let parameters = self.parameters;
+        let near = |baseline: &[f64]| baseline.iter().zip(values).zip(parameters).all(|((&b, &v), p)| {
+            (v - b).abs() <= rebase * b.abs().max(1E-3 * (p.upper - p.lower))
+        });
+        if self.baseline.as_ref().is_some_and(|(baseline, _)| near(baseline)) {
+            return Ok(());
+        }
+
+        let mut run_config = self.config_for(values);
+        run_config.record_histories = true;
+        let results = self.simulate(run_config)?;
+        self.baseline = Some((values.to_vec(), results));
+        Ok(())
+    }
+
+    fn predict(&mut self, values: &[f64]) -> Result<Prediction, FitError> {
+        if let Some((_, baseline)) = &self.baseline {
+            let layers = &self.config_for(values).layers;
+            let tissue = &layers[1..layers.len() - 1];
+            let mua: Vec<f64> = tissue.iter().map(|l| l.mua).collect();
+            let mus: Vec<f64> = tissue.iter().map(|l| l.mus).collect();
+            let perturbed = baseline.perturb(&mua, &mus).map_err(FitError::Perturbation)?;
+            return Ok(Prediction { rd_r: perturbed.rd_r, rd: perturbed.rd, tt: perturbed.tt });
+        }
+
+        let run_config = self.config_for(values);
+        let results = self.simulate(run_config.clone())?;
+        let result = results.finalize(&run_config, self.options.photons);
+        Ok(Prediction { rd_r: result.rd_r, rd: result.rd, tt: result.tt })
+    }
+
+    fn residuals(&mut self, values: &[f64]) -> Result<Vec<f64>, FitError> {
+        let prediction = self.predict(values)?;
+        let m = self.measurement;
+
+        let mut residuals = Vec::new();
+        for ((&model, &measured), &sigma) in prediction.rd_r.iter().zip(&m.rd_r).zip(&m.rd_r_sigma) {
+            if sigma > 0.0 {
+                residuals.push((model - measured) / sigma);
+            }
+        }
+        for (model, data) in [(prediction.rd, m.rd), (prediction.tt, m.tt)] {
+            if let Some((measured, sigma)) = data {
+                if sigma > 0.0 {
+                    residuals.push((model - measured) / sigma);
+                }
+            }
+        }
+        Ok(residuals)
+    }
+}
+
+fn chi2(residuals: &[f64]) -> f64 {
+    residuals.iter().map(|r| r * r).sum()
+}
+
+/***********************************************************
+ * Solve a * x = b for a small dense n x n matrix, stored by
+ * rows, with Gaussian elimination and partial pivoting.
+ * Returns None if a is singular.
+ ****/
+fn solve(a: &[f64], b: &[f64], n: usize) -> Option<Vec<f64>> {
+    let mut m = a.to_vec();
+    let mut x = b.to_vec();
+
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&i, &j| m[i * n + col].abs().total_cmp(&m[j * n + col].abs()))?;
+        if m[pivot * n + col].abs() < 1E-300 {
+            return None;
+        }
+        if pivot != col {
+            for k in 0..n {
+                m.swap(col * n + k, pivot * n + k);
+            }
+            x.swap(col, pivot);
+        }
+
+        for row in col + 1..n {
+            let factor = m[row * n + col] / m[col * n + col];
+            for k in col..n {
+                m[row * n + k] -= factor * m[col * n + k];
+            }
+            x[row] -= factor * x[col];
+        }
+    }
+
+    for col in (0..n).rev() {
+        let mut sum = x[col];
+        for k in col + 1..n {
+            sum -= m[col * n + k] * x[k];
+        }
+        x[col] = sum / m[col * n + col];
+    }
+
+    x.iter().all(|v| v.is_finite()).then_some(x)
+}
+
+fn check_inputs(run_config: &RunConfig, parameters: &[Parameter], measurement: &Measurement) -> Result<(), FitError> {
+    if parameters.is_empty() {
+        return Err(FitError::NoParameters);
+    }
+    for (i, p) in parameters.iter().enumerate() {
+        let tissue = p.layer >= 1 && p.layer + 1 < run_config.layers.len();
+        // The g of other phase functions follows from their own parameters.
+        let henyey_greenstein = p.property != Property::G || (tissue && run_config.layers[p.layer].phase.is_none());
+        if !tissue || !henyey_greenstein || p.lower >= p.upper || !(p.lower..=p.upper).contains(&p.initial) {
+            return Err(FitError::InvalidParameter(i));
+        }
+    }
+
+    if measurement.rd_r.len() != run_config.nr || measurement.rd_r_sigma.len() != run_config.nr {
+        return Err(FitError::InvalidMeasurement("rd_r and rd_r_sigma must have nr values"));
+    }
+    Ok(())
+}
+
+/***********************************************************
+ * Fit the free parameters of the layers of run_config to
+ * the measurement, starting from their initial values and
+ * keeping them within their bounds.
+ *
+ * The confidence intervals come from the covariance matrix
+ * at the best fit, scaled by the reduced chi2, and do not
+ * include the noise of the forward simulations.
+ ****/
+pub fn fit(run_config: &RunConfig, parameters: &[Parameter], measurement: &Measurement, options: &FitOptions) -> Result<FitResult, FitError> {
+    check_inputs(run_config, parameters, measurement)?;
+
+    let mut model = Model {
+        run_config,
+        parameters,
+        measurement,
+        options,
+        perturbation: options.use_perturbation && parameters.iter().all(|p| p.property != Property::G),
+        baseline: None,
+        simulations: 0
+    };
+
+    let n = parameters.len();
+    let clamp = |values: &mut Vec<f64>| {
+        for (value, p) in values.iter_mut().zip(parameters) {
+            *value = value.clamp(p.lower, p.upper);
+        }
+    };
+
+    let mut values: Vec<f64> = parameters.iter().map(|p| p.initial).collect();
+    model.update_baseline(&values)?;
+    let mut residuals = model.residuals(&values)?;
+    let m = residuals.len();
+    if m == 0 {
+        return Err(FitError::NoData);
+    }
+
+    let mut lambda = 1E-3;
+    let mut iterations = 0;
+    let mut converged = false;
+    let mut jacobian = vec![0.0; m * n];
+
+    loop {
+        // Finite differences, stepping away from the nearer bound.
+        for (j, p) in parameters.iter().enumerate() {
+            let mut h = options.step * values[j].abs().max(1E-3 * (p.upper - p.lower));
+            if values[j] + h > p.upper {
+                // Step into the side with more room, without passing its bound.
+                let above = p.upper - values[j];
+                let below = values[j] - p.lower;
+                h = if above >= below { above } else { -h.min(below) };
+            }
+            let mut shifted = values.clone();
+            shifted[j] += h;
+            let shifted_residuals = model.residuals(&shifted)?;
+            for i in 0..m {
+                jacobian[i * n + j] = (shifted_residuals[i] - residuals[i]) / h;
+            }
+        }
+
+        let mut jtj = vec![0.0; n * n];
+        let mut jtr = vec![0.0; n];
+        for i in 0..m {
+            for a in 0..n {
+                jtr[a] += jacobian[i * n + a] * residuals[i];
+                for b in 0..n {
+                    jtj[a * n + b] += jacobian[i * n + a] * jacobian[i * n + b];
+                }
+            }
+        }
+
+        if iterations >= options.max_iterations || converged {
+            let covariance: Option<Vec<Vec<f64>>> = (0..n).map(|j| {
+                let mut unit = vec![0.0; n];
+                unit[j] = 1.0;
+                solve(&jtj, &unit, n)
+            }).collect();
+            let covariance = covariance.ok_or(FitError::Singular)?;
+
+            let chi2 = chi2(&residuals);
+            let reduced_chi2 = if m > n { chi2 / (m - n) as f64 } else { f64::NAN };
+            let scale = if m > n { reduced_chi2 } else { 1.0 };
+            let std_errors: Vec<f64> = (0..n).map(|j| (covariance[j][j] * scale).max(0.0).sqrt()).collect();
+
+            return Ok(FitResult {
+                ci_lower: values.iter().zip(&std_errors).map(|(v, s)| v - Z_95 * s).collect(),
+                ci_upper: values.iter().zip(&std_errors).map(|(v, s)| v + Z_95 * s).collect(),
+                values,
+                std_errors,
+                chi2,
+                reduced_chi2,
+                iterations,
+                simulations: model.simulations,
+                converged
+            });
+        }
+        iterations += 1;
+
+        // Raise the damping until a step lowers chi2.
+        let current = chi2(&residuals);
+        let mut accepted = false;
+        while lambda < 1E10 {
+            let mut damped = jtj.clone();
+            for j in 0..n {
+                damped[j * n + j] += lambda * jtj[j * n + j].max(1E-12);
+            }
+            let minus_jtr: Vec<f64> = jtr.iter().map(|v| -v).collect();
+            let Some(delta) = solve(&damped, &minus_jtr, n) else {
+                lambda *= 10.0;
+                continue;
+            };
+
+            let mut trial: Vec<f64> = values.iter().zip(&delta).map(|(v, d)| v + d).collect();
+            clamp(&mut trial);
+            let trial_residuals = model.residuals(&trial)?;
+            let trial_chi2 = chi2(&trial_residuals);
+
+            if trial_chi2 < current {
+                converged = (current - trial_chi2) <= options.tolerance * current;
+                values = trial;
+                residuals = trial_residuals;
+                lambda = (lambda / 10.0).max(1E-12);
+                accepted = true;
+                break;
+            }
+            lambda *= 10.0;
+        }
+        if !accepted {
+            converged = true;
+        }
+
+        // A new baseline changes the model slightly, so the residuals are evaluated again
+        // and the fit goes on from there.
+        if model.baseline.is_some() {
+            let had = model.simulations;
+            model.update_baseline(&values)?;
+            if model.simulations != had {
+                residuals = model.residuals(&values)?;
+                converged = false;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MonteCarloConfig::sample_run;
+
+    fn parameter(layer: usize, property: Property, initial: f64, lower: f64, upper: f64) -> Parameter {
+        Parameter { layer, property, initial, lower, upper }
+    }
+
+    // Rd_r of the run as if measured with 5% errors, out to 2 mm where the simulation noise is lower.
+    fn measure(run_config: &RunConfig, photons: usize) -> Measurement {
+        let mut simulation = Simulation::new();
+        simulation.set_seed(7);
+        simulation.configure(run_config.clone()).unwrap();
+        simulation.launch_photons(photons);
+        let result = simulation.finalize(photons);
+        Measurement {
+            rd_r_sigma: result.rd_r.iter().enumerate().map(|(ir, &rd)| if ir < 20 { 0.05 * rd } else { 0.0 }).collect(),
+            rd_r: result.rd_r,
+            rd: Some((result.rd, 0.01 * result.rd)),
+            tt: None
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_inputs() {
+        let run_config = sample_run();
+        let measurement = measure(&run_config, 10);
+        let check = |parameters: &[Parameter], measurement: &Measurement| check_inputs(&run_config, parameters, measurement);
+
+        assert_eq!(check(&[], &measurement), Err(FitError::NoParameters));
+        assert_eq!(check(&[parameter(1, Property::Mua, 1.0, 0.0, 2.0), parameter(0, Property::Mua, 1.0, 0.0, 2.0)], &measurement), Err(FitError::InvalidParameter(1)));
+        assert_eq!(check(&[parameter(4, Property::Mus, 1.0, 0.0, 2.0)], &measurement), Err(FitError::InvalidParameter(0)));
+        assert_eq!(check(&[parameter(1, Property::Mua, 3.0, 0.0, 2.0)], &measurement), Err(FitError::InvalidParameter(0)));
+        assert_eq!(check(&[parameter(1, Property::Mua, 1.0, 1.0, 1.0)], &measurement), Err(FitError::InvalidParameter(0)));
+        assert_eq!(check(&[parameter(1, Property::Mua, 1.0, 2.0, 0.5)], &measurement), Err(FitError::InvalidParameter(0)));
+        assert_eq!(check(&[parameter(1, Property::G, 0.9, 0.0, 0.95)], &measurement), Ok(()));
+
+        let mut rayleigh = sample_run();
+        rayleigh.layers[1].phase = Some(crate::PhaseFunction::PhaseFunction::Rayleigh);
+        assert_eq!(check_inputs(&rayleigh, &[parameter(1, Property::G, 0.0, -0.5, 0.5)], &measurement), Err(FitError::InvalidParameter(0)));
+
+        let short = Measurement { rd_r: vec![0.0; 3], rd_r_sigma: vec![1.0; 3], ..Measurement::default() };
+        assert!(matches!(check(&[parameter(1, Property::Mua, 1.0, 0.0, 2.0)], &short), Err(FitError::InvalidMeasurement(_))));
+
+        let empty = Measurement { rd_r: vec![0.0; run_config.nr], rd_r_sigma: vec![0.0; run_config.nr], ..Measurement::default() };
+        let options = FitOptions { photons: 10, ..FitOptions::new() };
+        assert!(matches!(fit(&run_config, &[parameter(1, Property::Mua, 1.0, 0.0, 2.0)], &empty, &options), Err(FitError::NoData)));
+    }
+
+    #[test]
+    fn fits_simulated_measurement() {
+        let run_config = sample_run();
+        let measurement = measure(&run_config, 20000);
+        let parameters = [parameter(1, Property::Mua, 2.0, 0.1, 10.0), parameter(1, Property::Mus, 70.0, 10.0, 300.0)];
+        let options = FitOptions { photons: 20000, ..FitOptions::new() };
+
+        let result = fit(&run_config, &parameters, &measurement, &options).unwrap();
+        assert!(result.converged);
+        assert!(result.simulations < result.iterations, "the histories are re-weighted");
+        assert!((result.values[0] - 1.0).abs() < 0.15, "mua = {}", result.values[0]);
+        assert!((result.values[1] - 100.0).abs() < 10.0, "mus = {}", result.values[1]);
+        for j in 0..2 {
+            assert!(result.ci_lower[j] < result.values[j] && result.values[j] < result.ci_upper[j]);
+        }
+    }
+}
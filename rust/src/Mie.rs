@@ -0,0 +1,452 @@
+// Mie theory for homogeneous spheres, after BHMIE by Bohren and Huffman
+//
+// The size parameter is x = 2 pi n_medium a / wavelength and m is the refractive
+// index of the sphere relative to the medium. The scattering amplitudes S1 and S2
+// are those of Bohren and Huffman, so that 1/2 (|S1|^2 + |S2|^2) is S11.
+//
+// Spheres of a size distribution are averaged over their number: the mean cross
+// sections give mus and mua for a volume fraction of spheres, and the mean S11
+// the phase function, whose integral over the sphere is k^2 times the mean
+// scattering cross section.
+
+use std::fmt;
+use std::ops::{Add, Div, Mul, Sub};
+
+use js_sys::Float64Array;
+use wasm_bindgen::prelude::*;
+
+// Number of scattering angles the phase functions and the polarized scattering matrices are tabulated at, from 0 to pi.
+pub const N_ANGLES: usize = 1801;
+
+// Number of radii a size distribution is averaged over.
+const N_SIZES: usize = 100;
+
+// Standard deviations of a size distribution that are averaged over on either side.
+const SIZE_RANGE: f64 = 4.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64
+}
+
+impl Complex {
+    pub fn new(re: f64, im: f64) -> Complex {
+        Complex { re, im }
+    }
+
+    pub fn conj(self) -> Complex {
+        Complex::new(self.re, -self.im)
+    }
+
+    pub fn norm_sqr(self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+
+    pub fn scale(self, k: f64) -> Complex {
+        Complex::new(self.re * k, self.im * k)
+    }
+
+    pub fn sqrt(self) -> Complex {
+        let r = self.norm_sqr().sqrt();
+        let re = ((r + self.re) / 2.0).max(0.0).sqrt();
+        let im = ((r - self.re) / 2.0).max(0.0).sqrt();
+        Complex::new(re, if self.im < 0.0 { -im } else { im })
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+    fn add(self, o: Complex) -> Complex {
+        Complex::new(self.re + o.re, self.im + o.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+    fn sub(self, o: Complex) -> Complex {
+        Complex::new(self.re - o.re, self.im - o.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+    fn mul(self, o: Complex) -> Complex {
+        Complex::new(self.re * o.re - self.im * o.im, self.re * o.im + self.im * o.re)
+    }
+}
+
+impl Div for Complex {
+    type Output = Complex;
+    fn div(self, o: Complex) -> Complex {
+        let d = o.norm_sqr();
+        Complex::new((self.re * o.re + self.im * o.im) / d, (self.im * o.re - self.re * o.im) / d)
+    }
+}
+
+// The scattering coefficients a_n and b_n of a sphere, for n = 1..=nstop.
+pub struct MieCoefficients {
+    pub x: f64,
+    pub a: Vec<Complex>,
+    pub b: Vec<Complex>
+}
+
+/***********************************************************
+ * Compute a_n and b_n for size parameter x and relative
+ * refractive index m, with the logarithmic derivative
+ * D_n(mx) from downward recurrence.
+ ****/
+pub fn coefficients(x: f64, m: Complex) -> MieCoefficients {
+    let nstop = (x + 4.0 * x.cbrt() + 2.0) as usize;
+    let y = m.scale(x);
+    let nmx = (nstop as f64).max(y.norm_sqr().sqrt()) as usize + 15;
+
+    let mut d = vec![Complex::new(0.0, 0.0); nmx + 1];
+    for n in (1..=nmx).rev() {
+        let n_y = Complex::new(n as f64, 0.0) / y;
+        d[n - 1] = n_y - Complex::new(1.0, 0.0) / (d[n] + n_y);
+    }
+
+    let mut psi0 = x.cos();
+    let mut psi1 = x.sin();
+    let mut chi0 = -x.sin();
+    let mut chi1 = x.cos();
+    let mut xi1 = Complex::new(psi1, -chi1);
+
+    let mut a = Vec::with_capacity(nstop);
+    let mut b = Vec::with_capacity(nstop);
+    for (n, &dn) in d.iter().enumerate().take(nstop + 1).skip(1) {
+        let nf = n as f64;
+        let psi = (2.0 * nf - 1.0) * psi1 / x - psi0;
+        let chi = (2.0 * nf - 1.0) * chi1 / x - chi0;
+        let xi = Complex::new(psi, -chi);
+
+        let n_x = Complex::new(nf / x, 0.0);
+        let da = dn / m + n_x;
+        let db = m * dn + n_x;
+        let psi_c = Complex::new(psi, 0.0);
+        let psi1_c = Complex::new(psi1, 0.0);
+        a.push((da * psi_c - psi1_c) / (da * xi - xi1));
+        b.push((db * psi_c - psi1_c) / (db * xi - xi1));
+
+        psi0 = psi1;
+        psi1 = psi;
+        chi0 = chi1;
+        chi1 = chi;
+        xi1 = Complex::new(psi1, -chi1);
+    }
+
+    MieCoefficients { x, a, b }
+}
+
+impl MieCoefficients {
+    // Extinction and scattering efficiencies and the anisotropy g.
+    pub fn efficiencies(&self) -> (f64, f64, f64) {
+        let mut qext = 0.0;
+        let mut qsca = 0.0;
+        let mut gqsc = 0.0;
+        let count = self.a.len();
+        for i in 0..count {
+            let n = (i + 1) as f64;
+            let (an, bn) = (self.a[i], self.b[i]);
+            qext += (2.0 * n + 1.0) * (an.re + bn.re);
+            qsca += (2.0 * n + 1.0) * (an.norm_sqr() + bn.norm_sqr());
+            gqsc += (2.0 * n + 1.0) / (n * (n + 1.0)) * (an * bn.conj()).re;
+            if i + 1 < count {
+                let (an1, bn1) = (self.a[i + 1], self.b[i + 1]);
+                gqsc += n * (n + 2.0) / (n + 1.0) * (an * an1.conj() + bn * bn1.conj()).re;
+            }
+        }
+
+        let x2 = self.x * self.x;
+        let qsca = 2.0 / x2 * qsca;
+        let g = if qsca > 0.0 { 4.0 / x2 * gqsc / qsca } else { 0.0 };
+        (2.0 / x2 * qext, qsca, g)
+    }
+
+    // Scattering amplitudes S1 and S2 at the cosine mu of the scattering angle.
+    pub fn amplitudes(&self, mu: f64) -> (Complex, Complex) {
+        let mut s1 = Complex::new(0.0, 0.0);
+        let mut s2 = Complex::new(0.0, 0.0);
+        let mut pi0 = 0.0;
+        let mut pi1 = 1.0;
+        for i in 0..self.a.len() {
+            let n = (i + 1) as f64;
+            let tau = n * mu * pi1 - (n + 1.0) * pi0;
+            let k = (2.0 * n + 1.0) / (n * (n + 1.0));
+            s1 = s1 + (self.a[i].scale(pi1) + self.b[i].scale(tau)).scale(k);
+            s2 = s2 + (self.a[i].scale(tau) + self.b[i].scale(pi1)).scale(k);
+
+            let pi = ((2.0 * n + 1.0) * mu * pi1 - (n + 1.0) * pi0) / n;
+            pi0 = pi1;
+            pi1 = pi;
+        }
+        (s1, s2)
+    }
+}
+
+// Radii of the spheres [um].
+#[derive(Clone, Debug, PartialEq)]
+pub enum Distribution {
+    Monodisperse { radius: f64 },
+    LogNormal { median: f64, sigma: f64 },          // sigma is the standard deviation of ln(radius)
+    Normal { mean: f64, sd: f64 },                  // Cut off at radius 0
+    Tabulated { radii: Vec<f64>, fractions: Vec<f64> }  // Number fractions, normalized when averaged
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MieError {
+    InvalidWavelength(f64),
+    InvalidRefractiveIndex,
+    InvalidVolumeFraction(f64),
+    InvalidDistribution(&'static str)
+}
+
+impl fmt::Display for MieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MieError::InvalidWavelength(wavelength) => write!(f, "wavelength = {} um must be positive", wavelength),
+            MieError::InvalidRefractiveIndex => write!(f, "the refractive indices must be positive, with an imaginary part that is not negative"),
+            MieError::InvalidVolumeFraction(fraction) => write!(f, "volume fraction = {} must be in [0, 1)", fraction),
+            MieError::InvalidDistribution(reason) => write!(f, "size distribution: {}", reason)
+        }
+    }
+}
+
+impl std::error::Error for MieError {}
+
+impl Distribution {
+    /***********************************************************
+     * Radii and number fractions to average over, from the
+     * midpoints of N_SIZES intervals over SIZE_RANGE standard
+     * deviations either side for the continuous distributions.
+     ****/
+    pub fn sizes(&self) -> Result<Vec<(f64, f64)>, MieError> {
+        let positive = |v: f64| v > 0.0 && v.is_finite();
+        let midpoints = |lower: f64, upper: f64| {
+            let step = (upper - lower) / N_SIZES as f64;
+            (0..N_SIZES).map(move |i| lower + (i as f64 + 0.5) * step)
+        };
+
+        let sizes: Vec<(f64, f64)> = match self {
+            Distribution::Monodisperse { radius } => {
+                if !positive(*radius) {
+                    return Err(MieError::InvalidDistribution("the radius must be positive"));
+                }
+                vec![(*radius, 1.0)]
+            }
+            Distribution::LogNormal { median, sigma } => {
+                if !positive(*median) || !positive(*sigma) {
+                    return Err(MieError::InvalidDistribution("the median radius and sigma must be positive"));
+                }
+                let ln_median = median.ln();
+                midpoints(ln_median - SIZE_RANGE * sigma, ln_median + SIZE_RANGE * sigma)
+                    .map(|ln_r| (ln_r.exp(), (-0.5 * ((ln_r - ln_median) / sigma).powi(2)).exp()))
+                    .collect()
+            }
+            Distribution::Normal { mean, sd } => {
+                if !positive(*mean) || !positive(*sd) {
+                    return Err(MieError::InvalidDistribution("the mean radius and standard deviation must be positive"));
+                }
+                midpoints((mean - SIZE_RANGE * sd).max(0.0), mean + SIZE_RANGE * sd)
+                    .map(|r| (r, (-0.5 * ((r - mean) / sd).powi(2)).exp()))
+                    .collect()
+            }
+            Distribution::Tabulated { radii, fractions } => {
+                if radii.len() != fractions.len() || radii.is_empty() {
+                    return Err(MieError::InvalidDistribution("one number fraction is needed for each of at least one radius"));
+                }
+                if radii.iter().any(|&r| !positive(r)) || fractions.iter().any(|&f| !(f >= 0.0 && f.is_finite())) {
+                    return Err(MieError::InvalidDistribution("radii must be positive and fractions must not be negative"));
+                }
+                radii.iter().copied().zip(fractions.iter().copied()).collect()
+            }
+        };
+
+        let total: f64 = sizes.iter().map(|&(_, w)| w).sum();
+        if total <= 0.0 {
+            return Err(MieError::InvalidDistribution("the number fractions must not all be zero"));
+        }
+        Ok(sizes.into_iter().map(|(r, w)| (r, w / total)).collect())
+    }
+}
+
+// Spheres in a layer, for Simulation.add_layer_spheres and compute_mie.
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct Spheres {
+    pub wavelength: f64,        // Vacuum wavelength [um]
+    pub n_re: f64,              // Refractive index of the spheres
+    pub n_im: f64,              // Its imaginary part, positive for absorbing spheres
+    pub volume_fraction: f64,   // Volume of the spheres per volume of the layer
+
+    #[wasm_bindgen(skip)]
+    pub distribution: Distribution
+}
+
+#[wasm_bindgen]
+impl Spheres {
+    // Spheres of one radius in um.
+    #[wasm_bindgen(constructor)]
+    pub fn new(wavelength: f64, n_re: f64, n_im: f64, volume_fraction: f64, radius: f64) -> Spheres {
+        Spheres { wavelength, n_re, n_im, volume_fraction, distribution: Distribution::Monodisperse { radius } }
+    }
+
+    pub fn set_monodisperse(&mut self, radius: f64) {
+        self.distribution = Distribution::Monodisperse { radius };
+    }
+
+    // Radii whose logarithm has a normal distribution with standard deviation sigma.
+    pub fn set_log_normal(&mut self, median: f64, sigma: f64) {
+        self.distribution = Distribution::LogNormal { median, sigma };
+    }
+
+    pub fn set_normal(&mut self, mean: f64, sd: f64) {
+        self.distribution = Distribution::Normal { mean, sd };
+    }
+
+    // Radii in um with their number fractions, which do not need to be normalized.
+    pub fn set_sizes(&mut self, radii: &[f64], fractions: &[f64]) {
+        self.distribution = Distribution::Tabulated { radii: radii.to_vec(), fractions: fractions.to_vec() };
+    }
+}
+
+// Mean optical properties of spheres, with the phase function at increasing cosines.
+#[wasm_bindgen]
+pub struct MieResult {
+    pub qext: f64,              // Extinction efficiency, mean cross section over mean geometric cross section
+    pub qsca: f64,              // Scattering efficiency
+    pub qabs: f64,              // Absorption efficiency
+    pub g: f64,                 // Anisotropy
+    pub csca: f64,              // Mean scattering cross section [um2]
+    pub cabs: f64,              // Mean absorption cross section [um2]
+    pub mus: f64,               // Scattering coefficient of the spheres [1/cm]
+    pub mua: f64,               // Absorption coefficient of the spheres [1/cm]
+
+    #[wasm_bindgen(skip)]
+    pub mu: Vec<f64>,           // Cosines of the scattering angle, from -1 to 1
+    #[wasm_bindgen(skip)]
+    pub p: Vec<f64>             // Phase function, normalized over the sphere [1/sr]
+}
+
+#[wasm_bindgen]
+impl MieResult {
+    pub fn get_mu(&self) -> Float64Array {
+        Float64Array::from(self.mu.as_slice())
+    }
+
+    pub fn get_p(&self) -> Float64Array {
+        Float64Array::from(self.p.as_slice())
+    }
+}
+
+impl Spheres {
+    /***********************************************************
+     * Average the Mie solutions over the size distribution of
+     * the spheres in a medium with refractive index n_medium.
+     ****/
+    pub fn calculate(&self, n_medium: f64) -> Result<MieResult, MieError> {
+        if self.wavelength.is_nan() || self.wavelength <= 0.0 {
+            return Err(MieError::InvalidWavelength(self.wavelength));
+        }
+        if !(n_medium > 0.0 && self.n_re > 0.0 && self.n_im >= 0.0) {
+            return Err(MieError::InvalidRefractiveIndex);
+        }
+        if !(0.0..1.0).contains(&self.volume_fraction) {
+            return Err(MieError::InvalidVolumeFraction(self.volume_fraction));
+        }
+        let sizes = self.distribution.sizes()?;
+
+        let pi = std::f64::consts::PI;
+        let k = 2.0 * pi * n_medium / self.wavelength;
+        let m = Complex::new(self.n_re / n_medium, self.n_im / n_medium);
+        let dtheta = pi / (N_ANGLES - 1) as f64;
+        // From theta = pi down to 0, so that mu increases.
+        let mu: Vec<f64> = (0..N_ANGLES).map(|i| ((N_ANGLES - 1 - i) as f64 * dtheta).cos()).collect();
+
+        let mut s11 = vec![0.0; N_ANGLES];
+        let (mut area, mut volume, mut cext, mut csca, mut gcsca) = (0.0, 0.0, 0.0, 0.0, 0.0);
+        for &(radius, fraction) in &sizes {
+            let coefficients = coefficients(k * radius, m);
+            let (qext, qsca, g) = coefficients.efficiencies();
+            let geometric = pi * radius * radius;
+            area += fraction * geometric;
+            volume += fraction * 4.0 / 3.0 * geometric * radius;
+            cext += fraction * qext * geometric;
+            csca += fraction * qsca * geometric;
+            gcsca += fraction * g * qsca * geometric;
+            for (value, &cos) in s11.iter_mut().zip(&mu) {
+                let (s1, s2) = coefficients.amplitudes(cos);
+                *value += fraction * (s1.norm_sqr() + s2.norm_sqr()) / 2.0;
+            }
+        }
+
+        let cabs = cext - csca;
+        // Spheres per um3, times um2 cross sections, gives 1/um.
+        let number_density = self.volume_fraction / volume;
+        Ok(MieResult {
+            qext: cext / area,
+            qsca: csca / area,
+            qabs: cabs / area,
+            g: gcsca / csca,
+            csca,
+            cabs,
+            mus: number_density * csca * 1E4,
+            mua: number_density * cabs * 1E4,
+            mu,
+            p: s11.iter().map(|v| v / (k * k * csca)).collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The example of Bohren and Huffman, appendix A, with x = 5.213 from a radius of 0.525 um at 0.6328 um.
+    #[test]
+    fn matches_bohren_huffman() {
+        let x = 2.0 * std::f64::consts::PI * 0.525 / 0.6328;
+        let coefficients = coefficients(x, Complex::new(1.55, 0.0));
+        let (qext, qsca, _) = coefficients.efficiencies();
+        let (s1, _) = coefficients.amplitudes(-1.0);
+        let qback = 4.0 * s1.norm_sqr() / (x * x);
+
+        assert!((qsca - 3.1054).abs() < 1E-4, "Qsca = {}", qsca);
+        assert!((qext - qsca).abs() < 1E-10);
+        assert!((qback - 2.9253).abs() < 1E-4, "Qback = {}", qback);
+    }
+
+    #[test]
+    fn phase_function_is_normalized_with_mean_cosine_g() {
+        let mut spheres = Spheres::new(0.6328, 1.59, 0.01, 0.05, 0.5);
+        spheres.set_log_normal(0.5, 0.2);
+        let result = spheres.calculate(1.33).unwrap();
+
+        let (mut total, mut mean_cos) = (0.0, 0.0);
+        for i in 1..N_ANGLES {
+            let dmu = result.mu[i] - result.mu[i - 1];
+            total += (result.p[i] + result.p[i - 1]) / 2.0 * dmu;
+            mean_cos += (result.mu[i] * result.p[i] + result.mu[i - 1] * result.p[i - 1]) / 2.0 * dmu;
+        }
+        let two_pi = 2.0 * std::f64::consts::PI;
+        assert!((two_pi * total - 1.0).abs() < 1E-3, "integral = {}", two_pi * total);
+        assert!((two_pi * mean_cos - result.g).abs() < 1E-3, "mean cosine = {}, g = {}", two_pi * mean_cos, result.g);
+        assert!((result.qext - result.qsca - result.qabs).abs() < 1E-12 && result.qabs > 0.0);
+    }
+
+    #[test]
+    fn rejects_invalid_spheres() {
+        let spheres = Spheres::new(0.6328, 1.59, 0.0, 0.05, 0.5);
+        assert!(spheres.calculate(1.33).is_ok());
+        assert_eq!(Spheres { wavelength: 0.0, ..spheres.clone() }.calculate(1.33).err(), Some(MieError::InvalidWavelength(0.0)));
+        assert_eq!(Spheres { n_im: -0.1, ..spheres.clone() }.calculate(1.33).err(), Some(MieError::InvalidRefractiveIndex));
+        assert_eq!(Spheres { volume_fraction: 1.0, ..spheres.clone() }.calculate(1.33).err(), Some(MieError::InvalidVolumeFraction(1.0)));
+
+        let mut tabulated = spheres;
+        tabulated.set_sizes(&[0.5, 1.0], &[1.0]);
+        assert!(matches!(tabulated.calculate(1.33), Err(MieError::InvalidDistribution(_))));
+        tabulated.set_sizes(&[0.5, 1.0], &[0.0, 0.0]);
+        assert!(matches!(tabulated.calculate(1.33), Err(MieError::InvalidDistribution(_))));
+    }
+}
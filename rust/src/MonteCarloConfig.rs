@@ -0,0 +1,308 @@
+// Parser for the mcml .mci input format
+
+use std::fmt;
+
+use crate::RunConfig::{OutputFormat, RunConfig};
+
+// Default number of time steps when the input omits it (mcml 1.0 files).
+const DEFAULT_NT: usize = 80;
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub line: usize,    // 1-based line number, 0 if unknown
+    pub column: usize,  // 1-based column number, 0 if unknown
+    pub message: String
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+pub struct MonteCarloConfig {
+    pub version: f64,
+    pub runs: Vec<RunConfig>
+}
+
+impl MonteCarloConfig {
+    /***********************************************************
+     * Parse the body of an .mci file.
+     *
+     * Anything after "#" on a line is a comment and blank lines
+     * are skipped. Every run becomes a RunConfig with the
+     * ambient media above and below stored as the first and
+     * last layers, and with the layer boundaries and critical
+     * angles already computed.
+     ****/
+    pub fn parse(body: &str) -> Result<MonteCarloConfig, ParseError> {
+        let mut parser = Parser::new(body);
+
+        let line = parser.next_line("file version")?;
+        let version = line.f64(0, "file version")?;
+        line.expect_len(1)?;
+
+        let line = parser.next_line("number of runs")?;
+        let number_of_runs = line.usize(0, "number of runs")?;
+        line.expect_len(1)?;
+        if number_of_runs == 0 {
+            return Err(line.error(0, "number of runs must be at least 1"));
+        }
+
+        let mut runs = Vec::with_capacity(number_of_runs);
+        for _ in 0..number_of_runs {
+            runs.push(parser.parse_run()?);
+        }
+
+        if let Some(line) = parser.peek() {
+            return Err(line.error(0, "unexpected data after the last run"));
+        }
+
+        Ok(MonteCarloConfig { version, runs })
+    }
+}
+
+struct Token<'a> {
+    column: usize,
+    text: &'a str
+}
+
+struct Line<'a> {
+    number: usize,
+    tokens: Vec<Token<'a>>
+}
+
+impl<'a> Line<'a> {
+    fn error(&self, index: usize, message: &str) -> ParseError {
+        let column = self.tokens.get(index).map_or(0, |t| t.column);
+        ParseError {
+            line: self.number,
+            column,
+            message: message.to_string()
+        }
+    }
+
+    fn token(&self, index: usize, what: &str) -> Result<&Token<'a>, ParseError> {
+        self.tokens.get(index).ok_or_else(|| {
+            let last = self.tokens.last().unwrap();
+            ParseError {
+                line: self.number,
+                column: last.column + last.text.chars().count(),
+                message: format!("missing {}", what)
+            }
+        })
+    }
+
+    fn f64(&self, index: usize, what: &str) -> Result<f64, ParseError> {
+        let token = self.token(index, what)?;
+        match token.text.parse::<f64>() {
+            Ok(value) if value.is_finite() => Ok(value),
+            _ => Err(self.error(index, &format!("invalid {} '{}', expected a number", what, token.text)))
+        }
+    }
+
+    fn usize(&self, index: usize, what: &str) -> Result<usize, ParseError> {
+        let token = self.token(index, what)?;
+        token.text.parse::<usize>().map_err(|_| {
+            self.error(index, &format!("invalid {} '{}', expected a non-negative integer", what, token.text))
+        })
+    }
+
+    fn expect_len(&self, len: usize) -> Result<(), ParseError> {
+        if self.tokens.len() > len {
+            return Err(self.error(len, &format!("unexpected value '{}'", self.tokens[len].text)));
+        }
+        Ok(())
+    }
+}
+
+struct Parser<'a> {
+    lines: Vec<Line<'a>>,
+    index: usize,
+    last_line: usize
+}
+
+impl<'a> Parser<'a> {
+    fn new(body: &'a str) -> Parser<'a> {
+        let mut lines = Vec::new();
+        let mut last_line = 0;
+
+        for (i, raw) in body.lines().enumerate() {
+            last_line = i + 1;
+            let content = raw.split('#').next().unwrap_or("");
+
+            let mut tokens = Vec::new();
+            let mut start: Option<usize> = None;
+            for (byte, c) in content.char_indices() {
+                if c.is_whitespace() {
+                    if let Some(s) = start.take() {
+                        tokens.push(Token { column: raw[..s].chars().count() + 1, text: &content[s..byte] });
+                    }
+                } else if start.is_none() {
+                    start = Some(byte);
+                }
+            }
+            if let Some(s) = start {
+                tokens.push(Token { column: raw[..s].chars().count() + 1, text: &content[s..] });
+            }
+
+            if !tokens.is_empty() {
+                lines.push(Line { number: i + 1, tokens });
+            }
+        }
+
+        Parser { lines, index: 0, last_line }
+    }
+
+    fn peek(&self) -> Option<&Line<'a>> {
+        self.lines.get(self.index)
+    }
+
+    fn next_line(&mut self, what: &str) -> Result<&Line<'a>, ParseError> {
+        if self.index >= self.lines.len() {
+            return Err(ParseError {
+                line: self.last_line,
+                column: 0,
+                message: format!("unexpected end of file, expected {}", what)
+            });
+        }
+        self.index += 1;
+        Ok(&self.lines[self.index - 1])
+    }
+
+    fn parse_run(&mut self) -> Result<RunConfig, ParseError> {
+        let mut run_config = RunConfig::new();
+
+        // Output file name and optional A/B flag
+        let line = self.next_line("output file name")?;
+        run_config.output_file = line.token(0, "output file name")?.text.to_string();
+        if let Some(flag) = line.tokens.get(1) {
+            run_config.output_format = match flag.text {
+                "A" | "a" => OutputFormat::Ascii,
+                "B" | "b" => OutputFormat::Binary,
+                _ => return Err(line.error(1, &format!("invalid output format '{}', expected A or B", flag.text)))
+            };
+        }
+        line.expect_len(2)?;
+
+        let line = self.next_line("number of photons")?;
+        run_config.number_of_photons = line.usize(0, "number of photons")?;
+        line.expect_len(1)?;
+
+        let line = self.next_line("incident angle")?;
+        run_config.alpha = line.f64(0, "incident angle")?;
+        line.expect_len(1)?;
+
+        let line = self.next_line("dz and dr")?;
+        run_config.dz = line.f64(0, "dz")?;
+        run_config.dr = line.f64(1, "dr")?;
+        line.expect_len(2)?;
+
+        let line = self.next_line("number of dz, dr, da and t")?;
+        run_config.nz = line.usize(0, "number of dz")?;
+        run_config.nr = line.usize(1, "number of dr")?;
+        run_config.na = line.usize(2, "number of da")?;
+        run_config.nt = if line.tokens.len() > 3 {
+            line.usize(3, "number of t")?
+        } else {
+            DEFAULT_NT
+        };
+        line.expect_len(4)?;
+        run_config.da = 0.5 * std::f64::consts::PI / run_config.na as f64;
+
+        let line = self.next_line("number of layers")?;
+        let number_of_layers = line.usize(0, "number of layers")?;
+        line.expect_len(1)?;
+        if number_of_layers == 0 {
+            return Err(line.error(0, "number of layers must be at least 1"));
+        }
+
+        // Medium above
+        let line = self.next_line("n for medium above")?;
+        let n_above = line.f64(0, "n for medium above")?;
+        line.expect_len(1)?;
+        run_config.add_layer(n_above, 0.0, 0.0, 0.0, 0.0);
+
+        for _ in 0..number_of_layers {
+            let line = self.next_line("layer parameters")?;
+            let n = line.f64(0, "n")?;
+            let mua = line.f64(1, "mua")?;
+            let mus = line.f64(2, "mus")?;
+            let g = line.f64(3, "g")?;
+            let d = line.f64(4, "d")?;
+            line.expect_len(5)?;
+            run_config.add_layer(n, mua, mus, g, d);
+        }
+
+        // Medium below
+        let line = self.next_line("n for medium below")?;
+        let n_below = line.f64(0, "n for medium below")?;
+        line.expect_len(1)?;
+        run_config.add_layer(n_below, 0.0, 0.0, 0.0, 0.0);
+
+        run_config.wth = 1E-4;
+        run_config.chance = 0.1;
+
+        run_config.update_layer_boundaries();
+        run_config.update_cos_crit();
+
+        Ok(run_config)
+    }
+}
+
+// First run of sample.mci, the starting point of the tests of other modules.
+#[cfg(test)]
+pub(crate) fn sample_run() -> RunConfig {
+    MonteCarloConfig::parse(include_str!("../../sample.mci")).unwrap().runs.remove(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sample() {
+        let config = MonteCarloConfig::parse(include_str!("../../sample.mci")).unwrap();
+        assert_eq!(config.version, 1.0);
+        assert_eq!(config.runs.len(), 6);
+
+        let run = sample_run();
+        assert_eq!(run.output_file, "example.mco");
+        assert_eq!(run.number_of_photons, 1000000);
+        assert_eq!((run.dz, run.dr), (0.01, 0.01));
+        assert_eq!((run.nz, run.nr, run.na, run.nt), (40, 50, 30, 50));
+        assert_eq!(run.layers.len(), 5);
+        let layer = &run.layers[3];
+        assert_eq!((layer.n, layer.mua, layer.mus, layer.g, layer.d), (1.37, 2.0, 10.0, 0.7, 0.2));
+        assert!((layer.z0 - 0.2).abs() < 1E-12 && (layer.z1 - 0.4).abs() < 1E-12);
+    }
+
+    #[test]
+    fn parses_template() {
+        let config = MonteCarloConfig::parse(include_str!("../../template.mci")).unwrap();
+        assert_eq!(config.runs.len(), 2);
+
+        let run = &config.runs[0];
+        assert_eq!(run.output_file, "temp1.mco");
+        assert_eq!(run.number_of_photons, 10);
+        assert_eq!((run.dz, run.dr), (20E-4, 20E-4));
+        assert_eq!((run.nz, run.nr, run.na, run.nt), (10, 20, 30, DEFAULT_NT));
+        assert_eq!(run.layers.len(), 4);
+        assert_eq!(run.layers[2].d, 1E8);
+        assert_eq!(config.runs[1].layers.len(), 3);
+    }
+
+    #[test]
+    fn reports_line_and_column() {
+        let body = include_str!("../../template.mci").replacen("20E-4\t20E-4", "20E-4\tabc", 1);
+        let error = MonteCarloConfig::parse(&body).err().unwrap();
+        assert_eq!((error.line, error.column), (15, 7));
+        assert_eq!(error.message, "invalid dr 'abc', expected a number");
+
+        let body = "1.0\n1 2\n";
+        let error = MonteCarloConfig::parse(body).err().unwrap();
+        assert_eq!((error.line, error.column), (2, 3));
+    }
+}
@@ -0,0 +1,623 @@
+// Summing and scaling of the raw tallies into physical quantities
+
+use js_sys::Float64Array;
+use wasm_bindgen::prelude::*;
+
+use crate::Go;
+use crate::Results;
+use crate::RunConfig::RunConfig;
+use crate::Uncertainty;
+
+#[wasm_bindgen]
+pub struct FinalResults {
+    pub rsp: f64,               // Specular reflectance [-]
+    pub rd: f64,                // Diffuse reflectance [-]
+    pub a: f64,                 // Absorbed fraction [-]
+    pub tt: f64,                // Diffuse transmittance [-]
+    pub rd_unscattered: f64,    // Unscattered reflectance [-]
+    pub tt_unscattered: f64,    // Unscattered (specular) transmittance [-]
+
+    #[wasm_bindgen(skip)]
+    pub a_l: Vec<f64>,          // [layer - 1], [-]
+    #[wasm_bindgen(skip)]
+    pub a_z: Vec<f64>,          // [iz], [1/cm]
+    #[wasm_bindgen(skip)]
+    pub fluence: Vec<f64>,      // [iz], [1/cm2]
+    #[wasm_bindgen(skip)]
+    pub rd_x: Vec<f64>,         // [ix], [-]
+    #[wasm_bindgen(skip)]
+    pub rd_r: Vec<f64>,         // [ir], [1/cm2]
+    #[wasm_bindgen(skip)]
+    pub rd_a: Vec<f64>,         // [ia], [sr-1]
+    #[wasm_bindgen(skip)]
+    pub tt_r: Vec<f64>,         // [ir], [1/cm2]
+    #[wasm_bindgen(skip)]
+    pub tt_a: Vec<f64>,         // [ia], [sr-1]
+
+    #[wasm_bindgen(skip)]
+    pub a_rz: Vec<f64>,         // [ir * nz + iz], [1/cm3]
+    #[wasm_bindgen(skip)]
+    pub rd_ra: Vec<f64>,        // [ir * na + ia], [1/(cm2 sr)]
+    #[wasm_bindgen(skip)]
+    pub tt_ra: Vec<f64>,        // [ir * na + ia], [1/(cm2 sr)]
+    #[wasm_bindgen(skip)]
+    pub w_txz: Vec<f64>,        // [(it * nr * 2 + ix) * nz + iz]
+
+    #[wasm_bindgen(skip)]
+    pub rd_rt: Vec<f64>,        // [ir * nt + it], [1/(cm2 ps)]
+    #[wasm_bindgen(skip)]
+    pub tt_rt: Vec<f64>,        // [ir * nt + it], [1/(cm2 ps)]
+    #[wasm_bindgen(skip)]
+    pub rd_t: Vec<f64>,         // [it], [1/ps]
+    #[wasm_bindgen(skip)]
+    pub tt_t: Vec<f64>,         // [it], [1/ps]
+
+    #[wasm_bindgen(skip)]
+    pub rd_r_ac: Vec<f64>,      // [ifreq * nr + ir], AC amplitude [1/cm2]
+    #[wasm_bindgen(skip)]
+    pub rd_r_phase: Vec<f64>,   // [ifreq * nr + ir], phase lag [rad]
+
+    // Partial pathlengths, empty unless pathlength bins were set
+    #[wasm_bindgen(skip)]
+    pub rd_path_mean: Vec<f64>, // [ir * nl + il], mean partial pathlength of the reflected photons [cm]
+    #[wasm_bindgen(skip)]
+    pub rd_path_pdf: Vec<f64>,  // [(ir * nl + il) * npath + ip], distribution of the partial pathlength [1/cm]
+
+    // Absorption sensitivity, empty without detectors
+    #[wasm_bindgen(skip)]
+    pub jacobian: Vec<f64>,     // [(idet * nr + ir) * nz + iz], d Rd_r / d mua of the voxel [1/cm]
+
+    // Polarization, empty unless polarized
+    #[wasm_bindgen(skip)]
+    pub stokes_xy: Vec<f64>,    // [(iy * nr * 2 + ix) * 4 + k], reflected I, Q, U, V per area [1/cm2]
+
+    // Fluorescence, 0 or empty unless a layer has fluorescence
+    pub rd_fl: f64,             // Escaping fluorescence per excitation photon [-]
+    pub a_fl: f64,              // Absorbed fluorescence per excitation photon [-]
+    #[wasm_bindgen(skip)]
+    pub rd_r_fl: Vec<f64>,      // [ir], [1/cm2]
+    #[wasm_bindgen(skip)]
+    pub rd_a_fl: Vec<f64>,      // [ia], [sr-1]
+    #[wasm_bindgen(skip)]
+    pub rd_ra_fl: Vec<f64>,     // [ir * na + ia], [1/(cm2 sr)]
+    #[wasm_bindgen(skip)]
+    pub a_rz_fl: Vec<f64>,      // [ir * nz + iz], [1/cm3]
+
+    // Relative standard errors, NaN or empty unless second moments were tracked
+    pub rd_error: f64,
+    pub a_error: f64,
+    pub tt_error: f64,
+    #[wasm_bindgen(skip)]
+    pub rd_x_error: Vec<f64>,   // [ix]
+    #[wasm_bindgen(skip)]
+    pub a_rz_error: Vec<f64>,   // [ir * nz + iz]
+    #[wasm_bindgen(skip)]
+    pub rd_ra_error: Vec<f64>,  // [ir * na + ia]
+    #[wasm_bindgen(skip)]
+    pub tt_ra_error: Vec<f64>   // [ir * na + ia]
+}
+
+// Index of the layer that contains the center of depth bin iz.
+pub fn iz_to_layer(iz: usize, run_config: &RunConfig) -> usize {
+    let layers = &run_config.layers;
+    let mut i = 1;
+    while (iz as f64 + 0.5) * run_config.dz >= layers[i].z1 && i < layers.len() - 1 {
+        i += 1;
+    }
+    i
+}
+
+// Sum a row-major matrix per row, per column and in total.
+fn sum_2d(matrix: &[f64], rows: usize, cols: usize) -> (Vec<f64>, Vec<f64>, f64) {
+    let mut sum_r = vec![0.0; rows];
+    let mut sum_c = vec![0.0; cols];
+    let mut sum = 0.0;
+
+    for i in 0..rows {
+        for j in 0..cols {
+            let val = matrix[i * cols + j];
+            sum_r[i] += val;
+            sum_c[j] += val;
+            sum += val;
+        }
+    }
+
+    (sum_r, sum_c, sum)
+}
+
+/***********************************************************
+ * Scale a raw [ir * na + ia] tally of reflected or
+ * transmitted weight by the bin sizes and the number of
+ * photons. Returns it per area and solid angle, summed over
+ * the angles per area, summed over the radii per solid
+ * angle, and the total fraction, in that order.
+ ****/
+pub fn scale_ra(run_config: &RunConfig, raw: &[f64], n_photons: f64) -> (Vec<f64>, Vec<f64>, Vec<f64>, f64) {
+    let nr = run_config.nr;
+    let na = run_config.na;
+    let dr = run_config.dr;
+    let da = run_config.da;
+    let pi = std::f64::consts::PI;
+
+    let (mut r, mut a, total) = sum_2d(raw, nr, na);
+    let mut ra = raw.to_vec();
+
+    let mut scale1 = 4.0 * pi * pi * dr * (da / 2.0).sin() * dr * n_photons;
+    /* The factor (ir+0.5)*sin(2a) to be added. */
+    for ir in 0..nr {
+        for ia in 0..na {
+            let scale2 = 1.0 / ((ir as f64 + 0.5) * (2.0 * (ia as f64 + 0.5) * da).sin() * scale1);
+            ra[ir * na + ia] *= scale2;
+        }
+    }
+
+    scale1 = 2.0 * pi * dr * dr * n_photons;
+    /* area is 2*PI*[(ir+0.5)*dr]*dr. */
+    /* ir+0.5 to be added. */
+    for (ir, val) in r.iter_mut().enumerate() {
+        *val *= 1.0 / ((ir as f64 + 0.5) * scale1);
+    }
+
+    scale1 = 2.0 * pi * da * n_photons;
+    /* solid angle is 2*PI*sin(a)*da. sin(a) to be added. */
+    for (ia, val) in a.iter_mut().enumerate() {
+        *val *= 1.0 / (((ia as f64 + 0.5) * da).sin() * scale1);
+    }
+
+    (ra, r, a, total * (1.0 / n_photons))
+}
+
+impl Results {
+    /***********************************************************
+     * Sum the raw tallies over their extra dimensions and scale
+     * everything by the bin sizes and the number of photons,
+     * giving the physical quantities written to .mco files.
+     * Without photons every tally is 0 rather than 0 / 0.
+     ****/
+    pub fn finalize(&self, run_config: &RunConfig, n_photons: usize) -> FinalResults {
+        let nr = run_config.nr;
+        let nz = run_config.nz;
+        let na = run_config.na;
+        let dr = run_config.dr;
+        let dz = run_config.dz;
+        let nt = run_config.nt;
+        let dt = run_config.dt;
+        let n_photons = n_photons.max(1) as f64;
+        let pi = std::f64::consts::PI;
+
+        let (rd_ra, rd_r, rd_a, rd) = scale_ra(run_config, &self.rd_ra, n_photons);
+        let (tt_ra, tt_r, tt_a, tt) = scale_ra(run_config, &self.tt_ra, n_photons);
+        let (_, mut rd_t, _) = sum_2d(&self.rd_rt, nr, nt);
+        let (_, mut tt_t, _) = sum_2d(&self.tt_rt, nr, nt);
+
+        let mut a_z = vec![0.0; nz];
+        let mut a_l = vec![0.0; run_config.layers.len() - 2];
+        let mut a = 0.0;
+        for (iz, a_z_iz) in a_z.iter_mut().enumerate() {
+            let layer = iz_to_layer(iz, run_config);
+            for ir in 0..nr {
+                let val = self.a_rz[ir * nz + iz];
+                *a_z_iz += val;
+                if layer - 1 < a_l.len() {
+                    a_l[layer - 1] += val;
+                }
+                a += val;
+            }
+        }
+
+        let mut a_rz = self.a_rz.clone();
+        let mut rd_x = self.rd_x.clone();
+        let mut w_txz = self.w_txz.clone();
+
+        let mut scale1 = 2.0 * nr as f64 / n_photons;
+        for val in rd_x.iter_mut() {
+            *val *= scale1;
+        }
+
+        scale1 = 2.0 * pi * dr * dr * dz * n_photons;
+        for ir in 0..nr {
+            for iz in 0..nz {
+                a_rz[ir * nz + iz] /= (ir as f64 + 0.5) * scale1;
+            }
+        }
+
+        scale1 = n_photons * dz * dr;
+        for val in w_txz.iter_mut() {
+            *val /= scale1;
+        }
+
+        let mut rd_rt = self.rd_rt.clone();
+        let mut tt_rt = self.tt_rt.clone();
+        scale1 = 2.0 * pi * dr * dr * dt * n_photons;
+        /* area is 2*PI*[(ir+0.5)*dr]*dr, times the bin width dt. */
+        for ir in 0..nr {
+            let scale2 = 1.0 / ((ir as f64 + 0.5) * scale1);
+            for it in 0..nt {
+                rd_rt[ir * nt + it] *= scale2;
+                tt_rt[ir * nt + it] *= scale2;
+            }
+        }
+
+        // Amplitude scaled like Rd_r, phase lag of the photon density wave.
+        let n_frequencies = run_config.frequencies.len();
+        let mut rd_r_ac = vec![0.0; n_frequencies * nr];
+        let mut rd_r_phase = vec![0.0; n_frequencies * nr];
+        scale1 = 2.0 * pi * dr * dr * n_photons;
+        for i in 0..n_frequencies * nr {
+            let ir = i % nr;
+            let re = self.rd_r_fd[i * 2];
+            let im = self.rd_r_fd[i * 2 + 1];
+            rd_r_ac[i] = re.hypot(im) / ((ir as f64 + 0.5) * scale1);
+            rd_r_phase[i] = -im.atan2(re);
+        }
+
+        // Averages over the weight reflected in each radial bin, so the photon count cancels.
+        let nl = run_config.layers.len() - 2;
+        let npath = run_config.npath;
+        let mut rd_path_mean = self.rd_path.clone();
+        let mut rd_path_pdf = self.rd_path_hist.clone();
+        if npath > 0 {
+            for ir in 0..nr {
+                let weight: f64 = self.rd_ra[ir * na..(ir + 1) * na].iter().sum();
+                if weight == 0.0 {
+                    continue;
+                }
+                for il in 0..nl {
+                    rd_path_mean[ir * nl + il] /= weight;
+                    for ip in 0..npath {
+                        rd_path_pdf[(ir * nl + il) * npath + ip] /= weight * run_config.dl;
+                    }
+                }
+            }
+        }
+
+        // Derivative of Rd_r of each detector, scaled like Rd_r.
+        let mut jacobian = self.jacobian.clone();
+        scale1 = 2.0 * pi * dr * dr * n_photons;
+        for (idet, &ir) in run_config.detectors.iter().enumerate() {
+            let scale2 = -1.0 / ((ir as f64 + 0.5) * scale1);
+            for val in jacobian[idet * nr * nz..(idet + 1) * nr * nz].iter_mut() {
+                *val *= scale2;
+            }
+        }
+
+        // Square bins of dr on a side.
+        scale1 = 1.0 / (dr * dr * n_photons);
+        let stokes_xy = self.stokes_xy.iter().map(|val| val * scale1).collect();
+
+        // Fluorescence scaled like Rd and A_rz, per excitation photon.
+        let (rd_ra_fl, rd_r_fl, rd_a_fl, rd_fl, a_rz_fl, a_fl) = match &self.fluorescence {
+            Some(f) => {
+                let (rd_ra_fl, rd_r_fl, rd_a_fl, rd_fl) = scale_ra(run_config, &f.rd_ra, n_photons);
+                let mut a_rz_fl = f.a_rz.clone();
+                scale1 = 2.0 * pi * dr * dr * dz * n_photons;
+                for ir in 0..nr {
+                    for iz in 0..nz {
+                        a_rz_fl[ir * nz + iz] /= (ir as f64 + 0.5) * scale1;
+                    }
+                }
+                (rd_ra_fl, rd_r_fl, rd_a_fl, rd_fl, a_rz_fl, f.a_rz.iter().sum::<f64>() / n_photons)
+            }
+            None => (Vec::new(), Vec::new(), Vec::new(), 0.0, Vec::new(), 0.0)
+        };
+
+        scale1 = 1.0 / (dt * n_photons);
+        for it in 0..nt {
+            rd_t[it] *= scale1;
+            tt_t[it] *= scale1;
+        }
+
+        scale1 = 1.0 / (dz * n_photons);
+        for val in a_z.iter_mut() {
+            *val *= scale1;
+        }
+
+        // Fluence from the absorption and the mua of the layer
+        let fluence = a_z.iter().enumerate().map(|(iz, &val)| {
+            let mua = run_config.layers[iz_to_layer(iz, run_config)].mua;
+            if mua == 0.0 { 0.0 } else { val / mua }
+        }).collect();
+
+        scale1 = 1.0 / n_photons;
+        for val in a_l.iter_mut() {
+            *val *= scale1;
+        }
+
+        // Relative errors do not depend on the scaling.
+        let n = n_photons as usize;
+        let (rd_error, a_error, tt_error, rd_x_error, a_rz_error, rd_ra_error, tt_ra_error) = match &self.moments {
+            Some(m) => (
+                Uncertainty::relative_error(self.rd_ra.iter().sum(), m.rd, n),
+                Uncertainty::relative_error(a, m.a, n),
+                Uncertainty::relative_error(self.tt_ra.iter().sum(), m.tt, n),
+                Uncertainty::relative_errors(&self.rd_x, &m.rd_x, n),
+                Uncertainty::relative_errors(&self.a_rz, &m.a_rz, n),
+                Uncertainty::relative_errors(&self.rd_ra, &m.rd_ra, n),
+                Uncertainty::relative_errors(&self.tt_ra, &m.tt_ra, n)
+            ),
+            None => (f64::NAN, f64::NAN, f64::NAN, Vec::new(), Vec::new(), Vec::new(), Vec::new())
+        };
+
+        FinalResults {
+            rsp: Go::calculate_r_specular(run_config),
+            rd,
+            a: a * scale1,
+            tt,
+            rd_unscattered: self.rd_unscattered * scale1,
+            tt_unscattered: self.tt_unscattered * scale1,
+            a_l,
+            a_z,
+            fluence,
+            rd_x,
+            rd_r,
+            rd_a,
+            tt_r,
+            tt_a,
+            a_rz,
+            rd_ra,
+            tt_ra,
+            w_txz,
+            rd_rt,
+            tt_rt,
+            rd_t,
+            tt_t,
+            rd_r_ac,
+            rd_r_phase,
+            rd_path_mean,
+            rd_path_pdf,
+            jacobian,
+            stokes_xy,
+            rd_fl,
+            a_fl,
+            rd_r_fl,
+            rd_a_fl,
+            rd_ra_fl,
+            a_rz_fl,
+            rd_error,
+            a_error,
+            tt_error,
+            rd_x_error,
+            a_rz_error,
+            rd_ra_error,
+            tt_ra_error
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl FinalResults {
+    // Specular plus diffuse reflectance.
+    pub fn total_reflectance(&self) -> f64 {
+        self.rsp + self.rd
+    }
+
+    // Unscattered plus diffuse transmittance.
+    pub fn total_transmittance(&self) -> f64 {
+        self.tt_unscattered + self.tt
+    }
+
+    // Sum of all the fractions, 1 up to the statistical error of the photons lost to roulette.
+    pub fn energy_balance(&self) -> f64 {
+        self.rsp + self.rd + self.rd_unscattered + self.a + self.tt + self.tt_unscattered
+    }
+
+    pub fn get_a_l(&self) -> Float64Array {
+        Float64Array::from(self.a_l.as_slice())
+    }
+
+    pub fn get_a_z(&self) -> Float64Array {
+        Float64Array::from(self.a_z.as_slice())
+    }
+
+    pub fn get_fluence(&self) -> Float64Array {
+        Float64Array::from(self.fluence.as_slice())
+    }
+
+    pub fn get_rd_x(&self) -> Float64Array {
+        Float64Array::from(self.rd_x.as_slice())
+    }
+
+    pub fn get_rd_r(&self) -> Float64Array {
+        Float64Array::from(self.rd_r.as_slice())
+    }
+
+    pub fn get_rd_a(&self) -> Float64Array {
+        Float64Array::from(self.rd_a.as_slice())
+    }
+
+    pub fn get_tt_r(&self) -> Float64Array {
+        Float64Array::from(self.tt_r.as_slice())
+    }
+
+    pub fn get_tt_a(&self) -> Float64Array {
+        Float64Array::from(self.tt_a.as_slice())
+    }
+
+    pub fn get_a_rz(&self) -> Float64Array {
+        Float64Array::from(self.a_rz.as_slice())
+    }
+
+    pub fn get_rd_ra(&self) -> Float64Array {
+        Float64Array::from(self.rd_ra.as_slice())
+    }
+
+    pub fn get_tt_ra(&self) -> Float64Array {
+        Float64Array::from(self.tt_ra.as_slice())
+    }
+
+    pub fn get_w_txz(&self) -> Float64Array {
+        Float64Array::from(self.w_txz.as_slice())
+    }
+
+    pub fn get_rd_rt(&self) -> Float64Array {
+        Float64Array::from(self.rd_rt.as_slice())
+    }
+
+    pub fn get_tt_rt(&self) -> Float64Array {
+        Float64Array::from(self.tt_rt.as_slice())
+    }
+
+    pub fn get_rd_t(&self) -> Float64Array {
+        Float64Array::from(self.rd_t.as_slice())
+    }
+
+    pub fn get_tt_t(&self) -> Float64Array {
+        Float64Array::from(self.tt_t.as_slice())
+    }
+
+    pub fn get_rd_r_ac(&self) -> Float64Array {
+        Float64Array::from(self.rd_r_ac.as_slice())
+    }
+
+    pub fn get_rd_r_phase(&self) -> Float64Array {
+        Float64Array::from(self.rd_r_phase.as_slice())
+    }
+
+    pub fn get_rd_path_mean(&self) -> Float64Array {
+        Float64Array::from(self.rd_path_mean.as_slice())
+    }
+
+    pub fn get_rd_path_pdf(&self) -> Float64Array {
+        Float64Array::from(self.rd_path_pdf.as_slice())
+    }
+
+    pub fn get_jacobian(&self) -> Float64Array {
+        Float64Array::from(self.jacobian.as_slice())
+    }
+
+    pub fn get_stokes_xy(&self) -> Float64Array {
+        Float64Array::from(self.stokes_xy.as_slice())
+    }
+
+    pub fn get_rd_r_fl(&self) -> Float64Array {
+        Float64Array::from(self.rd_r_fl.as_slice())
+    }
+
+    pub fn get_rd_a_fl(&self) -> Float64Array {
+        Float64Array::from(self.rd_a_fl.as_slice())
+    }
+
+    pub fn get_rd_ra_fl(&self) -> Float64Array {
+        Float64Array::from(self.rd_ra_fl.as_slice())
+    }
+
+    pub fn get_a_rz_fl(&self) -> Float64Array {
+        Float64Array::from(self.a_rz_fl.as_slice())
+    }
+
+    pub fn get_rd_x_error(&self) -> Float64Array {
+        Float64Array::from(self.rd_x_error.as_slice())
+    }
+
+    pub fn get_a_rz_error(&self) -> Float64Array {
+        Float64Array::from(self.a_rz_error.as_slice())
+    }
+
+    pub fn get_rd_ra_error(&self) -> Float64Array {
+        Float64Array::from(self.rd_ra_error.as_slice())
+    }
+
+    pub fn get_tt_ra_error(&self) -> Float64Array {
+        Float64Array::from(self.tt_ra_error.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Simulation;
+
+    #[test]
+    fn conserves_energy() {
+        let mut simulation = Simulation::new();
+        simulation.set_seed(1);
+        simulation.configure(crate::MonteCarloConfig::sample_run()).unwrap();
+        simulation.launch_photons(5000);
+
+        let result = simulation.finalize(5000);
+        assert!((result.energy_balance() - 1.0).abs() < 0.01, "balance {}", result.energy_balance());
+        assert!((result.a_l.iter().sum::<f64>() - result.a).abs() < 1E-9);
+    }
+
+    #[test]
+    fn time_resolved_tallies_sum_to_totals() {
+        let mut simulation = Simulation::new();
+        simulation.set_seed(2);
+        simulation.configure(crate::MonteCarloConfig::sample_run()).unwrap();
+        simulation.launch_photons(2000);
+
+        // Every photon leaves after t0 = 0, the late ones are in the last bin.
+        let result = simulation.finalize(2000);
+        let dt = simulation.run_config.dt;
+        assert!((result.rd_t.iter().sum::<f64>() * dt - result.rd).abs() < 1E-9);
+        assert!((result.tt_t.iter().sum::<f64>() * dt - result.tt).abs() < 1E-9);
+        assert!(result.rd_t.iter().all(|&rd| rd >= 0.0) && result.rd_t[0] > 0.0);
+    }
+
+    #[test]
+    fn frequency_domain_reflectance() {
+        let mut run_config = crate::MonteCarloConfig::sample_run();
+        run_config.frequencies = vec![0.0, 500.0];
+        let mut simulation = Simulation::new();
+        simulation.set_seed(3);
+        simulation.configure(run_config).unwrap();
+        simulation.launch_photons(2000);
+
+        // Without modulation the amplitude is Rd_r, later photons lag more at 500 MHz.
+        let result = simulation.finalize(2000);
+        let nr = simulation.run_config.nr;
+        for ir in 0..nr {
+            assert!((result.rd_r_ac[ir] - result.rd_r[ir]).abs() <= 1E-9 * result.rd_r[ir]);
+            assert_eq!(result.rd_r_phase[ir], 0.0);
+            assert!(result.rd_r_ac[nr + ir] <= result.rd_r[ir] * (1.0 + 1E-9));
+        }
+        assert!(result.rd_r_phase[nr] > 0.0 && result.rd_r_phase[nr + 10] > result.rd_r_phase[nr]);
+    }
+
+    #[test]
+    fn partial_pathlengths_match_time_of_flight() {
+        let mut run_config = crate::MonteCarloConfig::sample_run();
+        run_config.frequencies = vec![1.0];
+        run_config.npath = 20;
+        run_config.dl = 0.5;
+        let mut simulation = Simulation::new();
+        simulation.set_seed(4);
+        simulation.configure(run_config).unwrap();
+        simulation.launch_photons(2000);
+
+        let result = simulation.finalize(2000);
+        let run_config = &simulation.run_config;
+        let nl = run_config.layers.len() - 2;
+        let omega = 2.0 * std::f64::consts::PI * 1E-6;
+        for ir in 0..run_config.nr {
+            if result.rd_r[ir] == 0.0 {
+                continue;
+            }
+
+            // At a low frequency the phase lag is omega times the mean time of flight.
+            let paths = &result.rd_path_mean[ir * nl..(ir + 1) * nl];
+            let time: f64 = paths.iter().zip(&run_config.layers[1..]).map(|(l, layer)| l * layer.n / crate::Go::C_VACUUM).sum();
+            assert!((result.rd_r_phase[ir] / omega - time).abs() < 1E-6 * time, "{} {}", result.rd_r_phase[ir] / omega, time);
+
+            let pdf = &result.rd_path_pdf[ir * nl * 20..(ir + 1) * nl * 20];
+            assert!((pdf.iter().sum::<f64>() * run_config.dl - nl as f64).abs() < 1E-9);
+        }
+
+        // Without pathlength bins the photons do not keep their pathlengths.
+        let mut simulation = Simulation::new();
+        simulation.configure(crate::MonteCarloConfig::sample_run()).unwrap();
+        simulation.launch_photons(10);
+        let result = simulation.finalize(10);
+        assert!(result.rd_path_mean.is_empty() && result.rd_path_pdf.is_empty());
+    }
+
+    #[test]
+    fn finalizes_without_photons() {
+        let mut simulation = Simulation::new();
+        simulation.configure(crate::MonteCarloConfig::sample_run()).unwrap();
+
+        let result = simulation.finalize(0);
+        assert_eq!(result.rd, 0.0);
+        assert_eq!(result.a, 0.0);
+        assert!(result.a_rz.iter().chain(&result.rd_ra).chain(&result.fluence).all(|&val| val == 0.0));
+    }
+}
@@ -0,0 +1,247 @@
+// Writer for the mcml .mco ASCII output format
+
+use crate::Convolution::{Beam, BeamKind, ConvolvedResults};
+use crate::OutputCalc::FinalResults;
+use crate::RunConfig::RunConfig;
+
+pub struct OutputWriter {
+    lines: Vec<String>
+}
+
+// Format a number like JavaScript's Number.prototype.toString().
+pub fn num_to_js(val: f64) -> String {
+    let abs = val.abs();
+    if abs != 0.0 && !(1E-6..1E21).contains(&abs) {
+        let s = format!("{:e}", val);
+        match s.split_once('e') {
+            Some((mantissa, exp)) if !exp.starts_with('-') => format!("{}e+{}", mantissa, exp),
+            _ => s
+        }
+    } else {
+        format!("{}", val)
+    }
+}
+
+// Format a number like JavaScript's toExponential(4), with an upper case E.
+pub fn num_to_sci(val: f64) -> String {
+    let s = format!("{:.4e}", val);
+    match s.split_once('e') {
+        Some((mantissa, exp)) if exp.starts_with('-') => format!("{}E{}", mantissa, exp),
+        Some((mantissa, exp)) => format!("{}E+{}", mantissa, exp),
+        None => s
+    }
+}
+
+impl OutputWriter {
+    pub fn new() -> OutputWriter {
+        OutputWriter { lines: Vec::new() }
+    }
+
+    pub fn build(&self) -> String {
+        self.lines.join("\n")
+    }
+
+    fn write_line(&mut self, line: &str) {
+        self.lines.push(line.to_string());
+    }
+
+    fn write_value(&mut self, val: f64) {
+        self.lines.push(format!("\t{}", num_to_sci(val)));
+    }
+
+    fn write_array(&mut self, header: &str, values: &[f64]) {
+        self.write_line(header);
+        for &val in values {
+            self.write_value(val);
+        }
+        self.write_line("");
+    }
+
+    fn write_matrix(&mut self, matrix: &[f64], cols: usize) {
+        if cols == 0 {
+            return;
+        }
+        for row in matrix.chunks(cols) {
+            let line: Vec<String> = row.iter().map(|&val| num_to_sci(val)).collect();
+            self.write_line(&line.join("\t"));
+        }
+    }
+
+    pub fn write_result(&mut self, run_config: &RunConfig, result: &FinalResults, simulation_time: f64) {
+        self.write_version("A1");
+
+        self.write_line(&format!("# Simulation time: {:.3} seconds", simulation_time));
+        self.write_input_parameters(run_config);
+        self.write_rat(result);
+
+        self.write_array("A_l #Absorption as a function of layer. [-]", &result.a_l);
+        self.write_array("A_z #A[0], [1],..A[nz-1]. [1/cm]", &result.a_z);
+        self.write_array("Rd_x #Rd[0], [1],..Rd[2nr-1]. [-]", &result.rd_x);
+        self.write_array("Rd_r #Rd[0], [1],..Rd[nr-1]. [1/cm2]", &result.rd_r);
+        self.write_array("Rd_a #Rd[0], [1],..Rd[na-1]. [sr-1]", &result.rd_a);
+        self.write_array("Tt_r #Tt[0], [1],..Tt[nr-1]. [1/cm2]", &result.tt_r);
+        self.write_array("Tt_a #Tt[0], [1],..Tt[na-1]. [sr-1]", &result.tt_a);
+
+        self.write_a_rz(&result.a_rz, run_config.nz);
+        self.write_rd_ra(&result.rd_ra, run_config.na);
+        self.write_tt_ra(&result.tt_ra, run_config.na);
+    }
+
+    // Responses to a beam of finite size, convolved from the pencil beam results.
+    pub fn write_convolution(&mut self, run_config: &RunConfig, beam: &Beam, result: &ConvolvedResults) {
+        let profile = match beam.kind {
+            BeamKind::Gaussian => "Gaussian",
+            BeamKind::Flat => "Flat"
+        };
+        self.write_line(&format!("# Convolution of {} for a beam of finite size", run_config.output_file));
+        self.write_line("Beam\t\t\t\t\t# Beam parameters. cm and W are used.");
+        self.write_line(&format!("{}\t\t\t\t# Profile, Gaussian radius at 1/e2.", profile));
+        self.write_line(&format!("{}\t{}\t\t\t\t# Radius [cm], power [W]", num_to_js(beam.radius), num_to_js(beam.power)));
+        self.write_line(&format!("{}\t{}\t\t\t\t# dz, dr [cm]", num_to_js(run_config.dz), num_to_js(run_config.dr)));
+        self.write_line(&format!("{}\t{}\t\t\t\t# No. of dz, dr.\n", run_config.nz, run_config.nr));
+
+        self.write_array("Rd_r #Rd[0], [1],..Rd[nr-1]. [W/cm2]", &result.rd_r);
+        self.write_array("Tt_r #Tt[0], [1],..Tt[nr-1]. [W/cm2]", &result.tt_r);
+
+        self.write_line("#A[r][z]. [W/cm3]");
+        self.write_line("A_rz");
+        self.write_matrix(&result.a_rz, run_config.nz);
+        self.write_line("");
+
+        self.write_line("#F[r][z]. [W/cm2]");
+        self.write_line("F_rz");
+        self.write_matrix(&result.fluence_rz, run_config.nz);
+        self.write_line("");
+    }
+
+    fn write_a_rz(&mut self, a_rz: &[f64], nz: usize) {
+        self.write_line("#A[r][z]. [1/cm3]");
+        self.write_line("# A[0][0], [0][1],..[0][nz-1]");
+        self.write_line("# A[1][0], [1][1],..[1][nz-1]");
+        self.write_line("# ...");
+        self.write_line("# A[nr-1][0], [nr-1][1],..[nr-1][nz-1]");
+        self.write_line("A_rz");
+
+        self.write_matrix(a_rz, nz);
+
+        self.write_line("");
+    }
+
+    fn write_rd_ra(&mut self, rd_ra: &[f64], na: usize) {
+        self.write_line("#Rd[r][angle]. [1/(cm2sr)].");
+        self.write_line("# Rd[0][0], [0][1],..[0][na-1]");
+        self.write_line("# Rd[1][0], [1][1],..[1][na-1]");
+        self.write_line("# ...");
+        self.write_line("# Rd[nr-1][0], [nr-1][1],..[nr-1][na-1]");
+        self.write_line("Rd_ra");
+
+        self.write_matrix(rd_ra, na);
+
+        self.write_line("");
+    }
+
+    fn write_tt_ra(&mut self, tt_ra: &[f64], na: usize) {
+        self.write_line("#Tt[r][angle]. [1/(cm2sr)].");
+        self.write_line("# Tt[0][0], [0][1],..[0][na-1]");
+        self.write_line("# Tt[1][0], [1][1],..[1][na-1]");
+        self.write_line("# ...");
+        self.write_line("# Tt[nr-1][0], [nr-1][1],..[nr-1][na-1]");
+        self.write_line("Tt_ra");
+
+        self.write_matrix(tt_ra, na);
+
+        self.write_line("");
+    }
+
+    fn write_rat(&mut self, result: &FinalResults) {
+        self.write_line("RAT #Reflectance, absorption, transmission.");
+        self.write_line(&format!("{:.6} \t# Specular reflectance [-]", result.rsp));
+        self.write_line(&format!("{:.6} \t# Diffuse reflectance [-]", result.rd));
+        self.write_line(&format!("{:.6} \t# Total reflectance [-]", result.total_reflectance()));
+        self.write_line(&format!("{:.6} \t# Absorbed fraction [-]", result.a));
+        self.write_line(&format!("{:.6} \t# Specular Transmittance [-]", result.tt_unscattered));
+        self.write_line(&format!("{:.6} \t# Diffuse Transmittance [-]", result.tt));
+        self.write_line(&format!("{:.6} \t# Total Transmittance [-]\n", result.total_transmittance()));
+    }
+
+    fn write_input_parameters(&mut self, run_config: &RunConfig) {
+        let layers = &run_config.layers;
+
+        self.write_line("InParm\t\t\t\t\t# Input parameters. cm is used.");
+        self.write_line(&format!("{}\t\t\t\t# output file name, ASCII.", run_config.output_file));
+        self.write_line(&format!("{}\t\t\t\t\t# No. of photons", run_config.number_of_photons));
+
+        self.write_line(&format!("{}\t{}\t\t\t\t# dz, dr [cm]", num_to_js(run_config.dz), num_to_js(run_config.dr)));
+        self.write_line(&format!("{}\t{}\t{}\t{}\t\t# No. of dz, dr, da, & t.\n", run_config.nz, run_config.nr, run_config.na, run_config.nt));
+
+        self.write_line(&format!("{}\t\t\t\t\t# Number of layers", layers.len() - 2));
+        self.write_line("#n\tmua\tmus\tg\td\t# One line for each layer");
+        self.write_line(&format!("{}\t\t\t\t\t# n for medium above", num_to_js(layers[0].n)));
+
+        for (i, s) in layers.iter().enumerate().take(layers.len() - 1).skip(1) {
+            self.write_line(&format!(
+                "{}\t{}\t{}\t{}\t{}\t# layer {}",
+                num_to_js(s.n),
+                num_to_js(s.mua),
+                num_to_js(s.mus),
+                num_to_js(s.g),
+                num_to_js(s.z1 - s.z0),
+                i
+            ));
+        }
+
+        self.write_line(&format!("{} \t\t\t\t\t# n for medium below\n", num_to_js(layers[layers.len() - 1].n)));
+    }
+
+    fn write_version(&mut self, version: &str) {
+        self.write_line(&format!("{} \t# Version number of the file format.\n", version));
+        self.write_line("####\n# Data categories include:");
+        self.write_line("# InParm, RAT,");
+        self.write_line("# A_l, A_z, Rd_r, Rd_a, Tt_r, Tt_a,");
+        self.write_line("# A_rz, Rd_ra, Tt_ra\n####\n");
+    }
+}
+
+impl Default for OutputWriter {
+    fn default() -> OutputWriter {
+        OutputWriter::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_numbers_like_javascript() {
+        assert_eq!(num_to_js(0.0), "0");
+        assert_eq!(num_to_js(1.37), "1.37");
+        assert_eq!(num_to_js(20E-4), "0.002");
+        assert_eq!(num_to_js(1E-7), "1e-7");
+        assert_eq!(num_to_js(-2.5E-8), "-2.5e-8");
+        assert_eq!(num_to_js(1E21), "1e+21");
+        assert_eq!(num_to_js(1E20), "100000000000000000000");
+    }
+
+    #[test]
+    fn formats_scientific_like_javascript() {
+        assert_eq!(num_to_sci(0.0), "0.0000E+0");
+        assert_eq!(num_to_sci(123.456), "1.2346E+2");
+        assert_eq!(num_to_sci(-4.5E-5), "-4.5000E-5");
+    }
+
+    #[test]
+    fn writes_every_section() {
+        let mut simulation = crate::Simulation::new();
+        simulation.set_seed(1);
+        simulation.configure(crate::MonteCarloConfig::sample_run()).unwrap();
+        simulation.launch_photons(100);
+
+        let mut output = OutputWriter::new();
+        output.write_result(&simulation.run_config, &simulation.finalize(100), 1.0);
+        let output = output.build();
+        for section in ["InParm", "RAT", "A_l", "A_z", "Rd_x", "Rd_r", "Rd_a", "Tt_r", "Tt_a", "A_rz", "Rd_ra", "Tt_ra"] {
+            assert!(output.lines().any(|line| line.split_whitespace().next() == Some(section)), "missing {}", section);
+        }
+    }
+}
@@ -0,0 +1,294 @@
+// Perturbation Monte Carlo: reflectance and transmittance for other optical
+// properties, by re-weighting the recorded histories of the photons that left
+// the tissue instead of launching new photons.
+//
+// A photon that had k interactions and travelled L in a layer was sampled with
+// mut = mua + mus and lost mua / mut of its weight at each interaction. With
+// mua' and mus' in that layer the same path has the weight ratio
+//   (mus' / mus)^k * exp(-(mut' - mut) * L).
+
+use std::fmt;
+
+use js_sys::Float64Array;
+use wasm_bindgen::prelude::*;
+
+use crate::OutputCalc;
+use crate::Results;
+use crate::RunConfig::RunConfig;
+
+// What is known about a photon when it leaves the tissue.
+#[derive(Clone)]
+pub struct Exit {
+    pub transmitted: bool,      // Left through the bottom
+    pub scattered: bool,        // Had at least one interaction
+    pub ir: usize,
+    pub ia: usize,
+    pub weight: f64             // Weight that left the tissue
+}
+
+// Histories of the photons that left the tissue, with the
+// interactions and pathlength in each of the nl layers
+// between the ambient media.
+#[derive(Clone)]
+pub struct Histories {
+    pub exits: Vec<Exit>,
+    pub interactions: Vec<u32>,     // [exit * nl + il]
+    pub path_lengths: Vec<f64>      // [exit * nl + il]
+}
+
+impl Histories {
+    pub fn new() -> Histories {
+        Histories { exits: Vec::new(), interactions: Vec::new(), path_lengths: Vec::new() }
+    }
+
+    // interactions and path_lengths are indexed by layer, including the ambient media.
+    pub fn record(&mut self, exit: Exit, interactions: &[u32], path_lengths: &[f64]) {
+        let nl = interactions.len() - 2;
+        self.exits.push(exit);
+        self.interactions.extend_from_slice(&interactions[1..=nl]);
+        self.path_lengths.extend_from_slice(&path_lengths[1..=nl]);
+    }
+
+    pub fn append(&mut self, other: &Histories) {
+        self.exits.extend_from_slice(&other.exits);
+        self.interactions.extend_from_slice(&other.interactions);
+        self.path_lengths.extend_from_slice(&other.path_lengths);
+    }
+}
+
+impl Default for Histories {
+    fn default() -> Histories {
+        Histories::new()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PerturbationError {
+    NoHistories,
+    LayerCount { expected: usize, got: usize },
+    InvalidAbsorption { layer: usize, mua: f64 },
+    InvalidScattering { layer: usize, mus: f64 },
+    ScatteringFromZero { layer: usize }
+}
+
+impl fmt::Display for PerturbationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PerturbationError::NoHistories => write!(f, "photon histories were not recorded, enable them before initializing"),
+            PerturbationError::LayerCount { expected, got } => write!(f, "expected properties for {} layers, got {}", expected, got),
+            PerturbationError::InvalidAbsorption { layer, mua } => write!(f, "layer {}: mua = {} must not be negative", layer, mua),
+            PerturbationError::InvalidScattering { layer, mus } => write!(f, "layer {}: mus = {} must not be negative", layer, mus),
+            PerturbationError::ScatteringFromZero { layer } => write!(f, "layer {}: cannot add scattering to a layer without scattering", layer)
+        }
+    }
+}
+
+impl std::error::Error for PerturbationError {}
+
+// Reflectance and transmittance for the perturbed optical properties, scaled like FinalResults.
+#[wasm_bindgen]
+pub struct PerturbedResults {
+    pub rd: f64,                // Diffuse reflectance [-]
+    pub tt: f64,                // Diffuse transmittance [-]
+    pub rd_unscattered: f64,    // Unscattered reflectance [-]
+    pub tt_unscattered: f64,    // Unscattered transmittance [-]
+
+    #[wasm_bindgen(skip)]
+    pub rd_r: Vec<f64>,         // [ir], [1/cm2]
+    #[wasm_bindgen(skip)]
+    pub rd_a: Vec<f64>,         // [ia], [sr-1]
+    #[wasm_bindgen(skip)]
+    pub tt_r: Vec<f64>,         // [ir], [1/cm2]
+    #[wasm_bindgen(skip)]
+    pub tt_a: Vec<f64>,         // [ia], [sr-1]
+    #[wasm_bindgen(skip)]
+    pub rd_ra: Vec<f64>,        // [ir * na + ia], [1/(cm2 sr)]
+    #[wasm_bindgen(skip)]
+    pub tt_ra: Vec<f64>         // [ir * na + ia], [1/(cm2 sr)]
+}
+
+#[wasm_bindgen]
+impl PerturbedResults {
+    pub fn get_rd_r(&self) -> Float64Array {
+        Float64Array::from(self.rd_r.as_slice())
+    }
+
+    pub fn get_rd_a(&self) -> Float64Array {
+        Float64Array::from(self.rd_a.as_slice())
+    }
+
+    pub fn get_tt_r(&self) -> Float64Array {
+        Float64Array::from(self.tt_r.as_slice())
+    }
+
+    pub fn get_tt_a(&self) -> Float64Array {
+        Float64Array::from(self.tt_a.as_slice())
+    }
+
+    pub fn get_rd_ra(&self) -> Float64Array {
+        Float64Array::from(self.rd_ra.as_slice())
+    }
+
+    pub fn get_tt_ra(&self) -> Float64Array {
+        Float64Array::from(self.tt_ra.as_slice())
+    }
+}
+
+// Check the new properties of the nl layers between the ambient media against the recorded ones.
+fn check_properties(run_config: &RunConfig, mua: &[f64], mus: &[f64]) -> Result<(), PerturbationError> {
+    let nl = run_config.layers.len() - 2;
+    for got in [mua.len(), mus.len()] {
+        if got != nl {
+            return Err(PerturbationError::LayerCount { expected: nl, got });
+        }
+    }
+
+    for il in 0..nl {
+        let layer = il + 1;
+        if mua[il].is_nan() || mua[il] < 0.0 {
+            return Err(PerturbationError::InvalidAbsorption { layer, mua: mua[il] });
+        }
+        if mus[il].is_nan() || mus[il] < 0.0 {
+            return Err(PerturbationError::InvalidScattering { layer, mus: mus[il] });
+        }
+        if run_config.layers[layer].mus == 0.0 && mus[il] != 0.0 {
+            return Err(PerturbationError::ScatteringFromZero { layer });
+        }
+    }
+    Ok(())
+}
+
+impl Results {
+    pub fn histories(&self) -> Option<&Histories> {
+        self.histories.as_ref()
+    }
+
+    /***********************************************************
+     * Reflectance and transmittance if the layers between the
+     * ambient media had absorption mua and scattering mus, from
+     * the recorded photon histories. The anisotropy, refractive
+     * indices and thicknesses stay the same.
+     *
+     * The further the properties are from the simulated ones,
+     * the noisier the result, since fewer histories carry most
+     * of the weight.
+     ****/
+    pub fn perturb(&self, mua: &[f64], mus: &[f64]) -> Result<PerturbedResults, PerturbationError> {
+        let histories = self.histories.as_ref().ok_or(PerturbationError::NoHistories)?;
+        let run_config = &self.run_config;
+        check_properties(run_config, mua, mus)?;
+
+        let nl = run_config.layers.len() - 2;
+        let na = run_config.na;
+        let layers = &run_config.layers[1..=nl];
+
+        let mut rd_ra = vec![0.0; run_config.nr * na];
+        let mut tt_ra = vec![0.0; run_config.nr * na];
+        let mut rd_unscattered = 0.0;
+        let mut tt_unscattered = 0.0;
+
+        for (i, exit) in histories.exits.iter().enumerate() {
+            let mut ratio = 1.0;
+            for (il, layer) in layers.iter().enumerate() {
+                let k = histories.interactions[i * nl + il];
+                let path_length = histories.path_lengths[i * nl + il];
+                if k > 0 {
+                    ratio *= (mus[il] / layer.mus).powi(k as i32);
+                }
+                ratio *= (-(mua[il] + mus[il] - layer.mua - layer.mus) * path_length).exp();
+            }
+
+            let weight = exit.weight * ratio;
+            match (exit.transmitted, exit.scattered) {
+                (false, true) => rd_ra[exit.ir * na + exit.ia] += weight,
+                (true, true) => tt_ra[exit.ir * na + exit.ia] += weight,
+                (false, false) => rd_unscattered += weight,
+                (true, false) => tt_unscattered += weight
+            }
+        }
+
+        let n_photons = self.n_photons as f64;
+        let (rd_ra, rd_r, rd_a, rd) = OutputCalc::scale_ra(run_config, &rd_ra, n_photons);
+        let (tt_ra, tt_r, tt_a, tt) = OutputCalc::scale_ra(run_config, &tt_ra, n_photons);
+
+        Ok(PerturbedResults {
+            rd,
+            tt,
+            rd_unscattered: rd_unscattered / n_photons,
+            tt_unscattered: tt_unscattered / n_photons,
+            rd_r,
+            rd_a,
+            tt_r,
+            tt_a,
+            rd_ra,
+            tt_ra
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Simulation;
+
+    fn simulate(mut run: RunConfig, n_photons: usize) -> Simulation {
+        run.record_histories = true;
+        let mut simulation = Simulation::new();
+        simulation.set_seed(1);
+        simulation.configure(run).unwrap();
+        simulation.launch_photons(n_photons);
+        simulation
+    }
+
+    fn layer_properties(run: &RunConfig) -> (Vec<f64>, Vec<f64>) {
+        let layers = &run.layers[1..run.layers.len() - 1];
+        (layers.iter().map(|l| l.mua).collect(), layers.iter().map(|l| l.mus).collect())
+    }
+
+    #[test]
+    fn unperturbed_matches_simulation() {
+        let simulation = simulate(crate::MonteCarloConfig::sample_run(), 2000);
+        let (mua, mus) = layer_properties(simulation.run_config());
+        let perturbed = simulation.results().perturb(&mua, &mus).unwrap();
+        let result = simulation.finalize(2000);
+
+        assert!((perturbed.rd - result.rd).abs() < 1E-12 * result.rd);
+        assert!((perturbed.tt - result.tt).abs() < 1E-12 * result.tt);
+        assert_eq!(perturbed.rd_unscattered, simulation.get_rd_unscattered());
+        for (a, b) in perturbed.rd_r.iter().zip(&result.rd_r) {
+            assert!((a - b).abs() <= 1E-12 * b.abs());
+        }
+    }
+
+    #[test]
+    fn absorption_change_matches_new_simulation() {
+        let run = crate::MonteCarloConfig::sample_run();
+        let simulation = simulate(run.clone(), 20000);
+        let (mut mua, mus) = layer_properties(&run);
+        mua[1] *= 1.5;
+        let perturbed = simulation.results().perturb(&mua, &mus).unwrap();
+
+        let mut changed = run;
+        changed.layers[2].mua = mua[1];
+        let expected = simulate(changed, 20000).finalize(20000);
+        assert!((perturbed.rd - expected.rd).abs() < 0.03 * expected.rd, "{} vs {}", perturbed.rd, expected.rd);
+    }
+
+    #[test]
+    fn rejects_invalid_properties() {
+        let mut simulation = Simulation::new();
+        simulation.configure(crate::MonteCarloConfig::sample_run()).unwrap();
+        let (mua, mus) = layer_properties(simulation.run_config());
+        assert_eq!(simulation.results().perturb(&mua, &mus).err(), Some(PerturbationError::NoHistories));
+
+        let simulation = simulate(crate::MonteCarloConfig::sample_run(), 10);
+        let results = simulation.results();
+        assert_eq!(results.perturb(&mua[..2], &mus).err(), Some(PerturbationError::LayerCount { expected: 3, got: 2 }));
+        let mut negative = mua.clone();
+        negative[0] = -1.0;
+        assert_eq!(results.perturb(&negative, &mus).err(), Some(PerturbationError::InvalidAbsorption { layer: 1, mua: -1.0 }));
+        let mut negative = mus.clone();
+        negative[2] = f64::NAN;
+        assert!(matches!(results.perturb(&mua, &negative), Err(PerturbationError::InvalidScattering { layer: 3, .. })));
+    }
+}
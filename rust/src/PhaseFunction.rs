@@ -0,0 +1,345 @@
+// Phase functions of the layers other than Henyey-Greenstein
+//
+// A layer without a phase function scatters by Henyey-Greenstein with its g, as
+// in MCML. The others are sampled for the cosine mu of the deflection angle from
+// their own inverse cumulative distribution, and set the layer's g to their mean
+// cosine. Tabulated phase functions are linear in mu between the given points,
+// which the sampling follows exactly.
+
+use std::fmt;
+
+use wasm_bindgen::prelude::*;
+
+use crate::Mie;
+
+// Kinds of phase function for Simulation.add_layer_phase.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PhaseKind {
+    HenyeyGreenstein,
+    ModifiedHenyeyGreenstein,
+    TwoTermHenyeyGreenstein,
+    Gegenbauer,
+    Rayleigh,
+    Mie
+}
+
+impl PhaseKind {
+    // Number of parameters the kind takes in add_layer_phase.
+    fn parameter_count(self) -> usize {
+        match self {
+            PhaseKind::HenyeyGreenstein => 1,
+            PhaseKind::ModifiedHenyeyGreenstein => 2,
+            PhaseKind::TwoTermHenyeyGreenstein => 3,
+            PhaseKind::Gegenbauer => 2,
+            PhaseKind::Rayleigh => 0,
+            PhaseKind::Mie => 3
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PhaseError {
+    ParameterCount { kind: PhaseKind, expected: usize, got: usize },
+    LengthMismatch(usize, usize),
+    TooFewPoints(usize),
+    InvalidCosines,
+    InvalidValue(usize),
+    Zero,
+    InvalidSphere
+}
+
+impl fmt::Display for PhaseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PhaseError::ParameterCount { kind, expected, got } => write!(f, "{:?} takes {} parameters, got {}", kind, expected, got),
+            PhaseError::LengthMismatch(mu, p) => write!(f, "{} cosines were given for {} phase function values", mu, p),
+            PhaseError::TooFewPoints(count) => write!(f, "a tabulated phase function needs at least 2 points, got {}", count),
+            PhaseError::InvalidCosines => write!(f, "the cosines must increase within [-1, 1]"),
+            PhaseError::InvalidValue(i) => write!(f, "phase function value {} must be finite and not negative", i),
+            PhaseError::Zero => write!(f, "the phase function must not be zero everywhere"),
+            PhaseError::InvalidSphere => write!(f, "the radius, wavelength and refractive indices must be positive")
+        }
+    }
+}
+
+impl std::error::Error for PhaseError {}
+
+// P(mu) at increasing cosines, with its cumulative integral.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PhaseTable {
+    mu: Vec<f64>,
+    p: Vec<f64>,
+    cdf: Vec<f64>
+}
+
+impl PhaseTable {
+    /***********************************************************
+     * Tabulate P(mu), given at cosines mu that increase within
+     * [-1, 1]. P is linear between the points and zero beyond
+     * them, and does not need to be normalized.
+     ****/
+    pub fn new(mu: &[f64], p: &[f64]) -> Result<PhaseTable, PhaseError> {
+        if mu.len() != p.len() {
+            return Err(PhaseError::LengthMismatch(mu.len(), p.len()));
+        }
+        if mu.len() < 2 {
+            return Err(PhaseError::TooFewPoints(mu.len()));
+        }
+        if mu.iter().any(|m| !(-1.0..=1.0).contains(m)) || mu.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(PhaseError::InvalidCosines);
+        }
+        if let Some(i) = p.iter().position(|&v| !v.is_finite() || v < 0.0) {
+            return Err(PhaseError::InvalidValue(i));
+        }
+
+        let mut cdf = vec![0.0; mu.len()];
+        for i in 1..mu.len() {
+            cdf[i] = cdf[i - 1] + (p[i - 1] + p[i]) / 2.0 * (mu[i] - mu[i - 1]);
+        }
+        if cdf[mu.len() - 1] <= 0.0 {
+            return Err(PhaseError::Zero);
+        }
+        Ok(PhaseTable { mu: mu.to_vec(), p: p.to_vec(), cdf })
+    }
+
+    pub fn mu(&self) -> &[f64] {
+        &self.mu
+    }
+
+    pub fn p(&self) -> &[f64] {
+        &self.p
+    }
+
+    fn mean_cosine(&self) -> f64 {
+        let mut sum = 0.0;
+        for i in 1..self.mu.len() {
+            let (m0, m1, p0, p1) = (self.mu[i - 1], self.mu[i], self.p[i - 1], self.p[i]);
+            sum += (m1 - m0) / 6.0 * (2.0 * m0 * p0 + m0 * p1 + m1 * p0 + 2.0 * m1 * p1);
+        }
+        sum / self.cdf[self.cdf.len() - 1]
+    }
+
+    // Invert the cumulative integral, which is quadratic in mu between the points.
+    fn sample(&self, random: f64) -> f64 {
+        let n = self.mu.len();
+        let target = random * self.cdf[n - 1];
+        let i = self.cdf.partition_point(|&c| c < target).clamp(1, n - 1);
+        let x = target - self.cdf[i - 1];
+        let p0 = self.p[i - 1];
+        let slope = (self.p[i] - p0) / (self.mu[i] - self.mu[i - 1]);
+        let denominator = p0 + (p0 * p0 + 2.0 * slope * x).max(0.0).sqrt();
+        let dmu = if denominator > 0.0 { 2.0 * x / denominator } else { 0.0 };
+        (self.mu[i - 1] + dmu).min(self.mu[i])
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PhaseFunction {
+    ModifiedHenyeyGreenstein { g: f64, beta: f64 },     // beta HG(g) + (1 - beta) 3/(4 pi) mu^2
+    TwoTermHenyeyGreenstein { g1: f64, g2: f64, f: f64 },   // f HG(g1) + (1 - f) HG(g2)
+    Gegenbauer { g: f64, alpha: f64 },                  // (1 + g^2 - 2 g mu)^-(alpha + 1), HG for alpha = 1/2
+    Rayleigh,                                           // 1 + mu^2
+    Tabulated(PhaseTable)
+}
+
+/***********************************************************
+ *  Choose (sample) a new theta angle for photon propagation
+ *  according to the anisotropy.
+ *
+ *  If anisotropy g is 0, then
+ *      cos(theta) = 2*rand-1.
+ *  otherwise
+ *  sample according to the Henyey-Greenstein function.
+ *
+ *  Returns the cosine of the polar deflection angle theta.
+ ****/
+pub fn henyey_greenstein(g: f64, random: f64) -> f64 {
+    if g == 0.0 {
+        2.0 * random - 1.0
+    } else {
+        let temp = (1.0 - g * g) / (1.0 - g + 2.0 * g * random);
+        ((1.0 + g * g - temp * temp) / (2.0 * g)).clamp(-1.0, 1.0)
+    }
+}
+
+// Integral of t^k from a to b.
+fn power_integral(a: f64, b: f64, k: f64) -> f64 {
+    if (k + 1.0).abs() < 1E-12 {
+        (b / a).ln()
+    } else {
+        (b.powf(k + 1.0) - a.powf(k + 1.0)) / (k + 1.0)
+    }
+}
+
+impl PhaseFunction {
+    /***********************************************************
+     * Phase function of spheres of radius and vacuum wavelength
+     * in um, with refractive index n_sphere, in a medium with
+     * refractive index n_medium, tabulated from Mie theory.
+     ****/
+    pub fn mie(radius: f64, wavelength: f64, n_sphere: f64, n_medium: f64) -> Result<PhaseFunction, PhaseError> {
+        // The volume fraction does not change the phase function.
+        let spheres = Mie::Spheres::new(wavelength, n_sphere, 0.0, 0.0, radius);
+        let result = spheres.calculate(n_medium).map_err(|_| PhaseError::InvalidSphere)?;
+        Ok(PhaseFunction::Tabulated(PhaseTable::new(&result.mu, &result.p)?))
+    }
+
+    /***********************************************************
+     * Phase function of the given kind from its parameters, in
+     * the order of the fields, or [radius, wavelength, n_sphere]
+     * for Mie spheres in a medium with index n_medium. Returns
+     * None with the layer's g for Henyey-Greenstein.
+     ****/
+    pub fn from_parameters(kind: PhaseKind, parameters: &[f64], n_medium: f64) -> Result<(Option<PhaseFunction>, f64), PhaseError> {
+        let expected = kind.parameter_count();
+        if parameters.len() != expected {
+            return Err(PhaseError::ParameterCount { kind, expected, got: parameters.len() });
+        }
+
+        let p = parameters;
+        let phase = match kind {
+            PhaseKind::HenyeyGreenstein => return Ok((None, p[0])),
+            PhaseKind::ModifiedHenyeyGreenstein => PhaseFunction::ModifiedHenyeyGreenstein { g: p[0], beta: p[1] },
+            PhaseKind::TwoTermHenyeyGreenstein => PhaseFunction::TwoTermHenyeyGreenstein { g1: p[0], g2: p[1], f: p[2] },
+            PhaseKind::Gegenbauer => PhaseFunction::Gegenbauer { g: p[0], alpha: p[1] },
+            PhaseKind::Rayleigh => PhaseFunction::Rayleigh,
+            PhaseKind::Mie => PhaseFunction::mie(p[0], p[1], p[2], n_medium)?
+        };
+        let g = phase.mean_cosine();
+        Ok((Some(phase), g))
+    }
+
+    // Why the parameters cannot be sampled, if they cannot.
+    pub fn check(&self) -> Result<(), &'static str> {
+        let anisotropy = |g: f64| g > -1.0 && g < 1.0;
+        let fraction = |f: f64| (0.0..=1.0).contains(&f);
+        match *self {
+            PhaseFunction::ModifiedHenyeyGreenstein { g, beta } if !anisotropy(g) || !fraction(beta) => Err("modified Henyey-Greenstein needs g in (-1, 1) and beta in [0, 1]"),
+            PhaseFunction::TwoTermHenyeyGreenstein { g1, g2, f } if !anisotropy(g1) || !anisotropy(g2) || !fraction(f) => Err("two-term Henyey-Greenstein needs g1 and g2 in (-1, 1) and f in [0, 1]"),
+            PhaseFunction::Gegenbauer { g, alpha } if !anisotropy(g) || alpha.is_nan() || alpha <= -0.5 => Err("the Gegenbauer kernel needs g in (-1, 1) and alpha > -1/2"),
+            _ => Ok(())
+        }
+    }
+
+    // Mean cosine of the deflection angle.
+    pub fn mean_cosine(&self) -> f64 {
+        match self {
+            PhaseFunction::ModifiedHenyeyGreenstein { g, beta } => beta * g,
+            PhaseFunction::TwoTermHenyeyGreenstein { g1, g2, f } => f * g1 + (1.0 - f) * g2,
+            PhaseFunction::Gegenbauer { g, alpha } => {
+                if *g == 0.0 {
+                    return 0.0;
+                }
+                // In t = 1 + g^2 - 2 g mu, from (1 - g)^2 to (1 + g)^2.
+                let (a, b) = ((1.0 - g) * (1.0 - g), (1.0 + g) * (1.0 + g));
+                let mean_t = power_integral(a, b, -alpha) / power_integral(a, b, -alpha - 1.0);
+                (1.0 + g * g - mean_t) / (2.0 * g)
+            }
+            PhaseFunction::Rayleigh => 0.0,
+            PhaseFunction::Tabulated(table) => table.mean_cosine()
+        }
+    }
+
+    /***********************************************************
+     * Sample the cosine of the deflection angle, drawing the
+     * random numbers in [0, 1] it needs from random.
+     ****/
+    pub fn sample(&self, random: &mut impl FnMut() -> f64) -> f64 {
+        match self {
+            PhaseFunction::ModifiedHenyeyGreenstein { g, beta } => {
+                if random() < *beta {
+                    henyey_greenstein(*g, random())
+                } else {
+                    (2.0 * random() - 1.0).cbrt()
+                }
+            }
+            PhaseFunction::TwoTermHenyeyGreenstein { g1, g2, f } => {
+                let g = if random() < *f { *g1 } else { *g2 };
+                henyey_greenstein(g, random())
+            }
+            PhaseFunction::Gegenbauer { g, alpha } => {
+                let (g, alpha, xi) = (*g, *alpha, random());
+                if g == 0.0 {
+                    return 2.0 * xi - 1.0;
+                }
+                let (a, b) = ((1.0 - g) * (1.0 - g), (1.0 + g) * (1.0 + g));
+                let t = if alpha.abs() < 1E-12 {
+                    b * (a / b).powf(xi)
+                } else {
+                    (b.powf(-alpha) + xi * (a.powf(-alpha) - b.powf(-alpha))).powf(-1.0 / alpha)
+                };
+                ((1.0 + g * g - t) / (2.0 * g)).clamp(-1.0, 1.0)
+            }
+            PhaseFunction::Rayleigh => {
+                // Root of mu^3 + 3 mu + 2 b = 0 from the cumulative distribution.
+                let b = 2.0 - 4.0 * random();
+                let s = (b * b + 1.0).sqrt();
+                ((s - b).cbrt() - (s + b).cbrt()).clamp(-1.0, 1.0)
+            }
+            PhaseFunction::Tabulated(table) => table.sample(random())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Mean of the sampled cosines with a fixed stream of random numbers.
+    fn sampled_mean(phase: &PhaseFunction, n: usize) -> f64 {
+        let mut rng = crate::Twister::Twister::new(1);
+        let mut random = || rng.next_u32() as f64 / u32::MAX as f64;
+        (0..n).map(|_| phase.sample(&mut random)).sum::<f64>() / n as f64
+    }
+
+    #[test]
+    fn samples_have_the_mean_cosine() {
+        let phases = [
+            PhaseFunction::ModifiedHenyeyGreenstein { g: 0.8, beta: 0.7 },
+            PhaseFunction::TwoTermHenyeyGreenstein { g1: 0.9, g2: -0.3, f: 0.8 },
+            PhaseFunction::Gegenbauer { g: 0.6, alpha: 1.0 },
+            PhaseFunction::Gegenbauer { g: 0.6, alpha: 0.0 },
+            PhaseFunction::Gegenbauer { g: 0.6, alpha: 0.5 },
+            PhaseFunction::Rayleigh,
+            PhaseFunction::Tabulated(PhaseTable::new(&[-1.0, 0.0, 0.5, 1.0], &[1.0, 0.5, 2.0, 4.0]).unwrap()),
+            PhaseFunction::mie(0.5, 0.6328, 1.59, 1.33).unwrap()
+        ];
+        for phase in &phases {
+            let mean = sampled_mean(phase, 200000);
+            assert!((mean - phase.mean_cosine()).abs() < 5E-3, "{:?}: {} vs {}", phase, mean, phase.mean_cosine());
+            assert!(phase.check().is_ok());
+        }
+
+        // The Gegenbauer kernel with alpha = 1/2 is Henyey-Greenstein.
+        assert!((PhaseFunction::Gegenbauer { g: 0.6, alpha: 0.5 }.mean_cosine() - 0.6).abs() < 1E-12);
+    }
+
+    #[test]
+    fn parameters_give_the_phase_function() {
+        assert_eq!(PhaseFunction::from_parameters(PhaseKind::HenyeyGreenstein, &[0.9], 1.0), Ok((None, 0.9)));
+        assert_eq!(PhaseFunction::from_parameters(PhaseKind::Rayleigh, &[], 1.0), Ok((Some(PhaseFunction::Rayleigh), 0.0)));
+        let (phase, g) = PhaseFunction::from_parameters(PhaseKind::TwoTermHenyeyGreenstein, &[0.9, -0.5, 0.5], 1.0).unwrap();
+        assert_eq!(phase, Some(PhaseFunction::TwoTermHenyeyGreenstein { g1: 0.9, g2: -0.5, f: 0.5 }));
+        assert!((g - 0.2).abs() < 1E-12);
+
+        assert_eq!(
+            PhaseFunction::from_parameters(PhaseKind::Gegenbauer, &[0.5], 1.0),
+            Err(PhaseError::ParameterCount { kind: PhaseKind::Gegenbauer, expected: 2, got: 1 })
+        );
+        assert_eq!(PhaseFunction::from_parameters(PhaseKind::Mie, &[0.5, 0.0, 1.59], 1.33), Err(PhaseError::InvalidSphere));
+        assert!(PhaseFunction::ModifiedHenyeyGreenstein { g: 0.5, beta: 1.5 }.check().is_err());
+        assert!(PhaseFunction::TwoTermHenyeyGreenstein { g1: 1.0, g2: 0.0, f: 0.5 }.check().is_err());
+        assert!(PhaseFunction::Gegenbauer { g: 0.5, alpha: -0.5 }.check().is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_tables() {
+        assert_eq!(PhaseTable::new(&[-1.0, 1.0], &[1.0]), Err(PhaseError::LengthMismatch(2, 1)));
+        assert_eq!(PhaseTable::new(&[0.0], &[1.0]), Err(PhaseError::TooFewPoints(1)));
+        assert_eq!(PhaseTable::new(&[0.5, 0.0], &[1.0, 1.0]), Err(PhaseError::InvalidCosines));
+        assert_eq!(PhaseTable::new(&[-1.0, 1.5], &[1.0, 1.0]), Err(PhaseError::InvalidCosines));
+        assert_eq!(PhaseTable::new(&[-1.0, 1.0], &[1.0, f64::NAN]), Err(PhaseError::InvalidValue(1)));
+        assert_eq!(PhaseTable::new(&[-1.0, 1.0], &[0.0, 0.0]), Err(PhaseError::Zero));
+    }
+}
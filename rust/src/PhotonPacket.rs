@@ -12,14 +12,23 @@ pub struct PhotonPacket {
     pub uz: f64,
 
     pub weight: f64,
+    pub time: f64,          // Time of flight in the tissue [ps]
     pub step_size: f64,
     pub step_size_left: f64,
 
     pub layer: usize,
     pub scatters: i64,
+    pub path_lengths: Vec<f64>,     // Distance travelled in each layer
+    pub interactions: Vec<u32>,     // Number of absorption and scattering events in each layer
     pub dead: bool,
+    pub emission: bool,     // Fluorescence packet at the emission wavelength
+
+    // Polarization, only in polarized runs
+    pub stokes: [f64; 4],       // Stokes vector with I = 1
+    pub reference: [f64; 3],    // Reference axis of the Stokes vector, perpendicular to the direction
 
     // Cached layer properties
+    pub layer_n: f64,
     pub layer_mua: f64,
     pub layer_mus: f64,
     pub layer_z0: f64,
@@ -38,11 +47,18 @@ impl PhotonPacket {
             uy: 0.0,
             uz: 0.0,
             weight: 0.0,
+            time: 0.0,
             step_size: 0.0,
             step_size_left: 0.0,
             layer: 0,
             scatters: 0,
+            path_lengths: Vec::new(),
+            interactions: Vec::new(),
             dead: false,
+            emission: false,
+            stokes: [1.0, 0.0, 0.0, 0.0],
+            reference: [1.0, 0.0, 0.0],
+            layer_n: 0.0,
             layer_mua: 0.0,
             layer_mus: 0.0,
             layer_z0: 0.0,
@@ -50,4 +66,15 @@ impl PhotonPacket {
             layer_g: 0.0
         }
     }
-}
\ No newline at end of file
+    // Start a new photon in this packet, keeping the buffers allocated for the previous one.
+    pub fn reset(&mut self, n_layers: usize) {
+        let mut path_lengths = std::mem::take(&mut self.path_lengths);
+        path_lengths.clear();
+        path_lengths.resize(n_layers, 0.0);
+        let mut interactions = std::mem::take(&mut self.interactions);
+        interactions.clear();
+        interactions.resize(n_layers, 0);
+
+        *self = PhotonPacket { path_lengths, interactions, ..PhotonPacket::new() };
+    }
+}
@@ -0,0 +1,354 @@
+// Polarized photon transport with Stokes vectors
+//
+// A polarized photon carries a Stokes vector [I, Q, U, V], normalized to I = 1
+// since the weight holds the intensity, relative to a reference axis v that is
+// perpendicular to the direction u: Q = 1 is light polarized along v, U = 1 along
+// 45 degrees towards u x v, and V = 2 Im(E_v E_(u x v)*).
+//
+// Scattering follows the meridian plane method. The reference axis is rotated
+// about u by the azimuth phi into the scattering plane, and the Mie scattering
+// matrix of the layer's spheres then gives the new Stokes vector. The polar angle
+// is sampled from S11 and phi by rejection from the phase function for the
+// current polarization, so the weight does not change. At boundaries the Stokes
+// vector is expressed in the plane of incidence and the Fresnel matrices decide
+// the chance of reflection and the polarization after it.
+
+use crate::Go;
+use crate::Mie::{self, Complex};
+use crate::PhotonPacket::PhotonPacket;
+use crate::RunConfig::RunConfig;
+use crate::Simulation;
+
+// Spheres that scatter light in a layer, in the medium of the layer's refractive index.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Scatterer {
+    pub radius: f64,            // Sphere radius [um]
+    pub n_sphere: f64           // Refractive index of the spheres
+}
+
+impl Scatterer {
+    // Mie coefficients of the spheres in a medium of refractive index n_medium.
+    pub fn coefficients(&self, n_medium: f64, wavelength: f64) -> Mie::MieCoefficients {
+        let x = 2.0 * std::f64::consts::PI * n_medium * self.radius / wavelength;
+        Mie::coefficients(x, Complex::new(self.n_sphere / n_medium, 0.0))
+    }
+}
+
+// Normalized scattering matrix of a layer, with the cumulative distribution of theta.
+pub(crate) struct MuellerTable {
+    s11: Vec<f64>,
+    s12: Vec<f64>,
+    s33: Vec<f64>,
+    s34: Vec<f64>,
+    cdf: Vec<f64>
+}
+
+impl MuellerTable {
+    fn new(coefficients: &Mie::MieCoefficients) -> MuellerTable {
+        let dtheta = std::f64::consts::PI / (Mie::N_ANGLES - 1) as f64;
+        let mut table = MuellerTable {
+            s11: Vec::with_capacity(Mie::N_ANGLES),
+            s12: Vec::with_capacity(Mie::N_ANGLES),
+            s33: Vec::with_capacity(Mie::N_ANGLES),
+            s34: Vec::with_capacity(Mie::N_ANGLES),
+            cdf: vec![0.0; Mie::N_ANGLES]
+        };
+
+        for i in 0..Mie::N_ANGLES {
+            let (s1, s2) = coefficients.amplitudes((i as f64 * dtheta).cos());
+            let c = s2 * s1.conj();
+            table.s11.push((s2.norm_sqr() + s1.norm_sqr()) / 2.0);
+            table.s12.push((s2.norm_sqr() - s1.norm_sqr()) / 2.0);
+            table.s33.push(c.re);
+            table.s34.push(c.im);
+        }
+
+        // Trapezoidal integral of S11 sin(theta).
+        for i in 1..Mie::N_ANGLES {
+            let a = table.s11[i - 1] * ((i - 1) as f64 * dtheta).sin();
+            let b = table.s11[i] * (i as f64 * dtheta).sin();
+            table.cdf[i] = table.cdf[i - 1] + (a + b) / 2.0 * dtheta;
+        }
+        table
+    }
+
+    // Sample theta from S11 sin(theta), returning the table position.
+    fn sample_theta(&self, random: f64) -> f64 {
+        let target = random * self.cdf[Mie::N_ANGLES - 1];
+        let i = self.cdf.partition_point(|&c| c < target).clamp(1, Mie::N_ANGLES - 1);
+        let (c0, c1) = (self.cdf[i - 1], self.cdf[i]);
+        let fraction = if c1 > c0 { (target - c0) / (c1 - c0) } else { 0.5 };
+        (i - 1) as f64 + fraction
+    }
+
+    // Matrix elements at a table position.
+    fn elements(&self, position: f64) -> (f64, f64, f64, f64) {
+        let i = (position as usize).min(Mie::N_ANGLES - 2);
+        let f = position - i as f64;
+        let at = |v: &Vec<f64>| v[i] + f * (v[i + 1] - v[i]);
+        (at(&self.s11), at(&self.s12), at(&self.s33), at(&self.s34))
+    }
+}
+
+// Scattering matrices of the layers with spheres, indexed by layer.
+pub(crate) struct Tables {
+    layers: Vec<Option<MuellerTable>>
+}
+
+impl Tables {
+    pub fn new(run_config: &RunConfig) -> Tables {
+        let layers = run_config.layers.iter().map(|layer| {
+            layer.scatterer.map(|s| MuellerTable::new(&s.coefficients(layer.n, run_config.wavelength)))
+        }).collect();
+        Tables { layers }
+    }
+}
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn normalize(a: [f64; 3]) -> [f64; 3] {
+    let norm = dot(a, a).sqrt();
+    [a[0] / norm, a[1] / norm, a[2] / norm]
+}
+
+fn direction(photon: &PhotonPacket) -> [f64; 3] {
+    [photon.ux, photon.uy, photon.uz]
+}
+
+// Express the Stokes vector relative to the reference axis turned by phi towards u x v.
+fn rotate(stokes: &mut [f64; 4], cos_phi: f64, sin_phi: f64) {
+    let cos2 = cos_phi * cos_phi - sin_phi * sin_phi;
+    let sin2 = 2.0 * sin_phi * cos_phi;
+    let (q, u) = (stokes[1], stokes[2]);
+    stokes[1] = cos2 * q + sin2 * u;
+    stokes[2] = -sin2 * q + cos2 * u;
+}
+
+// Apply [[a, b, 0, 0], [b, a, 0, 0], [0, 0, c.re, -c.im], [0, 0, c.im, c.re]] and normalize.
+fn apply(stokes: &mut [f64; 4], a: f64, b: f64, c: Complex) {
+    let [i, q, u, v] = *stokes;
+    let i1 = a * i + b * q;
+    *stokes = [1.0, (b * i + a * q) / i1, (c.re * u - c.im * v) / i1, (c.im * u + c.re * v) / i1];
+}
+
+// Fresnel coefficients rs and rp and the complex cosine of the transmission angle.
+fn fresnel_coefficients(ni: f64, nt: f64, cos_i: f64) -> (Complex, Complex, Complex) {
+    let sin_t2 = (ni / nt) * (ni / nt) * (1.0 - cos_i * cos_i);
+    let cos_t = Complex::new(1.0 - sin_t2, 0.0).sqrt();
+    let ci = Complex::new(cos_i, 0.0);
+    let (ni_c, nt_c) = (Complex::new(ni, 0.0), Complex::new(nt, 0.0));
+    let rs = (ni_c * ci - nt_c * cos_t) / (ni_c * ci + nt_c * cos_t);
+    let rp = (nt_c * ci - ni_c * cos_t) / (nt_c * ci + ni_c * cos_t);
+    (rs, rp, cos_t)
+}
+
+// Stokes vector after transmission, both in the p and s axes of the plane of incidence.
+fn transmit(stokes: &mut [f64; 4], ni: f64, nt: f64, rs: Complex, rp: Complex) {
+    let ts = Complex::new(1.0, 0.0) + rs;
+    let tp = (Complex::new(1.0, 0.0) + rp).scale(ni / nt);
+    let (t_s, t_p) = (ts.norm_sqr(), tp.norm_sqr());
+    apply(stokes, (t_p + t_s) / 2.0, (t_p - t_s) / 2.0, tp * ts.conj());
+}
+
+/***********************************************************
+ * Start with the incident Stokes vector, given relative to
+ * the x axis seen along the incident direction in the x-z
+ * plane, after transmission into the first layer. The
+ * weight keeps the specular reflectance of unpolarized light.
+ ****/
+pub fn launch(main: &Simulation, photon: &mut PhotonPacket) {
+    let s = main.run_config.stokes;
+    photon.stokes = [1.0, s[1] / s[0], s[2] / s[0], s[3] / s[0]];
+
+    // The x axis is the p axis of the incident light, up to a sign of both axes.
+    let layers = &main.run_config.layers;
+    let (rs, rp, _) = fresnel_coefficients(layers[0].n, layers[1].n, main.run_config.alpha.to_radians().cos());
+    transmit(&mut photon.stokes, layers[0].n, layers[1].n, rs, rp);
+    photon.reference = [-photon.uz, 0.0, photon.ux];
+}
+
+/***********************************************************
+ * Scatter the photon by the Mie matrix of its layer. Theta
+ * is sampled from S11 and the azimuth phi by rejection from
+ * S11 + S12 (Q cos(2 phi) + U sin(2 phi)).
+ ****/
+pub fn spin(main: &mut Simulation, photon: &mut PhotonPacket) {
+    let random = Go::gen_rand_float(main);
+    let Some(table) = main.polarization.as_ref().and_then(|t| t.layers[photon.layer].as_ref()) else {
+        return;
+    };
+    let position = table.sample_theta(random);
+    let (s11, s12, s33, s34) = table.elements(position);
+    let theta = position * std::f64::consts::PI / (Mie::N_ANGLES - 1) as f64;
+
+    let [_, q, u, _] = photon.stokes;
+    let bound = s11 + s12.abs() * (q * q + u * u).sqrt();
+    let phi = loop {
+        let phi = 2.0 * std::f64::consts::PI * Go::gen_rand_float(main);
+        let p = s11 + s12 * (q * (2.0 * phi).cos() + u * (2.0 * phi).sin());
+        if Go::gen_rand_float(main) * bound <= p {
+            break phi;
+        }
+    };
+
+    // Reference axis into the scattering plane, then the new direction in it.
+    let dir = direction(photon);
+    let v = photon.reference;
+    let w = cross(dir, v);
+    let (sin_phi, cos_phi) = phi.sin_cos();
+    let v1 = [0, 1, 2].map(|k| v[k] * cos_phi + w[k] * sin_phi);
+    rotate(&mut photon.stokes, cos_phi, sin_phi);
+    apply(&mut photon.stokes, s11, s12, Complex::new(s33, s34));
+
+    let (sin_t, cos_t) = theta.sin_cos();
+    let dir1 = normalize([0, 1, 2].map(|k| dir[k] * cos_t + v1[k] * sin_t));
+    photon.reference = normalize([0, 1, 2].map(|k| v1[k] * cos_t - dir[k] * sin_t));
+    photon.ux = dir1[0];
+    photon.uy = dir1[1];
+    photon.uz = dir1[2];
+    photon.scatters += 1;
+}
+
+// Perpendicular to the plane of incidence, or to the current reference at normal incidence.
+fn s_axis(photon: &PhotonPacket) -> [f64; 3] {
+    let dir = direction(photon);
+    let s = [photon.uy, -photon.ux, 0.0];
+    if dot(s, s) > 1E-24 {
+        normalize(s)
+    } else {
+        cross(dir, photon.reference)
+    }
+}
+
+/***********************************************************
+ * Reflect or transmit the photon at the boundary from index
+ * ni to nt with the Fresnel matrices for its polarization.
+ * Returns true if it is transmitted, with the direction and
+ * Stokes vector updated either way. Total internal reflection
+ * follows from the complex Fresnel coefficients.
+ ****/
+pub fn fresnel(main: &mut Simulation, photon: &mut PhotonPacket, ni: f64, nt: f64) -> bool {
+    // Reference axis to the p axis in the plane of incidence.
+    let s = s_axis(photon);
+    let p = cross(s, direction(photon));
+    let (cos_psi, sin_psi) = (dot(p, photon.reference), dot(p, cross(direction(photon), photon.reference)));
+    rotate(&mut photon.stokes, cos_psi, sin_psi);
+
+    let (rs, rp, cos_t) = fresnel_coefficients(ni, nt, photon.uz.abs());
+    let (r_s, r_p) = (rs.norm_sqr(), rp.norm_sqr());
+    let q = photon.stokes[1];
+    let reflectance = ((r_p + r_s) + (r_p - r_s) * q) / 2.0;
+    let transmitted = cos_t.im == 0.0 && Go::gen_rand_float(main) > reflectance;
+
+    if transmitted {
+        transmit(&mut photon.stokes, ni, nt, rs, rp);
+        photon.ux *= ni / nt;
+        photon.uy *= ni / nt;
+        photon.uz = photon.uz.signum() * cos_t.re;
+    } else {
+        apply(&mut photon.stokes, (r_p + r_s) / 2.0, (r_p - r_s) / 2.0, rp * rs.conj());
+        photon.uz = -photon.uz;
+    }
+
+    photon.reference = normalize(cross(s, direction(photon)));
+    transmitted
+}
+
+/***********************************************************
+ * Record the Stokes vector of a photon leaving through the
+ * top in the x-y grid, relative to the x axis seen along its
+ * direction, weighted by the weight that leaves. For light
+ * going up, U = 1 is polarized between the x and -y axes.
+ ****/
+pub fn record(main: &mut Simulation, photon: &PhotonPacket, weight: f64) {
+    let run_config = &main.run_config;
+    let nx = run_config.nr * 2;
+    let dir = direction(photon);
+    let e1 = normalize([1.0 - photon.ux * photon.ux, -photon.ux * photon.uy, -photon.ux * photon.uz]);
+    let v = photon.reference;
+    let mut stokes = photon.stokes;
+    rotate(&mut stokes, dot(e1, v), dot(e1, cross(dir, v)));
+
+    let bin = |x: f64| ((x / run_config.dr).round() as i64 + run_config.nr as i64).clamp(0, nx as i64 - 1) as usize;
+    let index = (bin(photon.y) * nx + bin(photon.x)) * 4;
+    for (k, val) in stokes.iter().enumerate() {
+        main.results.stokes_xy[index + k] += weight * val;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // sample.mci with polystyrene spheres in every layer, lit by light polarized along x.
+    fn polarized_run() -> RunConfig {
+        let mut run_config = crate::MonteCarloConfig::sample_run();
+        run_config.polarized = true;
+        run_config.wavelength = 0.6328;
+        run_config.stokes = [1.0, 1.0, 0.0, 0.0];
+        for layer in &mut run_config.layers[1..4] {
+            layer.scatterer = Some(Scatterer { radius: 0.5, n_sphere: 1.59 });
+        }
+        run_config
+    }
+
+    #[test]
+    fn layer_g_is_that_of_the_spheres() {
+        let mut simulation = Simulation::new();
+        simulation.configure(polarized_run()).unwrap();
+
+        let scatterer = Scatterer { radius: 0.5, n_sphere: 1.59 };
+        let (_, _, g) = scatterer.coefficients(1.37, 0.6328).efficiencies();
+        assert!(g > 0.5 && g < 1.0);
+        assert_eq!(simulation.run_config().layers[1].g, g);
+        assert_eq!(simulation.results().run_config().layers[1].g, g);
+    }
+
+    #[test]
+    fn sampled_theta_has_mean_cosine_g() {
+        let coefficients = Scatterer { radius: 0.5, n_sphere: 1.59 }.coefficients(1.37, 0.6328);
+        let (_, _, g) = coefficients.efficiencies();
+        let table = MuellerTable::new(&coefficients);
+
+        let n = 100000;
+        let mean: f64 = (0..n).map(|i| {
+            let position = table.sample_theta((i as f64 + 0.5) / n as f64);
+            (position * std::f64::consts::PI / (Mie::N_ANGLES - 1) as f64).cos()
+        }).sum::<f64>() / n as f64;
+        assert!((mean - g).abs() < 1E-3, "{} vs {}", mean, g);
+    }
+
+    #[test]
+    fn unpolarized_fresnel_reflectance_matches_rfresnel() {
+        for cos_i in [1.0, 0.8, 0.5, 0.1] {
+            let (rs, rp, _) = fresnel_coefficients(1.0, 1.37, cos_i);
+            let (r, _) = Go::rfresnel(1.0, 1.37, cos_i);
+            assert!(((rs.norm_sqr() + rp.norm_sqr()) / 2.0 - r).abs() < 1E-12);
+        }
+    }
+
+    #[test]
+    fn polarized_run_conserves_energy() {
+        let mut simulation = Simulation::new();
+        simulation.set_seed(1);
+        simulation.configure(polarized_run()).unwrap();
+        simulation.launch_photons(2000);
+
+        let result = simulation.finalize(2000);
+        assert!((result.energy_balance() - 1.0).abs() < 0.01, "balance {}", result.energy_balance());
+
+        // I holds all the scattered reflectance, and no bin is more than fully polarized.
+        let dr = simulation.run_config().dr;
+        let total: f64 = result.stokes_xy.chunks(4).map(|s| s[0]).sum::<f64>() * dr * dr;
+        assert!((total - result.rd).abs() < 1E-9 * result.rd);
+        for s in result.stokes_xy.chunks(4) {
+            assert!((s[1] * s[1] + s[2] * s[2] + s[3] * s[3]).sqrt() <= s[0] * (1.0 + 1E-9));
+        }
+    }
+}
@@ -0,0 +1,767 @@
+// Merging of Results and their binary serialization
+//
+// Layout, all values little endian:
+//   magic "MCRS", format version (u32)
+//   nz, nr, na, nt, npath (u64), dz, dr, da, dt, t0, dl, alpha, wth, chance (f64)
+//   number of modulation frequencies (u64), then the frequencies (f64)
+//   number of detectors (u64), then their radial bins (u64)
+//   number of layers (u64), then n, mua, mus, g, d (f64) per layer
+//   per layer 1 if fluorescence follows, else 0 (u32), then mua_f, quantum_yield, mua_em, mus_em, g_em (f64)
+//   per layer 1 if a scatterer follows, else 0 (u32), then radius, n_sphere (f64)
+//   1 if polarized, else 0 (u32), wavelength and the incident Stokes vector (f64)
+//   photons launched (u64), rd_unscattered, tt_unscattered (f64)
+//   tt_ra, rd_ra, rd_x, a_rz, w_txz, rd_rt, tt_rt, rd_r_fd, rd_path, rd_path_hist, jacobian, stokes_xy as length (u64) followed by the values (f64)
+//   if a layer has fluorescence, its rd_ra and a_rz as arrays
+//   1 if second moments follow, else 0 (u32)
+//   second moments of rd, a, tt (f64) and of tt_ra, rd_ra, rd_x, a_rz as arrays
+//   1 if photon histories follow, else 0 (u32)
+//   number of exits (u64), then per exit: 1 if transmitted + 2 if scattered (u32), ir, ia (u64), weight (f64)
+//   interactions per exit and layer (u32), pathlengths per exit and layer as an array
+//   1 if white Monte Carlo exits follow, else 0 (u32)
+//   maximum pathlength in mean free paths (f64), then radii, pathlengths and weights of the exits as arrays
+
+use std::fmt;
+
+use crate::Fluorescence::{FluorescenceTallies, LayerFluorescence};
+use crate::Perturbation::{Exit, Histories};
+use crate::PhaseFunction::{PhaseError, PhaseFunction, PhaseTable};
+use crate::Polarization::Scatterer;
+use crate::Results;
+use crate::RunConfig::{ConfigError, RunConfig};
+use crate::Source::{Source, SourceKind};
+use crate::Uncertainty::Moments;
+use crate::White::{WhiteExit, WhiteExits};
+
+const MAGIC: &[u8; 4] = b"MCRS";
+const FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum MergeError {
+    GridMismatch(&'static str),
+    LayerCountMismatch(usize, usize),
+    LayerMismatch(usize),
+    UncertaintyMismatch,
+    HistoriesMismatch,
+    WhiteMismatch
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MergeError::GridMismatch(name) => write!(f, "cannot merge results with different {}", name),
+            MergeError::LayerCountMismatch(a, b) => write!(f, "cannot merge results with {} and {} layers", a, b),
+            MergeError::LayerMismatch(layer) => write!(f, "cannot merge results with different properties of layer {}", layer),
+            MergeError::UncertaintyMismatch => write!(f, "cannot merge results with and without second moments"),
+            MergeError::HistoriesMismatch => write!(f, "cannot merge results with and without photon histories"),
+            MergeError::WhiteMismatch => write!(f, "cannot merge white Monte Carlo results with other results")
+        }
+    }
+}
+
+impl std::error::Error for MergeError {}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    BadMagic,
+    UnsupportedVersion(u32),
+    UnexpectedEnd,
+    TrailingData,
+    LengthMismatch(&'static str),
+    InvalidConfig(Vec<ConfigError>),
+    InvalidText,
+    InvalidRngState,
+    InvalidFlag(u32),
+    InvalidPhaseFunction(PhaseError),
+    Incompatible(MergeError)
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::BadMagic => write!(f, "not a serialized results file"),
+            DecodeError::UnsupportedVersion(v) => write!(f, "unsupported results format version {}", v),
+            DecodeError::UnexpectedEnd => write!(f, "unexpected end of serialized results"),
+            DecodeError::TrailingData => write!(f, "unexpected data after serialized results"),
+            DecodeError::LengthMismatch(name) => write!(f, "length of {} does not match the grid", name),
+            DecodeError::InvalidText => write!(f, "invalid text in serialized data"),
+            DecodeError::InvalidRngState => write!(f, "invalid random number generator state"),
+            DecodeError::InvalidFlag(flag) => write!(f, "invalid flag {} in serialized data", flag),
+            DecodeError::InvalidPhaseFunction(e) => write!(f, "invalid phase function: {}", e),
+            DecodeError::Incompatible(e) => write!(f, "{}", e),
+            DecodeError::InvalidConfig(errors) => {
+                let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "invalid run configuration: {}", messages.join("; "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/***********************************************************
+ * Check that tallies recorded with two run configs can be
+ * added. The grid, the incident angle and the layers must
+ * agree; the file name, photon count, weight threshold and
+ * roulette chance do not change what is being estimated.
+ * Second moments, photon histories and white Monte Carlo
+ * exits must be kept by both or neither.
+ ****/
+pub fn check_compatible(a: &RunConfig, b: &RunConfig) -> Result<(), MergeError> {
+    let counts = [("nz", a.nz, b.nz), ("nr", a.nr, b.nr), ("na", a.na, b.na), ("nt", a.nt, b.nt), ("npath", a.npath, b.npath)];
+    for (name, x, y) in counts {
+        if x != y {
+            return Err(MergeError::GridMismatch(name));
+        }
+    }
+
+    let spacings = [
+        ("dz", a.dz, b.dz),
+        ("dr", a.dr, b.dr),
+        ("da", a.da, b.da),
+        ("dt", a.dt, b.dt),
+        ("t0", a.t0, b.t0),
+        ("dl", a.dl, b.dl),
+        ("incident angle", a.alpha, b.alpha)
+    ];
+    for (name, x, y) in spacings {
+        if x != y {
+            return Err(MergeError::GridMismatch(name));
+        }
+    }
+
+    if a.frequencies != b.frequencies {
+        return Err(MergeError::GridMismatch("modulation frequencies"));
+    }
+
+    if a.detectors != b.detectors {
+        return Err(MergeError::GridMismatch("detectors"));
+    }
+
+    if a.track_uncertainty != b.track_uncertainty {
+        return Err(MergeError::UncertaintyMismatch);
+    }
+
+    if a.record_histories != b.record_histories {
+        return Err(MergeError::HistoriesMismatch);
+    }
+
+    if a.source != b.source {
+        return Err(MergeError::GridMismatch("source"));
+    }
+
+    if a.polarized != b.polarized || a.wavelength != b.wavelength || a.stokes != b.stokes {
+        return Err(MergeError::GridMismatch("polarization"));
+    }
+
+    if a.white != b.white || (a.white && a.white_max_path != b.white_max_path) {
+        return Err(MergeError::WhiteMismatch);
+    }
+
+    if a.layers.len() != b.layers.len() {
+        return Err(MergeError::LayerCountMismatch(a.layers.len(), b.layers.len()));
+    }
+
+    for (i, (x, y)) in a.layers.iter().zip(&b.layers).enumerate() {
+        if x.n != y.n || x.mua != y.mua || x.mus != y.mus || x.g != y.g || x.d != y.d || x.phase != y.phase || x.fluorescence != y.fluorescence || x.scatterer != y.scatterer {
+            return Err(MergeError::LayerMismatch(i));
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) struct Writer {
+    pub bytes: Vec<u8>
+}
+
+impl Writer {
+    pub fn new() -> Writer {
+        Writer { bytes: Vec::new() }
+    }
+
+    pub fn u32(&mut self, val: u32) {
+        self.bytes.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, val: u64) {
+        self.bytes.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn f64(&mut self, val: f64) {
+        self.bytes.extend_from_slice(&val.to_le_bytes());
+    }
+
+    // Length followed by the bytes.
+    pub fn data(&mut self, data: &[u8]) {
+        self.u64(data.len() as u64);
+        self.bytes.extend_from_slice(data);
+    }
+
+    pub fn array(&mut self, values: &[f64]) {
+        self.u64(values.len() as u64);
+        for &val in values {
+            self.f64(val);
+        }
+    }
+}
+
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Reader<'a> {
+        Reader { bytes, pos: 0 }
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        self.pos == self.bytes.len()
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.bytes.len() - self.pos
+    }
+
+    pub fn take<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        let end = self.pos.checked_add(N).ok_or(DecodeError::UnexpectedEnd)?;
+        let slice = self.bytes.get(self.pos..end).ok_or(DecodeError::UnexpectedEnd)?;
+        self.pos = end;
+        Ok(slice.try_into().unwrap())
+    }
+
+    pub fn u32(&mut self) -> Result<u32, DecodeError> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, DecodeError> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    pub fn usize(&mut self) -> Result<usize, DecodeError> {
+        usize::try_from(self.u64()?).map_err(|_| DecodeError::UnexpectedEnd)
+    }
+
+    pub fn f64(&mut self) -> Result<f64, DecodeError> {
+        Ok(f64::from_le_bytes(self.take()?))
+    }
+
+    // Bytes written by Writer::data.
+    pub fn data(&mut self) -> Result<&'a [u8], DecodeError> {
+        let len = self.usize()?;
+        let end = self.pos.checked_add(len).ok_or(DecodeError::UnexpectedEnd)?;
+        let slice = self.bytes.get(self.pos..end).ok_or(DecodeError::UnexpectedEnd)?;
+        self.pos = end;
+        Ok(slice)
+    }
+
+    pub fn array(&mut self, name: &'static str, len: usize) -> Result<Vec<f64>, DecodeError> {
+        if self.usize()? != len {
+            return Err(DecodeError::LengthMismatch(name));
+        }
+        self.f64s(len)
+    }
+
+    // An array written by Writer::array whose length is not known in advance.
+    pub fn values(&mut self) -> Result<Vec<f64>, DecodeError> {
+        let len = self.usize()?;
+        self.f64s(len)
+    }
+
+    fn f64s(&mut self, len: usize) -> Result<Vec<f64>, DecodeError> {
+        // Do not trust the length for the allocation before the data is known to be there.
+        if self.remaining() / 8 < len {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        (0..len).map(|_| self.f64()).collect()
+    }
+}
+
+impl Results {
+    /***********************************************************
+     * Add the tallies of other to these results, after checking
+     * that both were recorded for the same grid and layers.
+     ****/
+    pub fn merge(&mut self, other: &Results) -> Result<(), MergeError> {
+        check_compatible(&self.run_config, &other.run_config)?;
+        self.accumulate(other);
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let c = &self.run_config;
+        let mut w = Writer::new();
+
+        w.bytes.extend_from_slice(MAGIC);
+        w.u32(FORMAT_VERSION);
+
+        for val in [c.nz, c.nr, c.na, c.nt, c.npath] {
+            w.u64(val as u64);
+        }
+        for val in [c.dz, c.dr, c.da, c.dt, c.t0, c.dl, c.alpha, c.wth, c.chance] {
+            w.f64(val);
+        }
+        w.u32(c.source.kind() as u32);
+        w.array(&c.source.parameters());
+        w.array(&c.frequencies);
+        w.u64(c.detectors.len() as u64);
+        for &ir in &c.detectors {
+            w.u64(ir as u64);
+        }
+        w.u64(c.layers.len() as u64);
+        for layer in &c.layers {
+            for val in [layer.n, layer.mua, layer.mus, layer.g, layer.d] {
+                w.f64(val);
+            }
+        }
+        for layer in &c.layers {
+            match &layer.phase {
+                None => w.u32(0),
+                Some(PhaseFunction::ModifiedHenyeyGreenstein { g, beta }) => {
+                    w.u32(1);
+                    w.f64(*g);
+                    w.f64(*beta);
+                }
+                Some(PhaseFunction::TwoTermHenyeyGreenstein { g1, g2, f }) => {
+                    w.u32(2);
+                    for val in [g1, g2, f] {
+                        w.f64(*val);
+                    }
+                }
+                Some(PhaseFunction::Gegenbauer { g, alpha }) => {
+                    w.u32(3);
+                    w.f64(*g);
+                    w.f64(*alpha);
+                }
+                Some(PhaseFunction::Rayleigh) => w.u32(4),
+                Some(PhaseFunction::Tabulated(table)) => {
+                    w.u32(5);
+                    w.array(table.mu());
+                    w.array(table.p());
+                }
+            }
+        }
+        for layer in &c.layers {
+            match &layer.fluorescence {
+                Some(f) => {
+                    w.u32(1);
+                    for val in [f.mua_f, f.quantum_yield, f.mua_em, f.mus_em, f.g_em] {
+                        w.f64(val);
+                    }
+                }
+                None => w.u32(0)
+            }
+        }
+        for layer in &c.layers {
+            match &layer.scatterer {
+                Some(s) => {
+                    w.u32(1);
+                    w.f64(s.radius);
+                    w.f64(s.n_sphere);
+                }
+                None => w.u32(0)
+            }
+        }
+        w.u32(c.polarized as u32);
+        w.f64(c.wavelength);
+        for val in c.stokes {
+            w.f64(val);
+        }
+
+        w.u64(self.n_photons as u64);
+        w.f64(self.rd_unscattered);
+        w.f64(self.tt_unscattered);
+
+        w.array(&self.tt_ra);
+        w.array(&self.rd_ra);
+        w.array(&self.rd_x);
+        w.array(&self.a_rz);
+        w.array(&self.w_txz);
+        w.array(&self.rd_rt);
+        w.array(&self.tt_rt);
+        w.array(&self.rd_r_fd);
+        w.array(&self.rd_path);
+        w.array(&self.rd_path_hist);
+        w.array(&self.jacobian);
+        w.array(&self.stokes_xy);
+        if let Some(f) = &self.fluorescence {
+            w.array(&f.rd_ra);
+            w.array(&f.a_rz);
+        }
+
+        match &self.moments {
+            Some(m) => {
+                w.u32(1);
+                for val in [m.rd, m.a, m.tt] {
+                    w.f64(val);
+                }
+                w.array(&m.tt_ra);
+                w.array(&m.rd_ra);
+                w.array(&m.rd_x);
+                w.array(&m.a_rz);
+            }
+            None => w.u32(0)
+        }
+
+        match &self.histories {
+            Some(h) => {
+                w.u32(1);
+                w.u64(h.exits.len() as u64);
+                for exit in &h.exits {
+                    w.u32(exit.transmitted as u32 + 2 * exit.scattered as u32);
+                    w.u64(exit.ir as u64);
+                    w.u64(exit.ia as u64);
+                    w.f64(exit.weight);
+                }
+                for &k in &h.interactions {
+                    w.u32(k);
+                }
+                w.array(&h.path_lengths);
+            }
+            None => w.u32(0)
+        }
+
+        match &self.white {
+            Some(white) => {
+                w.u32(1);
+                w.f64(self.run_config.white_max_path);
+                let values = |f: fn(&WhiteExit) -> f64| white.exits.iter().map(f).collect::<Vec<f64>>();
+                w.array(&values(|e| e.r));
+                w.array(&values(|e| e.path_length));
+                w.array(&values(|e| e.weight));
+            }
+            None => w.u32(0)
+        }
+
+        w.bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Results, DecodeError> {
+        let mut r = Reader::new(bytes);
+
+        if &r.take::<4>()? != MAGIC {
+            return Err(DecodeError::BadMagic);
+        }
+        let version = r.u32()?;
+        if version != FORMAT_VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+
+        let mut c = RunConfig::new();
+        c.nz = r.usize()?;
+        c.nr = r.usize()?;
+        c.na = r.usize()?;
+        c.nt = r.usize()?;
+        c.npath = r.usize()?;
+        c.dz = r.f64()?;
+        c.dr = r.f64()?;
+        c.da = r.f64()?;
+        c.dt = r.f64()?;
+        c.t0 = r.f64()?;
+        c.dl = r.f64()?;
+        c.alpha = r.f64()?;
+        c.wth = r.f64()?;
+        c.chance = r.f64()?;
+
+        let kind = match r.u32()? {
+            0 => SourceKind::Pencil,
+            1 => SourceKind::Gaussian,
+            2 => SourceKind::Flat,
+            3 => SourceKind::Rectangular,
+            4 => SourceKind::Annular,
+            5 => SourceKind::Line,
+            6 => SourceKind::Fiber,
+            7 => SourceKind::Focused,
+            8 => SourceKind::Isotropic,
+            9 => SourceKind::Lambertian,
+            flag => return Err(DecodeError::InvalidFlag(flag))
+        };
+        c.source = Source::from_parameters(kind, &r.values()?).map_err(|_| DecodeError::LengthMismatch("source parameters"))?;
+        c.frequencies = r.values()?;
+        let n_detectors = r.usize()?;
+        if r.remaining() / 8 < n_detectors {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        c.detectors = (0..n_detectors).map(|_| r.usize()).collect::<Result<_, _>>()?;
+
+        let n_layers = r.usize()?;
+        for _ in 0..n_layers {
+            let n = r.f64()?;
+            let mua = r.f64()?;
+            let mus = r.f64()?;
+            let g = r.f64()?;
+            let d = r.f64()?;
+            c.add_layer(n, mua, mus, g, d);
+        }
+        for layer in &mut c.layers {
+            layer.phase = match r.u32()? {
+                0 => None,
+                1 => Some(PhaseFunction::ModifiedHenyeyGreenstein { g: r.f64()?, beta: r.f64()? }),
+                2 => Some(PhaseFunction::TwoTermHenyeyGreenstein { g1: r.f64()?, g2: r.f64()?, f: r.f64()? }),
+                3 => Some(PhaseFunction::Gegenbauer { g: r.f64()?, alpha: r.f64()? }),
+                4 => Some(PhaseFunction::Rayleigh),
+                5 => {
+                    let mu = r.values()?;
+                    let p = r.values()?;
+                    Some(PhaseFunction::Tabulated(PhaseTable::new(&mu, &p).map_err(DecodeError::InvalidPhaseFunction)?))
+                }
+                flag => return Err(DecodeError::InvalidFlag(flag))
+            };
+        }
+        for layer in &mut c.layers {
+            layer.fluorescence = match r.u32()? {
+                0 => None,
+                1 => Some(LayerFluorescence {
+                    mua_f: r.f64()?,
+                    quantum_yield: r.f64()?,
+                    mua_em: r.f64()?,
+                    mus_em: r.f64()?,
+                    g_em: r.f64()?
+                }),
+                flag => return Err(DecodeError::InvalidFlag(flag))
+            };
+        }
+        for layer in &mut c.layers {
+            layer.scatterer = match r.u32()? {
+                0 => None,
+                1 => Some(Scatterer { radius: r.f64()?, n_sphere: r.f64()? }),
+                flag => return Err(DecodeError::InvalidFlag(flag))
+            };
+        }
+        c.polarized = match r.u32()? {
+            0 => false,
+            1 => true,
+            flag => return Err(DecodeError::InvalidFlag(flag))
+        };
+        c.wavelength = r.f64()?;
+        for val in c.stokes.iter_mut() {
+            *val = r.f64()?;
+        }
+
+        c.validate().map_err(DecodeError::InvalidConfig)?;
+        c.update_layer_boundaries();
+        c.update_cos_crit();
+
+        let n_photons = r.usize()?;
+        let rd_unscattered = r.f64()?;
+        let tt_unscattered = r.f64()?;
+
+        let len = |dims: &[usize]| dims.iter().try_fold(1usize, |acc, &d| acc.checked_mul(d));
+        let too_large = DecodeError::LengthMismatch;
+        let tt_ra = r.array("tt_ra", len(&[c.na, c.nr]).ok_or(too_large("tt_ra"))?)?;
+        let rd_ra = r.array("rd_ra", len(&[c.na, c.nr]).ok_or(too_large("rd_ra"))?)?;
+        let rd_x = r.array("rd_x", len(&[c.nr, 2]).ok_or(too_large("rd_x"))?)?;
+        let a_rz = r.array("a_rz", len(&[c.nz, c.nr]).ok_or(too_large("a_rz"))?)?;
+        let w_txz = r.array("w_txz", len(&[c.nz, c.nr, 2, c.nt]).ok_or(too_large("w_txz"))?)?;
+        let rd_rt = r.array("rd_rt", len(&[c.nr, c.nt]).ok_or(too_large("rd_rt"))?)?;
+        let tt_rt = r.array("tt_rt", len(&[c.nr, c.nt]).ok_or(too_large("tt_rt"))?)?;
+        let rd_r_fd = r.array("rd_r_fd", len(&[c.frequencies.len(), c.nr, 2]).ok_or(too_large("rd_r_fd"))?)?;
+        let nl = c.layers.len() - 2;
+        let rd_path = r.array("rd_path", len(&[c.nr, nl, c.npath.min(1)]).ok_or(too_large("rd_path"))?)?;
+        let rd_path_hist = r.array("rd_path_hist", len(&[c.nr, nl, c.npath]).ok_or(too_large("rd_path_hist"))?)?;
+        let jacobian = r.array("jacobian", len(&[c.detectors.len(), c.nr, c.nz]).ok_or(too_large("jacobian"))?)?;
+        let n_stokes = if c.polarized { len(&[c.nr, c.nr, 16]).ok_or(too_large("stokes_xy"))? } else { 0 };
+        let stokes_xy = r.array("stokes_xy", n_stokes)?;
+        let fluorescence = if c.fluorescence() {
+            Some(FluorescenceTallies {
+                rd_ra: r.array("fluorescence rd_ra", rd_ra.len())?,
+                a_rz: r.array("fluorescence a_rz", a_rz.len())?
+            })
+        } else {
+            None
+        };
+
+        let moments = match r.u32()? {
+            0 => None,
+            1 => Some(Moments {
+                rd: r.f64()?,
+                a: r.f64()?,
+                tt: r.f64()?,
+                tt_ra: r.array("tt_ra moments", tt_ra.len())?,
+                rd_ra: r.array("rd_ra moments", rd_ra.len())?,
+                rd_x: r.array("rd_x moments", rd_x.len())?,
+                a_rz: r.array("a_rz moments", a_rz.len())?
+            }),
+            flag => return Err(DecodeError::InvalidFlag(flag))
+        };
+        c.track_uncertainty = moments.is_some();
+
+        let nl = c.layers.len() - 2;
+        let histories = match r.u32()? {
+            0 => None,
+            1 => {
+                let count = r.usize()?;
+                // Every exit takes 28 bytes plus 12 per layer.
+                if r.remaining() / (28 + 12 * nl) < count {
+                    return Err(DecodeError::UnexpectedEnd);
+                }
+                let mut h = Histories::new();
+                for _ in 0..count {
+                    let flags = r.u32()?;
+                    if flags > 3 {
+                        return Err(DecodeError::InvalidFlag(flags));
+                    }
+                    let ir = r.usize()?;
+                    let ia = r.usize()?;
+                    if ir >= c.nr || ia >= c.na {
+                        return Err(DecodeError::LengthMismatch("photon exit bin"));
+                    }
+                    let weight = r.f64()?;
+                    h.exits.push(Exit { transmitted: flags & 1 != 0, scattered: flags & 2 != 0, ir, ia, weight });
+                }
+                h.interactions = (0..count * nl).map(|_| r.u32()).collect::<Result<_, _>>()?;
+                h.path_lengths = r.array("photon pathlengths", count * nl)?;
+                Some(h)
+            }
+            flag => return Err(DecodeError::InvalidFlag(flag))
+        };
+        c.record_histories = histories.is_some();
+
+        let white = match r.u32()? {
+            0 => None,
+            1 => {
+                c.white_max_path = r.f64()?;
+                let radii = r.values()?;
+                let path_lengths = r.array("white pathlengths", radii.len())?;
+                let weights = r.array("white weights", radii.len())?;
+                let exits = radii.iter().zip(&path_lengths).zip(&weights)
+                    .map(|((&r, &path_length), &weight)| WhiteExit { r, path_length, weight })
+                    .collect();
+                Some(WhiteExits { exits })
+            }
+            flag => return Err(DecodeError::InvalidFlag(flag))
+        };
+        c.white = white.is_some();
+        if c.white {
+            c.validate().map_err(DecodeError::InvalidConfig)?;
+        }
+
+        if !r.is_at_end() {
+            return Err(DecodeError::TrailingData);
+        }
+
+        Ok(Results {
+            run_config: c,
+            n_photons,
+            tt_ra,
+            rd_ra,
+            rd_x,
+            a_rz,
+            w_txz,
+            rd_rt,
+            tt_rt,
+            rd_r_fd,
+            rd_path,
+            rd_path_hist,
+            jacobian,
+            stokes_xy,
+            rd_unscattered,
+            tt_unscattered,
+            moments,
+            histories,
+            white,
+            fluorescence
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MonteCarloConfig::sample_run;
+    use crate::SimulationRunner;
+    use crate::White::white_run;
+
+    #[test]
+    fn results_round_trip() {
+        let run_config = sample_run();
+        let results = SimulationRunner::run_parallel(&run_config, 2000, 1, 2, &|_| {}).unwrap();
+        let bytes = results.to_bytes();
+
+        let decoded = Results::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.n_photons(), 2000);
+        assert_eq!(decoded.to_bytes(), bytes);
+        assert_eq!(decoded.finalize(&run_config, 2000).rd_ra, results.finalize(&run_config, 2000).rd_ra);
+
+        assert!(matches!(Results::from_bytes(&bytes[..bytes.len() - 1]), Err(DecodeError::UnexpectedEnd)));
+        assert!(matches!(Results::from_bytes(&[bytes.as_slice(), &[0]].concat()), Err(DecodeError::TrailingData)));
+        assert!(matches!(Results::from_bytes(b"MCML"), Err(DecodeError::BadMagic)));
+    }
+
+    // Every optional section.
+    fn every_section() -> RunConfig {
+        let mut run_config = sample_run();
+        run_config.track_uncertainty = true;
+        run_config.frequencies = vec![0.1, 0.2];
+        run_config.npath = 4;
+        run_config.dl = 0.1;
+        run_config.record_histories = true;
+        run_config.detectors = vec![2, 10];
+        run_config.source = Source::Gaussian { radius: 0.05 };
+        run_config.polarized = true;
+        run_config.wavelength = 0.6328;
+        for layer in &mut run_config.layers[1..4] {
+            layer.fluorescence = Some(LayerFluorescence { mua_f: 0.5, quantum_yield: 0.8, mua_em: 0.5, mus_em: 50.0, g_em: 0.8 });
+            layer.scatterer = Some(Scatterer { radius: 0.5, n_sphere: 1.59 });
+        }
+        run_config.layers[2].phase = Some(PhaseFunction::TwoTermHenyeyGreenstein { g1: 0.9, g2: -0.3, f: 0.8 });
+        run_config.layers[3].phase = Some(PhaseFunction::Tabulated(PhaseTable::new(&[-1.0, 0.0, 1.0], &[1.0, 2.0, 4.0]).unwrap()));
+        run_config
+    }
+
+    #[test]
+    fn optional_sections_round_trip() {
+        for run_config in [every_section(), white_run()] {
+            let results = SimulationRunner::run_parallel(&run_config, 500, 1, 2, &|_| {}).unwrap();
+            let bytes = results.to_bytes();
+            let decoded = Results::from_bytes(&bytes).unwrap();
+            assert_eq!(decoded.to_bytes(), bytes);
+            assert!(check_compatible(results.run_config(), decoded.run_config()).is_ok());
+            assert_eq!(decoded.moments.is_some(), run_config.track_uncertainty);
+            assert_eq!(decoded.run_config().frequencies, run_config.frequencies);
+            assert_eq!(decoded.histories().map(|h| h.exits.len()), results.histories().map(|h| h.exits.len()));
+            assert_eq!(decoded.white_exits().map(|w| w.exits.len()), results.white_exits().map(|w| w.exits.len()));
+            assert_eq!(decoded.fluorescence().map(|f| f.a_rz.clone()), results.fluorescence().map(|f| f.a_rz.clone()));
+            assert_eq!(decoded.fluorescence().is_some(), run_config.fluorescence());
+            assert!(decoded.run_config().layers.iter().zip(&run_config.layers).all(|(a, b)| a.scatterer == b.scatterer && a.phase == b.phase));
+        }
+    }
+
+    #[test]
+    fn incompatible_configs_are_rejected() {
+        let a = every_section();
+        let changed = |change: &dyn Fn(&mut RunConfig)| {
+            let mut b = a.clone();
+            change(&mut b);
+            check_compatible(&a, &b)
+        };
+
+        assert!(changed(&|b| {
+            b.output_file = "other.mco".to_string();
+            b.number_of_photons += 1;
+            b.wth = 1E-3;
+            b.chance = 0.2;
+        }).is_ok());
+        assert!(matches!(changed(&|b| b.nz += 1), Err(MergeError::GridMismatch("nz"))));
+        assert!(matches!(changed(&|b| b.dr *= 2.0), Err(MergeError::GridMismatch("dr"))));
+        assert!(matches!(changed(&|b| b.npath += 1), Err(MergeError::GridMismatch("npath"))));
+        assert!(matches!(changed(&|b| b.dt *= 2.0), Err(MergeError::GridMismatch("dt"))));
+        assert!(matches!(changed(&|b| b.frequencies.push(0.3)), Err(MergeError::GridMismatch("modulation frequencies"))));
+        assert!(matches!(changed(&|b| b.stokes[1] = 0.0), Err(MergeError::GridMismatch("polarization"))));
+        assert!(matches!(changed(&|b| { b.detectors.pop(); }), Err(MergeError::GridMismatch("detectors"))));
+        assert!(matches!(changed(&|b| b.source = Source::Flat { radius: 0.05 }), Err(MergeError::GridMismatch("source"))));
+        assert!(matches!(changed(&|b| b.alpha = 10.0), Err(MergeError::GridMismatch("incident angle"))));
+        assert!(matches!(changed(&|b| b.track_uncertainty = false), Err(MergeError::UncertaintyMismatch)));
+        assert!(matches!(changed(&|b| b.record_histories = false), Err(MergeError::HistoriesMismatch)));
+        assert!(matches!(changed(&|b| b.white = true), Err(MergeError::WhiteMismatch)));
+        assert!(matches!(changed(&|b| { b.layers.pop(); }), Err(MergeError::LayerCountMismatch(5, 4))));
+        assert!(matches!(changed(&|b| b.layers[2].mus = 1.0), Err(MergeError::LayerMismatch(2))));
+        assert!(matches!(changed(&|b| b.layers[3].fluorescence = None), Err(MergeError::LayerMismatch(3))));
+        assert!(matches!(changed(&|b| b.layers[2].phase = None), Err(MergeError::LayerMismatch(2))));
+
+        let mut b = a.clone();
+        b.nt = 5;
+        let mut results = Results::new(&a);
+        assert!(matches!(results.merge(&Results::new(&b)), Err(MergeError::GridMismatch("nt"))));
+    }
+}
@@ -1,10 +1,20 @@
 // Run config struct
 
+use std::fmt;
+
+use crate::Fluorescence::LayerFluorescence;
+use crate::Mie::{MieError, MieResult, Spheres};
+use crate::PhaseFunction::{PhaseError, PhaseFunction, PhaseKind, PhaseTable};
+use crate::Polarization::Scatterer;
+use crate::Source::Source;
+use crate::White;
+
+#[derive(Clone)]
 pub struct Layer {
     pub n: f64,         // Refractive index
     pub mua: f64,       // Absorption coefficient
     pub mus: f64,       // Scattering coefficient
-    pub g: f64,         // Anisotropy
+    pub g: f64,         // Anisotropy, the mean cosine of the phase function
     pub d: f64,         // Thickness
 
     // z0 and z1 are the boundaries of the layer
@@ -13,7 +23,11 @@ pub struct Layer {
 
     // cos_crit0 and cos_crit1 are the critical angles of the layer
     pub cos_crit0: f64,
-    pub cos_crit1: f64
+    pub cos_crit1: f64,
+
+    pub phase: Option<PhaseFunction>,               // Phase function, Henyey-Greenstein with g if none
+    pub fluorescence: Option<LayerFluorescence>,   // Fluorophore and emission properties, if any
+    pub scatterer: Option<Scatterer>                // Spheres for polarized transport, if any
 }
 
 impl Layer {
@@ -27,13 +41,92 @@ impl Layer {
             z0: 0.0,
             z1: 0.0,
             cos_crit0: 0.0,
-            cos_crit1: 0.0
+            cos_crit1: 0.0,
+            phase: None,
+            fluorescence: None,
+            scatterer: None
+        }
+    }
+}
+
+// A problem found by RunConfig::validate. Layer indices count the medium above as 0.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    TooFewLayers(usize),
+    InvalidRefractiveIndex { layer: usize, n: f64 },
+    InvalidAbsorption { layer: usize, mua: f64 },
+    InvalidScattering { layer: usize, mus: f64 },
+    InvalidAnisotropy { layer: usize, g: f64 },
+    InvalidPhaseFunction { layer: usize, reason: &'static str },
+    InvalidThickness { layer: usize, d: f64 },
+    InvalidGridSpacing { name: &'static str, value: f64 },
+    InvalidGridCount { name: &'static str, value: usize },
+    InvalidStartTime(f64),
+    InvalidFrequency(f64),
+    InvalidDetector(usize),
+    InvalidChance(f64),
+    InvalidIncidentAngle(f64),
+    InvalidSource(&'static str),
+    InvalidWhiteRun(&'static str),
+    InvalidFluorescence { layer: usize, reason: &'static str },
+    InvalidPolarization(&'static str),
+    InvalidScatterer(usize)
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::TooFewLayers(count) => write!(f, "at least one layer between the media above and below is required, got {} layers in total", count),
+            ConfigError::InvalidRefractiveIndex { layer, n } => write!(f, "layer {}: refractive index n = {} must be positive", layer, n),
+            ConfigError::InvalidAbsorption { layer, mua } => write!(f, "layer {}: mua = {} must not be negative", layer, mua),
+            ConfigError::InvalidScattering { layer, mus } => write!(f, "layer {}: mus = {} must not be negative", layer, mus),
+            ConfigError::InvalidAnisotropy { layer, g } => write!(f, "layer {}: anisotropy g = {} must be in [0, 1)", layer, g),
+            ConfigError::InvalidPhaseFunction { layer, reason } => write!(f, "layer {}: {}", layer, reason),
+            ConfigError::InvalidThickness { layer, d } => write!(f, "layer {}: thickness d = {} must be positive", layer, d),
+            ConfigError::InvalidGridSpacing { name, value } => write!(f, "grid spacing {} = {} must be positive", name, value),
+            ConfigError::InvalidGridCount { name, value } => write!(f, "number of grid elements {} = {} must be positive", name, value),
+            ConfigError::InvalidStartTime(t0) => write!(f, "start time t0 = {} ps must not be negative", t0),
+            ConfigError::InvalidFrequency(frequency) => write!(f, "modulation frequency = {} MHz must be finite and not negative", frequency),
+            ConfigError::InvalidDetector(ir) => write!(f, "detector radial bin {} is outside the grid", ir),
+            ConfigError::InvalidChance(chance) => write!(f, "roulette chance = {} must be in (0, 1]", chance),
+            ConfigError::InvalidIncidentAngle(alpha) => write!(f, "incident angle = {} must be in [0, 90) degrees", alpha),
+            ConfigError::InvalidSource(reason) => write!(f, "source: {}", reason),
+            ConfigError::InvalidWhiteRun(reason) => write!(f, "white Monte Carlo: {}", reason),
+            ConfigError::InvalidFluorescence { layer, reason } => write!(f, "layer {}: fluorescence: {}", layer, reason),
+            ConfigError::InvalidPolarization(reason) => write!(f, "polarization: {}", reason),
+            ConfigError::InvalidScatterer(layer) => write!(f, "layer {}: polarized transport needs spheres with a positive radius and refractive index", layer)
         }
     }
 }
 
+impl std::error::Error for ConfigError {}
+
+// Also false for NaN.
+fn positive(value: f64) -> bool {
+    value > 0.0
+}
+
+fn non_negative(value: f64) -> bool {
+    value >= 0.0
+}
+
+// Width of the time bins unless set otherwise [ps].
+pub const DEFAULT_DT: f64 = 1.0;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Ascii,
+    Binary
+}
+
+#[derive(Clone)]
 pub struct RunConfig {
+    pub output_file: String,            // Output file name
+    pub output_format: OutputFormat,    // ASCII (A) or binary (B) output
+    pub number_of_photons: usize,       // Number of photons to launch
+
     pub alpha: f64,        // Incident angle in degrees
+    pub source: Source,    // Where photons enter, the origin for a pencil beam
     pub dz: f64,        // Step size in z
     pub dr: f64,        // Step size in r
     pub da: f64,        // Step size in angle
@@ -41,10 +134,28 @@ pub struct RunConfig {
     pub nr: usize,      // Number of steps in r
     pub na: usize,      // Number of steps in angle
     pub nt: usize,      // Number of time steps
+    pub dt: f64,        // Width of the time bins [ps]
+    pub t0: f64,        // Start of the first time bin [ps]
+
+    pub frequencies: Vec<f64>,      // Modulation frequencies for the frequency-domain reflectance [MHz]
+
+    pub dl: f64,        // Width of the pathlength bins [cm]
+    pub npath: usize,   // Number of pathlength bins, 0 for no histogram
+
+    pub detectors: Vec<usize>,      // Radial bins with an absorption sensitivity map
 
     pub wth: f64,       // Weight threshold
     pub chance: f64,    // Chance of photon survival
 
+    pub track_uncertainty: bool,    // Tally second moments for relative errors
+    pub record_histories: bool,     // Keep the photon histories for perturbation
+    pub white: bool,                // Keep the exits for white Monte Carlo scaling
+    pub white_max_path: f64,        // Mean free paths after which a photon is dropped in a white run
+
+    pub polarized: bool,            // Trace Stokes vectors with the Mie matrices of the layers' spheres
+    pub wavelength: f64,            // Vacuum wavelength for the Mie matrices [um]
+    pub stokes: [f64; 4],           // Incident Stokes vector, relative to the x axis
+
     // vec for layers
     pub layers: Vec<Layer>
 }
@@ -52,7 +163,11 @@ pub struct RunConfig {
 impl RunConfig {
     pub fn new() -> RunConfig {
         RunConfig {
+            output_file: String::new(),
+            output_format: OutputFormat::Ascii,
+            number_of_photons: 0,
             alpha: 0.0,
+            source: Source::Pencil,
             dz: 0.0,
             dr: 0.0,
             da: 0.0,
@@ -60,17 +175,258 @@ impl RunConfig {
             nr: 0,
             na: 0,
             nt: 0,
+            dt: DEFAULT_DT,
+            t0: 0.0,
+            frequencies: Vec::new(),
+            dl: 0.0,
+            npath: 0,
+            detectors: Vec::new(),
             wth: 0.0,
             chance: 0.0,
+            track_uncertainty: false,
+            record_histories: false,
+            white: false,
+            white_max_path: White::DEFAULT_MAX_PATH,
+            polarized: false,
+            wavelength: 0.0,
+            stokes: [1.0, 1.0, 0.0, 0.0],
             layers: Vec::new()
         }
     }
 
+    // Whether any layer has fluorescence.
+    pub fn fluorescence(&self) -> bool {
+        self.layers.iter().any(|layer| layer.fluorescence.is_some())
+    }
+
     pub fn add_layer(&mut self, n: f64, mua: f64, mus: f64, g: f64, d: f64) {
         let layer = Layer::new(n, mua, mus, g, d);
         self.layers.push(layer);
     }
 
+    // Add a layer with a phase function other than Henyey-Greenstein, with g its mean cosine.
+    pub fn add_layer_with_phase(&mut self, n: f64, mua: f64, mus: f64, d: f64, phase: PhaseFunction) {
+        let mut layer = Layer::new(n, mua, mus, phase.mean_cosine(), d);
+        layer.phase = Some(phase);
+        self.layers.push(layer);
+    }
+
+    /***********************************************************
+     * Add a layer with a phase function of the given kind, from
+     * its parameters as in PhaseFunction::from_parameters, with
+     * Mie spheres in the layer's refractive index n.
+     ****/
+    pub fn add_layer_phase(&mut self, n: f64, mua: f64, mus: f64, d: f64, kind: PhaseKind, parameters: &[f64]) -> Result<(), PhaseError> {
+        let (phase, g) = PhaseFunction::from_parameters(kind, parameters, n)?;
+        let mut layer = Layer::new(n, mua, mus, g, d);
+        layer.phase = phase;
+        self.layers.push(layer);
+        Ok(())
+    }
+
+    /***********************************************************
+     * Add a layer with spheres in a medium with refractive index
+     * n and absorption coefficient mua. The layer's mus and mua
+     * add those of the spheres, and it scatters by Henyey-
+     * Greenstein with their g, or by their tabulated phase
+     * function. Returns the optical properties of the spheres.
+     ****/
+    pub fn add_layer_spheres(&mut self, n: f64, mua: f64, d: f64, spheres: &Spheres, tabulated: bool) -> Result<MieResult, MieError> {
+        let result = spheres.calculate(n)?;
+        let mut layer = Layer::new(n, mua + result.mua, result.mus, result.g, d);
+        if tabulated {
+            let table = PhaseTable::new(&result.mu, &result.p).map_err(|_| MieError::InvalidDistribution("the spheres do not scatter"))?;
+            layer.phase = Some(PhaseFunction::Tabulated(table));
+        }
+        self.layers.push(layer);
+        Ok(result)
+    }
+
+    // Add a layer with the phase function P(mu) tabulated at increasing cosines mu.
+    pub fn add_layer_tabulated(&mut self, n: f64, mua: f64, mus: f64, d: f64, mu: &[f64], p: &[f64]) -> Result<(), PhaseError> {
+        let table = PhaseTable::new(mu, p)?;
+        self.add_layer_with_phase(n, mua, mus, d, PhaseFunction::Tabulated(table));
+        Ok(())
+    }
+
+    /***********************************************************
+     * Check that the run can be simulated.
+     *
+     * Returns every problem found rather than stopping at the
+     * first one, so that a user can fix an input in one go.
+     ****/
+    pub fn validate(&self) -> Result<(), Vec<ConfigError>> {
+        let mut errors = Vec::new();
+        let layers = &self.layers;
+
+        if layers.len() < 3 {
+            errors.push(ConfigError::TooFewLayers(layers.len()));
+        }
+
+        for (i, layer) in layers.iter().enumerate() {
+            if !positive(layer.n) {
+                errors.push(ConfigError::InvalidRefractiveIndex { layer: i, n: layer.n });
+            }
+
+            // The media above and below only need a refractive index.
+            if i == 0 || i == layers.len() - 1 {
+                continue;
+            }
+
+            if !non_negative(layer.mua) {
+                errors.push(ConfigError::InvalidAbsorption { layer: i, mua: layer.mua });
+            }
+            if !non_negative(layer.mus) {
+                errors.push(ConfigError::InvalidScattering { layer: i, mus: layer.mus });
+            }
+            // The g of other phase functions is their mean cosine, and spheres in polarized runs set their own.
+            let spheres = self.polarized && layer.scatterer.is_some();
+            match &layer.phase {
+                None if !spheres && !(0.0..1.0).contains(&layer.g) => errors.push(ConfigError::InvalidAnisotropy { layer: i, g: layer.g }),
+                Some(phase) => if let Err(reason) = phase.check() {
+                    errors.push(ConfigError::InvalidPhaseFunction { layer: i, reason });
+                },
+                None => {}
+            }
+            if !positive(layer.d) {
+                errors.push(ConfigError::InvalidThickness { layer: i, d: layer.d });
+            }
+        }
+
+        for (name, value) in [("dz", self.dz), ("dr", self.dr), ("da", self.da)] {
+            if !positive(value) {
+                errors.push(ConfigError::InvalidGridSpacing { name, value });
+            }
+        }
+
+        for (name, value) in [("nz", self.nz), ("nr", self.nr), ("na", self.na)] {
+            if value == 0 {
+                errors.push(ConfigError::InvalidGridCount { name, value });
+            }
+        }
+
+        // Time bins are only used with nt > 0.
+        if self.nt > 0 {
+            if !positive(self.dt) {
+                errors.push(ConfigError::InvalidGridSpacing { name: "dt", value: self.dt });
+            }
+            if !non_negative(self.t0) {
+                errors.push(ConfigError::InvalidStartTime(self.t0));
+            }
+        }
+
+        if self.npath > 0 && !positive(self.dl) {
+            errors.push(ConfigError::InvalidGridSpacing { name: "dl", value: self.dl });
+        }
+
+        for &frequency in &self.frequencies {
+            if !non_negative(frequency) || !frequency.is_finite() {
+                errors.push(ConfigError::InvalidFrequency(frequency));
+            }
+        }
+
+        for &ir in &self.detectors {
+            if ir >= self.nr {
+                errors.push(ConfigError::InvalidDetector(ir));
+            }
+        }
+
+        if !positive(self.chance) || self.chance > 1.0 {
+            errors.push(ConfigError::InvalidChance(self.chance));
+        }
+
+        if !(0.0..90.0).contains(&self.alpha) {
+            errors.push(ConfigError::InvalidIncidentAngle(self.alpha));
+        }
+
+        if let Err(reason) = self.source.check() {
+            errors.push(ConfigError::InvalidSource(reason));
+        } else if self.source.has_directions() && layers.len() >= 3 {
+            if self.alpha != 0.0 {
+                errors.push(ConfigError::InvalidSource("the incident angle must be 0 for a source with directions of its own"));
+            }
+            let thickness: f64 = layers[1..layers.len() - 1].iter().map(|layer| layer.d).sum();
+            match self.source {
+                Source::Fiber { na, .. } if na > layers[0].n => {
+                    errors.push(ConfigError::InvalidSource("the numerical aperture must not exceed the refractive index of the medium above"));
+                }
+                Source::Focused { radius, depth } if layers[1].n * radius / radius.hypot(depth) >= layers[0].n => {
+                    errors.push(ConfigError::InvalidSource("the focused beam converges more steeply than light from the medium above can"));
+                }
+                Source::Isotropic { depth } if depth >= thickness => {
+                    errors.push(ConfigError::InvalidSource("the isotropic source must be inside the layers"));
+                }
+                _ => {}
+            }
+        }
+
+        // Emission packets can reach every layer, so all of them need the emission properties.
+        if self.fluorescence() && layers.len() >= 3 {
+            for (i, layer) in layers.iter().enumerate().take(layers.len() - 1).skip(1) {
+                let Some(f) = layer.fluorescence else {
+                    errors.push(ConfigError::InvalidFluorescence { layer: i, reason: "emission properties are required in every layer" });
+                    continue;
+                };
+                if !(0.0..=layer.mua).contains(&f.mua_f) {
+                    errors.push(ConfigError::InvalidFluorescence { layer: i, reason: "mua_f must be in [0, mua]" });
+                }
+                if !(0.0..=1.0).contains(&f.quantum_yield) {
+                    errors.push(ConfigError::InvalidFluorescence { layer: i, reason: "the quantum yield must be in [0, 1]" });
+                }
+                if !non_negative(f.mua_em) || !non_negative(f.mus_em) {
+                    errors.push(ConfigError::InvalidFluorescence { layer: i, reason: "mua and mus at the emission wavelength must not be negative" });
+                }
+                if !(0.0..1.0).contains(&f.g_em) {
+                    errors.push(ConfigError::InvalidFluorescence { layer: i, reason: "g at the emission wavelength must be in [0, 1)" });
+                }
+            }
+        }
+
+        // Every scattering layer needs spheres for the Mie matrices.
+        if self.polarized {
+            if !positive(self.wavelength) {
+                errors.push(ConfigError::InvalidPolarization("the wavelength must be positive"));
+            }
+            let [i, q, u, v] = self.stokes;
+            let polarized = q * q + u * u + v * v;
+            if !positive(i) || polarized.is_nan() || polarized > i * i * (1.0 + 1E-12) {
+                errors.push(ConfigError::InvalidPolarization("the Stokes vector must have I > 0 and a degree of polarization of at most 1"));
+            }
+            // The Stokes vector is launched in the plane of incidence of the incident angle.
+            if self.source.has_directions() {
+                errors.push(ConfigError::InvalidPolarization("the source must enter at the incident angle"));
+            }
+            for (i, layer) in layers.iter().enumerate().take(layers.len().saturating_sub(1)).skip(1) {
+                let valid = |s: &Scatterer| positive(s.radius) && positive(s.n_sphere);
+                if layer.mus > 0.0 && !layer.scatterer.as_ref().is_some_and(valid) {
+                    errors.push(ConfigError::InvalidScatterer(i));
+                }
+            }
+        }
+
+        // White runs are scaled afterwards, which only works for one layer without absorption.
+        if self.white {
+            if layers.len() != 3 {
+                errors.push(ConfigError::InvalidWhiteRun("exactly one layer is required"));
+            } else if layers[1].mua != 0.0 || !positive(layers[1].mus) {
+                errors.push(ConfigError::InvalidWhiteRun("the layer must scatter and must not absorb"));
+            }
+            // Scaling the radii would also scale the beam.
+            if self.source != Source::Pencil {
+                errors.push(ConfigError::InvalidWhiteRun("the source must be a pencil beam"));
+            }
+            if !positive(self.white_max_path) {
+                errors.push(ConfigError::InvalidWhiteRun("the maximum pathlength must be positive"));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
     pub fn update_layer_boundaries(&mut self) {
         let mut z = 0.0;
         for layer in &mut self.layers {
@@ -80,6 +436,18 @@ impl RunConfig {
         }
     }
 
+    // In polarized runs the spheres of a layer scatter by their Mie matrix, so its g is theirs.
+    pub fn update_scatterer_g(&mut self) {
+        if !self.polarized {
+            return;
+        }
+        for layer in &mut self.layers {
+            if let Some(scatterer) = layer.scatterer {
+                layer.g = scatterer.coefficients(layer.n, self.wavelength).efficiencies().2;
+            }
+        }
+    }
+
     pub fn update_cos_crit(&mut self) {
         for i in 1..(self.layers.len() - 1) {
             let n2 = self.layers[i - 1].n;
@@ -101,4 +469,126 @@ impl RunConfig {
             };
         }
     }
-}
\ No newline at end of file
+}
+impl Default for RunConfig {
+    fn default() -> RunConfig {
+        RunConfig::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MonteCarloConfig::{sample_run, MonteCarloConfig};
+
+    #[test]
+    fn accepts_the_sample_runs() {
+        for body in [include_str!("../../sample.mci"), include_str!("../../template.mci")] {
+            for run in MonteCarloConfig::parse(body).unwrap().runs {
+                assert_eq!(run.validate(), Ok(()));
+            }
+        }
+    }
+
+    #[test]
+    fn reports_every_error() {
+        let mut run = sample_run();
+        run.layers[0].n = 0.0;
+        run.layers[1].mua = -1.0;
+        run.layers[1].mus = -2.0;
+        run.layers[2].g = 1.0;
+        run.layers[3].d = 0.0;
+        run.dr = -0.01;
+        run.na = 0;
+        run.dt = 0.0;
+        run.t0 = -1.0;
+        run.npath = 5;
+        run.frequencies = vec![100.0, f64::INFINITY];
+        run.detectors = vec![3, 50];
+        run.chance = 1.5;
+        run.alpha = 90.0;
+        run.source = crate::Source::Source::Annular { inner: 0.2, outer: 0.1 };
+        run.white = true;
+        run.white_max_path = 0.0;
+
+        assert_eq!(run.validate(), Err(vec![
+            ConfigError::InvalidRefractiveIndex { layer: 0, n: 0.0 },
+            ConfigError::InvalidAbsorption { layer: 1, mua: -1.0 },
+            ConfigError::InvalidScattering { layer: 1, mus: -2.0 },
+            ConfigError::InvalidAnisotropy { layer: 2, g: 1.0 },
+            ConfigError::InvalidThickness { layer: 3, d: 0.0 },
+            ConfigError::InvalidGridSpacing { name: "dr", value: -0.01 },
+            ConfigError::InvalidGridCount { name: "na", value: 0 },
+            ConfigError::InvalidGridSpacing { name: "dt", value: 0.0 },
+            ConfigError::InvalidStartTime(-1.0),
+            ConfigError::InvalidGridSpacing { name: "dl", value: 0.0 },
+            ConfigError::InvalidFrequency(f64::INFINITY),
+            ConfigError::InvalidDetector(50),
+            ConfigError::InvalidChance(1.5),
+            ConfigError::InvalidIncidentAngle(90.0),
+            ConfigError::InvalidSource("the radii must satisfy 0 <= inner < outer"),
+            ConfigError::InvalidWhiteRun("exactly one layer is required"),
+            ConfigError::InvalidWhiteRun("the source must be a pencil beam"),
+            ConfigError::InvalidWhiteRun("the maximum pathlength must be positive")
+        ]));
+
+        let mut run = sample_run();
+        run.layers[1].fluorescence = Some(crate::Fluorescence::LayerFluorescence { mua_f: 2.0, quantum_yield: 1.5, mua_em: -1.0, mus_em: 10.0, g_em: 1.0 });
+        run.layers[3].fluorescence = run.layers[1].fluorescence.map(|f| crate::Fluorescence::LayerFluorescence { mua_f: 0.0, quantum_yield: 0.5, mua_em: 1.0, g_em: 0.5, ..f });
+        assert_eq!(run.validate(), Err(vec![
+            ConfigError::InvalidFluorescence { layer: 1, reason: "mua_f must be in [0, mua]" },
+            ConfigError::InvalidFluorescence { layer: 1, reason: "the quantum yield must be in [0, 1]" },
+            ConfigError::InvalidFluorescence { layer: 1, reason: "mua and mus at the emission wavelength must not be negative" },
+            ConfigError::InvalidFluorescence { layer: 1, reason: "g at the emission wavelength must be in [0, 1)" },
+            ConfigError::InvalidFluorescence { layer: 2, reason: "emission properties are required in every layer" }
+        ]));
+
+        let mut run = sample_run();
+        run.polarized = true;
+        run.stokes = [1.0, 1.0, 1.0, 0.0];
+        run.layers[2].g = 1.0;
+        run.layers[2].scatterer = Some(crate::Polarization::Scatterer { radius: 0.5, n_sphere: 1.59 });
+        run.layers[3].scatterer = Some(crate::Polarization::Scatterer { radius: 0.0, n_sphere: 1.59 });
+        assert_eq!(run.validate(), Err(vec![
+            ConfigError::InvalidPolarization("the wavelength must be positive"),
+            ConfigError::InvalidPolarization("the Stokes vector must have I > 0 and a degree of polarization of at most 1"),
+            ConfigError::InvalidScatterer(1),
+            ConfigError::InvalidScatterer(3)
+        ]));
+
+        let mut run = sample_run();
+        run.layers[2].phase = Some(PhaseFunction::Gegenbauer { g: 0.5, alpha: -1.0 });
+        run.layers[2].g = 2.0;
+        assert_eq!(run.validate(), Err(vec![
+            ConfigError::InvalidPhaseFunction { layer: 2, reason: "the Gegenbauer kernel needs g in (-1, 1) and alpha > -1/2" }
+        ]));
+
+        let mut run = sample_run();
+        run.alpha = 10.0;
+        run.source = crate::Source::Source::Fiber { radius: 0.02, na: 1.2 };
+        run.polarized = true;
+        run.wavelength = 0.6328;
+        for layer in &mut run.layers[1..4] {
+            layer.scatterer = Some(crate::Polarization::Scatterer { radius: 0.5, n_sphere: 1.59 });
+        }
+        assert_eq!(run.validate(), Err(vec![
+            ConfigError::InvalidSource("the incident angle must be 0 for a source with directions of its own"),
+            ConfigError::InvalidSource("the numerical aperture must not exceed the refractive index of the medium above"),
+            ConfigError::InvalidPolarization("the source must enter at the incident angle")
+        ]));
+
+        let mut run = sample_run();
+        run.source = crate::Source::Source::Isotropic { depth: 10.0 };
+        assert_eq!(run.validate(), Err(vec![ConfigError::InvalidSource("the isotropic source must be inside the layers")]));
+        run.source = crate::Source::Source::Focused { radius: 1.0, depth: 0.1 };
+        assert_eq!(run.validate(), Err(vec![ConfigError::InvalidSource("the focused beam converges more steeply than light from the medium above can")]));
+
+        let mut run = crate::White::white_run();
+        run.layers[1].mua = 0.1;
+        assert_eq!(run.validate(), Err(vec![ConfigError::InvalidWhiteRun("the layer must scatter and must not absorb")]));
+
+        let mut run = sample_run();
+        run.layers.truncate(2);
+        assert_eq!(run.validate(), Err(vec![ConfigError::TooFewLayers(2)]));
+    }
+}
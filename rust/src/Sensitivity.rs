@@ -0,0 +1,111 @@
+// Absorption sensitivity of the diffuse reflectance on the r-z grid
+//
+// Raising mua by dmua in a voxel where a detected photon travelled L lowers its
+// weight by the factor exp(-dmua * L), so the derivative of the reflectance in
+// detector bin ir with respect to mua in the voxel is -sum(w * L) over the
+// photons detected there, scaled like Rd_r.
+
+use crate::PhotonPacket::PhotonPacket;
+use crate::RunConfig::RunConfig;
+use crate::Simulation;
+use crate::Uncertainty::Score;
+
+// Pathlengths of the photon being traced in each voxel [ir * nz + iz].
+pub(crate) struct VoxelPaths {
+    paths: Score
+}
+
+impl VoxelPaths {
+    pub fn new(run_config: &RunConfig) -> VoxelPaths {
+        VoxelPaths { paths: Score::new(run_config.nr * run_config.nz) }
+    }
+}
+
+/***********************************************************
+ * Add the step the photon just made to the pathlengths of
+ * the voxels it passed. Steps longer than the smaller grid
+ * spacing are split, and every part goes to the voxel at
+ * its middle.
+ ****/
+pub fn record_voxel_path(main: &mut Simulation, photon: &PhotonPacket) {
+    let run_config = &main.run_config;
+    let Some(voxel_paths) = main.voxel_paths.as_mut() else {
+        return;
+    };
+
+    let s = photon.step_size;
+    let parts = (s / run_config.dr.min(run_config.dz)).ceil().max(1.0);
+    let ds = s / parts;
+    for i in 0..parts as usize {
+        // Distance back from the end of the step to the middle of this part.
+        let back = s - (i as f64 + 0.5) * ds;
+        let xm = photon.x - back * photon.ux;
+        let ym = photon.y - back * photon.uy;
+        let zm = photon.z - back * photon.uz;
+
+        let ir = (((xm * xm + ym * ym).sqrt() / run_config.dr) as usize).min(run_config.nr - 1);
+        let iz = ((zm.max(0.0) / run_config.dz) as usize).min(run_config.nz - 1);
+        voxel_paths.paths.add(ir * run_config.nz + iz, ds);
+    }
+}
+
+/***********************************************************
+ * Add the pathlengths of a photon reflected into radial
+ * bin ir, weighted by the reflected weight, to the map of
+ * every detector at that bin.
+ ****/
+pub fn record_detected(main: &mut Simulation, ir: usize, weight: f64) {
+    let Some(voxel_paths) = main.voxel_paths.as_mut() else {
+        return;
+    };
+
+    let size = main.run_config.nr * main.run_config.nz;
+    let detectors = &main.run_config.detectors;
+    let jacobian = &mut main.results.jacobian;
+    voxel_paths.paths.drain(|index, path| {
+        for (idet, &detector) in detectors.iter().enumerate() {
+            if detector == ir {
+                jacobian[idet * size + index] += weight * path;
+            }
+        }
+    });
+}
+
+// Forget the pathlengths of the photon, called when it is dead.
+pub fn clear(main: &mut Simulation) {
+    if let Some(voxel_paths) = main.voxel_paths.as_mut() {
+        voxel_paths.paths.drain(|_, _| {});
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Simulation;
+
+    #[test]
+    fn jacobian_sums_to_mean_pathlength() {
+        let mut run_config = crate::MonteCarloConfig::sample_run();
+        run_config.npath = 1;
+        run_config.dl = 10.0;
+        run_config.detectors = vec![0, 5, 20];
+        let mut simulation = Simulation::new();
+        simulation.set_seed(2);
+        simulation.configure(run_config).unwrap();
+        simulation.launch_photons(2000);
+
+        // The grid covers the whole tissue, so every pathlength of a detected photon is in the map.
+        let result = simulation.finalize(2000);
+        let run_config = &simulation.run_config;
+        let (nr, nz) = (run_config.nr, run_config.nz);
+        let nl = run_config.layers.len() - 2;
+        for (idet, &ir) in run_config.detectors.iter().enumerate() {
+            let map = &result.jacobian[idet * nr * nz..(idet + 1) * nr * nz];
+            assert!(map.iter().all(|&val| val <= 0.0));
+
+            let path: f64 = result.rd_path_mean[ir * nl..(ir + 1) * nl].iter().sum();
+            let expected = -result.rd_r[ir] * path;
+            assert!(expected < 0.0);
+            assert!((map.iter().sum::<f64>() - expected).abs() < 1E-9 * expected.abs());
+        }
+    }
+}
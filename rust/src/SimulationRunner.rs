@@ -0,0 +1,257 @@
+// Native runner that spreads the photons of a run over several threads
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::Convergence::{Clock, ConvergenceReport, CriteriaError, StopCriteria, StopReason, TotalSums};
+use crate::Results;
+use crate::ResultsIO::{check_compatible, DecodeError, Reader, Writer};
+use crate::RunConfig::{ConfigError, RunConfig};
+use crate::Simulation;
+
+// Photons launched between progress updates in each thread.
+const PROGRESS_CHUNK: usize = 1000;
+
+// Checkpoints hold the snapshot of every thread after the number of photons it has to launch.
+const CHECKPOINT_MAGIC: &[u8; 4] = b"MCPR";
+const CHECKPOINT_VERSION: u32 = 1;
+
+/***********************************************************
+ * Derive the seed of the Mt64 stream of a thread from the
+ * master seed with SplitMix64, so that neighbouring streams
+ * are well separated and always the same for a given seed.
+ ****/
+pub fn stream_seed(seed: u64, stream: u64) -> u64 {
+    let mut z = seed.wrapping_add(stream.wrapping_add(1).wrapping_mul(0x9E3779B97F4A7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+// Number of photons launched by a thread, the remainder goes to the first threads.
+fn photons_for_thread(n_photons: usize, threads: usize, thread: usize) -> usize {
+    n_photons / threads + if thread < n_photons % threads { 1 } else { 0 }
+}
+
+/***********************************************************
+ * A run spread over several threads that can be advanced in
+ * steps and checkpointed between them.
+ *
+ * Every thread has its own simulation seeded from the master
+ * seed, and the tallies are summed in thread order, so the
+ * same seed and thread count always give identical results,
+ * whether or not the run was checkpointed and resumed.
+ ****/
+pub struct ParallelRun {
+    simulations: Vec<Simulation>,
+    targets: Vec<usize>     // Photons to launch in each thread
+}
+
+impl ParallelRun {
+    pub fn new(run_config: &RunConfig, n_photons: usize, seed: u64, threads: usize) -> Result<ParallelRun, Vec<ConfigError>> {
+        run_config.validate()?;
+
+        let threads = threads.max(1);
+        let mut simulations = Vec::with_capacity(threads);
+        for thread in 0..threads {
+            let mut simulation = Simulation::new();
+            simulation.set_seed(stream_seed(seed, thread as u64));
+            simulation.configure(run_config.clone())?;
+            simulations.push(simulation);
+        }
+        let targets = (0..threads).map(|thread| photons_for_thread(n_photons, threads, thread)).collect();
+
+        Ok(ParallelRun { simulations, targets })
+    }
+
+    // Resume a run from the bytes of checkpoint().
+    pub fn from_checkpoint(bytes: &[u8]) -> Result<ParallelRun, DecodeError> {
+        let mut r = Reader::new(bytes);
+
+        if &r.take::<4>()? != CHECKPOINT_MAGIC {
+            return Err(DecodeError::BadMagic);
+        }
+        let version = r.u32()?;
+        if version != CHECKPOINT_VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+
+        let threads = r.usize()?;
+        let mut simulations = Vec::new();
+        let mut targets = Vec::new();
+        for _ in 0..threads {
+            targets.push(r.usize()?);
+            simulations.push(Simulation::from_snapshot(r.data()?)?);
+        }
+
+        if !r.is_at_end() {
+            return Err(DecodeError::TrailingData);
+        }
+        if simulations.is_empty() {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        for simulation in &simulations[1..] {
+            check_compatible(simulations[0].run_config(), simulation.run_config())
+                .map_err(DecodeError::Incompatible)?;
+        }
+
+        Ok(ParallelRun { simulations, targets })
+    }
+
+    // Snapshot of every thread, see Checkpoint.
+    pub fn checkpoint(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+
+        w.bytes.extend_from_slice(CHECKPOINT_MAGIC);
+        w.u32(CHECKPOINT_VERSION);
+
+        w.u64(self.simulations.len() as u64);
+        for (simulation, &target) in self.simulations.iter().zip(&self.targets) {
+            w.u64(target as u64);
+            w.data(&simulation.to_snapshot());
+        }
+
+        w.bytes
+    }
+
+    pub fn run_config(&self) -> &RunConfig {
+        self.simulations[0].run_config()
+    }
+
+    pub fn threads(&self) -> usize {
+        self.simulations.len()
+    }
+
+    pub fn photons_launched(&self) -> usize {
+        self.simulations.iter().map(|s| s.results().n_photons()).sum()
+    }
+
+    pub fn photons_total(&self) -> usize {
+        self.targets.iter().sum()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.photons_launched() >= self.photons_total()
+    }
+
+    /***********************************************************
+     * Launch up to max_photons more photons in each thread and
+     * return once all threads have stopped.
+     *
+     * progress is called with the total number of photons
+     * launched so far, from any of the threads.
+     ****/
+    pub fn advance(&mut self, max_photons: usize, progress: &(dyn Fn(usize) + Sync)) {
+        let launched = AtomicUsize::new(self.photons_launched());
+
+        std::thread::scope(|scope| {
+            for (simulation, &target) in self.simulations.iter_mut().zip(&self.targets) {
+                let launched = &launched;
+                scope.spawn(move || {
+                    let mut left = target.saturating_sub(simulation.results().n_photons()).min(max_photons);
+                    while left > 0 {
+                        let photons = left.min(PROGRESS_CHUNK);
+                        simulation.launch_photons(photons);
+                        left -= photons;
+                        progress(launched.fetch_add(photons, Ordering::Relaxed) + photons);
+                    }
+                });
+            }
+        });
+    }
+
+    /***********************************************************
+     * Launch batches of photons on all threads until the stop
+     * criteria are met for the summed results, or every thread
+     * has launched its share of the photons of the run.
+     ****/
+    pub fn advance_until(&mut self, criteria: &StopCriteria, progress: &(dyn Fn(usize) + Sync)) -> Result<ConvergenceReport, CriteriaError> {
+        criteria.validate()?;
+        if criteria.has_error_target() && self.simulations[0].results().moments().is_none() {
+            return Err(CriteriaError::NoSecondMoments);
+        }
+
+        let batch = criteria.batch_size.max(1).div_ceil(self.threads());
+        let clock = Clock::start();
+        loop {
+            let mut sums = TotalSums::default();
+            for simulation in &self.simulations {
+                sums.add(&TotalSums::of(simulation.results()));
+            }
+            let errors = sums.errors();
+
+            let reason = criteria.check(sums.n_photons, clock.seconds(), &errors)
+                .or_else(|| self.is_done().then_some(StopReason::PhotonBudget));
+            if let Some(reason) = reason {
+                return Ok(ConvergenceReport::new(reason, sums.n_photons, clock.seconds(), &errors));
+            }
+
+            self.advance(batch, progress);
+        }
+    }
+
+    // Sum of the tallies of all threads, in thread order.
+    pub fn into_results(self) -> Results {
+        let mut thread_results = self.simulations.into_iter().map(|s| s.into_results());
+
+        let mut results = thread_results.next().expect("at least one thread");
+        for other in thread_results {
+            results.accumulate(&other);
+        }
+
+        results
+    }
+}
+
+/***********************************************************
+ * Launch n_photons photons of a run on the given number of
+ * threads and return the summed tallies.
+ ****/
+pub fn run_parallel(
+    run_config: &RunConfig,
+    n_photons: usize,
+    seed: u64,
+    threads: usize,
+    progress: &(dyn Fn(usize) + Sync),
+) -> Result<Results, Vec<ConfigError>> {
+    let mut run = ParallelRun::new(run_config, n_photons, seed, threads)?;
+    run.advance(usize::MAX, progress);
+    Ok(run.into_results())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MonteCarloConfig::sample_run;
+
+    #[test]
+    fn splits_photons_over_threads() {
+        let counts: Vec<usize> = (0..4).map(|thread| photons_for_thread(10, 4, thread)).collect();
+        assert_eq!(counts, vec![3, 3, 2, 2]);
+    }
+
+    #[test]
+    fn fixed_seed_is_reproducible() {
+        let run_config = sample_run();
+        let a = run_parallel(&run_config, 3000, 7, 3, &|_| {}).unwrap();
+        let b = run_parallel(&run_config, 3000, 7, 3, &|_| {}).unwrap();
+        let c = run_parallel(&run_config, 3000, 8, 3, &|_| {}).unwrap();
+
+        assert_eq!((&a.rd_ra, &a.tt_ra, &a.a_rz, &a.w_txz), (&b.rd_ra, &b.tt_ra, &b.a_rz, &b.w_txz));
+        assert_eq!((a.rd_unscattered, a.tt_unscattered), (b.rd_unscattered, b.tt_unscattered));
+        assert_ne!(a.a_rz, c.a_rz);
+    }
+
+    #[test]
+    fn resumed_checkpoint_matches_uninterrupted() {
+        let run_config = sample_run();
+        let uninterrupted = run_parallel(&run_config, 3000, 7, 3, &|_| {}).unwrap();
+
+        let mut run = ParallelRun::new(&run_config, 3000, 7, 3).unwrap();
+        run.advance(300, &|_| {});
+        let mut resumed = ParallelRun::from_checkpoint(&run.checkpoint()).unwrap();
+        assert_eq!(resumed.photons_launched(), 900);
+        resumed.advance(usize::MAX, &|_| {});
+
+        assert_eq!(resumed.into_results().to_bytes(), uninterrupted.to_bytes());
+    }
+}
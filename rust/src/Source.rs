@@ -0,0 +1,322 @@
+// Sources of the photons
+//
+// MCML launches every photon at the origin, as an infinitely narrow beam. An
+// extended source samples where each photon enters from the irradiance profile
+// of the beam on the surface, so that the tallies are the response to the whole
+// beam with the same total power, without a convolution afterwards. Photons of
+// these sources still enter in the x-z plane at the incident angle. Fibers,
+// focused beams and diffuse illumination also sample the direction, with the
+// specular reflection of each photon's own angle of incidence, and an isotropic
+// source starts photons inside the tissue. The cylindrical tallies average over
+// the azimuth around the z axis, so the shape of a source that is not round only
+// shows in the x grids.
+
+use std::fmt;
+
+use wasm_bindgen::prelude::*;
+
+// Kinds of source for Simulation.set_source.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SourceKind {
+    Pencil,
+    Gaussian,
+    Flat,
+    Rectangular,
+    Annular,
+    Line,
+    Fiber,
+    Focused,
+    Isotropic,
+    Lambertian
+}
+
+impl SourceKind {
+    // Number of parameters the kind takes in set_source.
+    fn parameter_count(self) -> usize {
+        match self {
+            SourceKind::Pencil => 0,
+            SourceKind::Gaussian => 1,
+            SourceKind::Flat => 1,
+            SourceKind::Rectangular => 2,
+            SourceKind::Annular => 2,
+            SourceKind::Line => 1,
+            SourceKind::Fiber => 2,
+            SourceKind::Focused => 2,
+            SourceKind::Isotropic => 1,
+            SourceKind::Lambertian => 1
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SourceError {
+    ParameterCount { kind: SourceKind, expected: usize, got: usize }
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SourceError::ParameterCount { kind, expected, got } => write!(f, "a {:?} source takes {} parameters, got {}", kind, expected, got)
+        }
+    }
+}
+
+impl std::error::Error for SourceError {}
+
+// Irradiance profile of the beam on the surface, centred on the origin [cm].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Source {
+    Pencil,
+    Gaussian { radius: f64 },                   // exp(-2 r^2 / radius^2), radius at 1/e^2
+    Flat { radius: f64 },                       // Uniform over a disk
+    Rectangular { width: f64, height: f64 },    // Uniform over width along x and height along y
+    Annular { inner: f64, outer: f64 },         // Uniform between two radii
+    Line { length: f64 },                       // Uniform along x
+    Fiber { radius: f64, na: f64 },             // Uniform over the core, uniform in solid angle within the numerical aperture
+    Focused { radius: f64, depth: f64 },        // Uniform over a disk, converging to depth on the z axis as refracted into the first layer
+    Isotropic { depth: f64 },                   // Point on the z axis inside the layers, emitting in all directions
+    Lambertian { radius: f64 }                  // Uniform over a disk, diffuse with a cosine distribution
+}
+
+impl Source {
+    // Source of the given kind with its parameters in the order of the fields.
+    pub fn from_parameters(kind: SourceKind, parameters: &[f64]) -> Result<Source, SourceError> {
+        let expected = kind.parameter_count();
+        if parameters.len() != expected {
+            return Err(SourceError::ParameterCount { kind, expected, got: parameters.len() });
+        }
+
+        let p = parameters;
+        Ok(match kind {
+            SourceKind::Pencil => Source::Pencil,
+            SourceKind::Gaussian => Source::Gaussian { radius: p[0] },
+            SourceKind::Flat => Source::Flat { radius: p[0] },
+            SourceKind::Rectangular => Source::Rectangular { width: p[0], height: p[1] },
+            SourceKind::Annular => Source::Annular { inner: p[0], outer: p[1] },
+            SourceKind::Line => Source::Line { length: p[0] },
+            SourceKind::Fiber => Source::Fiber { radius: p[0], na: p[1] },
+            SourceKind::Focused => Source::Focused { radius: p[0], depth: p[1] },
+            SourceKind::Isotropic => Source::Isotropic { depth: p[0] },
+            SourceKind::Lambertian => Source::Lambertian { radius: p[0] }
+        })
+    }
+
+    pub fn kind(&self) -> SourceKind {
+        match self {
+            Source::Pencil => SourceKind::Pencil,
+            Source::Gaussian { .. } => SourceKind::Gaussian,
+            Source::Flat { .. } => SourceKind::Flat,
+            Source::Rectangular { .. } => SourceKind::Rectangular,
+            Source::Annular { .. } => SourceKind::Annular,
+            Source::Line { .. } => SourceKind::Line,
+            Source::Fiber { .. } => SourceKind::Fiber,
+            Source::Focused { .. } => SourceKind::Focused,
+            Source::Isotropic { .. } => SourceKind::Isotropic,
+            Source::Lambertian { .. } => SourceKind::Lambertian
+        }
+    }
+
+    pub fn parameters(&self) -> Vec<f64> {
+        match *self {
+            Source::Pencil => vec![],
+            Source::Gaussian { radius } | Source::Flat { radius } | Source::Lambertian { radius } => vec![radius],
+            Source::Rectangular { width, height } => vec![width, height],
+            Source::Annular { inner, outer } => vec![inner, outer],
+            Source::Line { length } => vec![length],
+            Source::Fiber { radius, na } => vec![radius, na],
+            Source::Focused { radius, depth } => vec![radius, depth],
+            Source::Isotropic { depth } => vec![depth]
+        }
+    }
+
+    // Why the source cannot be sampled, if it cannot.
+    pub fn check(&self) -> Result<(), &'static str> {
+        let positive = |v: f64| v > 0.0 && v.is_finite();
+        match *self {
+            Source::Gaussian { radius } | Source::Flat { radius } | Source::Lambertian { radius } if !positive(radius) => Err("the beam radius must be positive"),
+            Source::Rectangular { width, height } if !positive(width) || !positive(height) => Err("the width and height must be positive"),
+            Source::Annular { inner, outer } if !(inner >= 0.0 && positive(outer) && inner < outer) => Err("the radii must satisfy 0 <= inner < outer"),
+            Source::Line { length } if !positive(length) => Err("the line length must be positive"),
+            Source::Fiber { radius, na } if !positive(radius) || !positive(na) => Err("the core radius and numerical aperture must be positive"),
+            Source::Focused { radius, depth } if !positive(radius) || !positive(depth) => Err("the beam radius and focal depth must be positive"),
+            Source::Isotropic { depth } if !positive(depth) => Err("the depth must be positive"),
+            _ => Ok(())
+        }
+    }
+
+    /***********************************************************
+     * Sample where a photon enters, drawing the random numbers
+     * in [0, 1] it needs from random. The pencil beam draws
+     * none, so runs with it are not changed.
+     ****/
+    pub fn position(&self, random: &mut impl FnMut() -> f64) -> (f64, f64) {
+        let pi = std::f64::consts::PI;
+        let polar = |r: f64, psi: f64| (r * psi.cos(), r * psi.sin());
+        match *self {
+            Source::Pencil => (0.0, 0.0),
+            Source::Gaussian { radius } => {
+                // 1 - random is in (0, 1], so the logarithm stays finite.
+                let r = radius * (-(1.0 - random()).ln() / 2.0).sqrt();
+                polar(r, 2.0 * pi * random())
+            }
+            Source::Flat { radius } | Source::Fiber { radius, .. } | Source::Focused { radius, .. } | Source::Lambertian { radius } => {
+                let r = radius * random().sqrt();
+                polar(r, 2.0 * pi * random())
+            }
+            Source::Rectangular { width, height } => ((random() - 0.5) * width, (random() - 0.5) * height),
+            Source::Annular { inner, outer } => {
+                let r = (inner * inner + random() * (outer * outer - inner * inner)).sqrt();
+                polar(r, 2.0 * pi * random())
+            }
+            Source::Line { length } => ((random() - 0.5) * length, 0.0),
+            Source::Isotropic { .. } => (0.0, 0.0)
+        }
+    }
+
+    // Whether photons enter in directions of their own rather than at the incident angle.
+    pub fn has_directions(&self) -> bool {
+        matches!(self, Source::Fiber { .. } | Source::Focused { .. } | Source::Isotropic { .. } | Source::Lambertian { .. })
+    }
+
+    /***********************************************************
+     * Cosine of the angle of incidence in the medium above,
+     * of index n_above, onto the first layer, of index n_below.
+     * u is uniform in [0, 1] for the fiber and the diffuse
+     * source, and the focused beam follows from the radius rho
+     * where the photon enters.
+     ****/
+    fn incident_cosine(&self, u: f64, rho: f64, n_above: f64, n_below: f64) -> f64 {
+        match *self {
+            Source::Fiber { na, .. } => {
+                let cos_max = (1.0 - (na / n_above).powi(2)).max(0.0).sqrt();
+                1.0 - u * (1.0 - cos_max)
+            }
+            Source::Focused { depth, .. } => {
+                // Snell's law backwards from the ray to the focus.
+                let sin_i = n_below / n_above * rho / rho.hypot(depth);
+                (1.0 - sin_i * sin_i).max(0.0).sqrt()
+            }
+            Source::Lambertian { .. } => u.sqrt(),
+            _ => 1.0
+        }
+    }
+
+    /***********************************************************
+     * Sample the cosine of the angle of incidence and the
+     * azimuth of the direction of a photon entering at (x, y),
+     * for the sources that enter in directions of their own.
+     * The focused beam points towards the z axis.
+     ****/
+    pub fn incidence(&self, x: f64, y: f64, n_above: f64, n_below: f64, random: &mut impl FnMut() -> f64) -> Option<(f64, f64)> {
+        let pi = std::f64::consts::PI;
+        match *self {
+            Source::Fiber { .. } | Source::Lambertian { .. } => {
+                let cos_i = self.incident_cosine(random(), 0.0, n_above, n_below);
+                Some((cos_i, 2.0 * pi * random()))
+            }
+            Source::Focused { .. } => Some((self.incident_cosine(0.0, x.hypot(y), n_above, n_below), (-y).atan2(-x))),
+            _ => None
+        }
+    }
+
+    /***********************************************************
+     * Mean of f over the cosines of the angle of incidence of
+     * the sources that enter in directions of their own, by
+     * the midpoint rule on the uniform numbers they are
+     * sampled from.
+     ****/
+    pub fn mean_over_incidence(&self, n_above: f64, n_below: f64, f: impl Fn(f64) -> f64) -> Option<f64> {
+        const STEPS: usize = 10000;
+        let radius = match *self {
+            Source::Focused { radius, .. } => radius,
+            Source::Fiber { .. } | Source::Lambertian { .. } => 0.0,
+            _ => return None
+        };
+        let sum: f64 = (0..STEPS).map(|i| {
+            let u = (i as f64 + 0.5) / STEPS as f64;
+            f(self.incident_cosine(u, radius * u.sqrt(), n_above, n_below))
+        }).sum();
+        Some(sum / STEPS as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Mean of r^2 over many sampled positions, with the largest |x| and |y|.
+    fn sample(source: Source) -> (f64, f64, f64) {
+        let mut rng = crate::Twister::Twister::new(1);
+        let mut random = || rng.next_u32() as f64 / u32::MAX as f64;
+        let n = 100000;
+        let (mut r2, mut x_max, mut y_max) = (0.0, 0.0_f64, 0.0_f64);
+        for _ in 0..n {
+            let (x, y) = source.position(&mut random);
+            r2 += x * x + y * y;
+            x_max = x_max.max(x.abs());
+            y_max = y_max.max(y.abs());
+        }
+        (r2 / n as f64, x_max, y_max)
+    }
+
+    #[test]
+    fn samples_the_beam_profiles() {
+        let close = |a: f64, b: f64| (a - b).abs() < 0.01 * b;
+
+        // Mean r^2 is radius^2 / 2 for both the Gaussian at 1/e^2 and the flat beam.
+        let (r2, _, _) = sample(Source::Gaussian { radius: 0.1 });
+        assert!(close(r2, 0.005), "Gaussian <r^2> = {}", r2);
+        let (r2, x_max, y_max) = sample(Source::Flat { radius: 0.1 });
+        assert!(close(r2, 0.005) && x_max <= 0.1 && y_max <= 0.1, "flat <r^2> = {}", r2);
+        let (r2, x_max, y_max) = sample(Source::Rectangular { width: 0.2, height: 0.1 });
+        assert!(close(r2, (0.04 + 0.01) / 12.0) && x_max <= 0.1 && y_max <= 0.05, "rectangular <r^2> = {}", r2);
+        let (r2, _, _) = sample(Source::Annular { inner: 0.1, outer: 0.2 });
+        assert!(close(r2, (0.01 + 0.04) / 2.0), "annular <r^2> = {}", r2);
+        let (r2, x_max, y_max) = sample(Source::Line { length: 0.2 });
+        assert!(close(r2, 0.04 / 12.0) && x_max <= 0.1 && y_max == 0.0, "line <r^2> = {}", r2);
+    }
+
+    #[test]
+    fn pencil_beam_draws_no_random_numbers() {
+        let mut draws = 0;
+        assert_eq!(Source::Pencil.position(&mut || { draws += 1; 0.5 }), (0.0, 0.0));
+        assert_eq!(draws, 0);
+    }
+
+    #[test]
+    fn parameters_round_trip() {
+        let source = Source::from_parameters(SourceKind::Annular, &[0.1, 0.2]).unwrap();
+        assert_eq!(source, Source::Annular { inner: 0.1, outer: 0.2 });
+        assert_eq!(Source::from_parameters(source.kind(), &source.parameters()), Ok(source));
+        assert_eq!(Source::from_parameters(SourceKind::Gaussian, &[]), Err(SourceError::ParameterCount { kind: SourceKind::Gaussian, expected: 1, got: 0 }));
+        assert!(Source::Flat { radius: 0.0 }.check().is_err() && Source::Pencil.check().is_ok());
+    }
+
+    #[test]
+    fn samples_the_incident_directions() {
+        let mut rng = crate::Twister::Twister::new(1);
+        let mut random = || rng.next_u32() as f64 / u32::MAX as f64;
+        let n = 100000;
+
+        // Cosines within the numerical aperture, and a mean cosine of 2/3 for diffuse light.
+        let fiber = Source::Fiber { radius: 0.02, na: 0.22 };
+        let cos_max = (1.0 - 0.22 * 0.22_f64).sqrt();
+        assert!((0..n).all(|_| fiber.incidence(0.0, 0.0, 1.0, 1.4, &mut random).unwrap().0 >= cos_max));
+        let lambertian = Source::Lambertian { radius: 0.1 };
+        let mean = (0..n).map(|_| lambertian.incidence(0.0, 0.0, 1.0, 1.4, &mut random).unwrap().0).sum::<f64>() / n as f64;
+        assert!((mean - 2.0 / 3.0).abs() < 0.005, "mean cosine = {}", mean);
+        let exact = lambertian.mean_over_incidence(1.0, 1.4, |cos| cos).unwrap();
+        assert!((exact - 2.0 / 3.0).abs() < 1E-6, "mean cosine = {}", exact);
+
+        // The focused beam refracts towards its focus on the z axis.
+        let focused = Source::Focused { radius: 0.1, depth: 0.2 };
+        let (cos_i, psi) = focused.incidence(0.0, 0.1, 1.0, 1.4, &mut random).unwrap();
+        let sin_t = (1.0 - cos_i * cos_i).sqrt() / 1.4;
+        assert!((sin_t - 0.1 / 0.1_f64.hypot(0.2)).abs() < 1E-12);
+        assert!((psi + std::f64::consts::FRAC_PI_2).abs() < 1E-12);
+
+        assert_eq!(Source::Gaussian { radius: 0.1 }.incidence(0.0, 0.0, 1.0, 1.4, &mut random), None);
+        assert!(!Source::Line { length: 0.1 }.has_directions() && Source::Isotropic { depth: 0.1 }.has_directions());
+    }
+}
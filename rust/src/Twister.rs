@@ -0,0 +1,124 @@
+// Mersenne Twister whose state can be saved and restored
+//
+// rand_mt does not expose the state of Mt64, but it can rebuild a generator
+// from its last 312 outputs, so those are kept in a ring buffer.
+
+use rand_mt::Mt64;
+
+// Number of outputs needed to recover the state of Mt64.
+pub const HISTORY: usize = 312;
+
+#[derive(Clone)]
+pub struct Twister {
+    mt: Mt64,
+    seed: u64,
+    draws: u64,                 // Number of outputs since seeding
+    history: [u64; HISTORY]     // Output i is stored at i % HISTORY
+}
+
+impl Twister {
+    pub fn new(seed: u64) -> Twister {
+        Twister {
+            mt: Mt64::new(seed),
+            seed,
+            draws: 0,
+            history: [0; HISTORY]
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let val = self.mt.next_u64();
+        self.history[(self.draws % HISTORY as u64) as usize] = val;
+        self.draws += 1;
+        val
+    }
+
+    // Same output as Mt64::next_u32.
+    pub fn next_u32(&mut self) -> u32 {
+        self.next_u64() as u32
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn draws(&self) -> u64 {
+        self.draws
+    }
+
+    // The last min(draws, HISTORY) outputs, oldest first.
+    pub fn history(&self) -> Vec<u64> {
+        let count = self.draws.min(HISTORY as u64);
+        (self.draws - count..self.draws).map(|i| self.history[(i % HISTORY as u64) as usize]).collect()
+    }
+
+    /***********************************************************
+     * Rebuild a generator from seed, draws and history().
+     *
+     * With a full history the state is recovered directly,
+     * otherwise the few outputs drawn since seeding are replayed.
+     * Returns None if the history does not fit the draw count.
+     ****/
+    pub fn restore(seed: u64, draws: u64, history: &[u64]) -> Option<Twister> {
+        if history.len() as u64 != draws.min(HISTORY as u64) {
+            return None;
+        }
+
+        let mut twister = Twister::new(seed);
+        if draws < HISTORY as u64 {
+            for _ in 0..draws {
+                twister.next_u64();
+            }
+            if twister.history() != history {
+                return None;
+            }
+        } else {
+            twister.mt = Mt64::recover(history.iter().copied()).ok()?;
+            twister.draws = draws;
+            for (i, &val) in (draws - HISTORY as u64..draws).zip(history) {
+                twister.history[(i % HISTORY as u64) as usize] = val;
+            }
+        }
+
+        Some(twister)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Restore after `draws` outputs and compare the next 1000 with the original generator.
+    fn continues_stream(draws: u64) {
+        let mut original = Twister::new(42);
+        for _ in 0..draws {
+            original.next_u64();
+        }
+        let mut restored = Twister::restore(original.seed(), original.draws(), &original.history()).unwrap();
+        assert_eq!(restored.draws(), draws);
+
+        for _ in 0..1000 {
+            assert_eq!(restored.next_u64(), original.next_u64());
+        }
+        assert_eq!(restored.history(), original.history());
+    }
+
+    #[test]
+    fn restore_continues_stream() {
+        for draws in [0, 1, HISTORY as u64 - 1, HISTORY as u64, 5000] {
+            continues_stream(draws);
+        }
+    }
+
+    #[test]
+    fn restore_rejects_other_history() {
+        let mut twister = Twister::new(42);
+        for _ in 0..10 {
+            twister.next_u64();
+        }
+        let mut history = twister.history();
+        assert!(Twister::restore(42, 9, &history).is_none());
+        history[3] ^= 1;
+        assert!(Twister::restore(42, 10, &history).is_none());
+    }
+}
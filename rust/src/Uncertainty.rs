@@ -0,0 +1,203 @@
+// Second moments of the tallies, for the statistical error of the results
+//
+// Each photon's contribution to a bin is summed while it travels and its square
+// is added to the bin's second moment when the photon dies. With the sum S1 and
+// the sum of squares S2 of N photons, the standard error of the mean S1 / N is
+// sqrt((S2 / N - (S1 / N)^2) / (N - 1)).
+
+use crate::RunConfig::RunConfig;
+
+// Sums of the squared contributions of each photon.
+#[derive(Clone)]
+pub struct Moments {
+    pub tt_ra: Vec<f64>,    // [ir * na + ia]
+    pub rd_ra: Vec<f64>,    // [ir * na + ia]
+    pub rd_x: Vec<f64>,     // [ix]
+    pub a_rz: Vec<f64>,     // [ir * nz + iz]
+    pub rd: f64,            // Diffuse reflectance
+    pub a: f64,             // Absorbed fraction
+    pub tt: f64             // Diffuse transmittance
+}
+
+impl Moments {
+    pub fn new(run_config: &RunConfig) -> Moments {
+        Moments {
+            tt_ra: vec![0.0; run_config.na * run_config.nr],
+            rd_ra: vec![0.0; run_config.na * run_config.nr],
+            rd_x: vec![0.0; run_config.nr * 2],
+            a_rz: vec![0.0; run_config.nz * run_config.nr],
+            rd: 0.0,
+            a: 0.0,
+            tt: 0.0
+        }
+    }
+
+    pub fn accumulate(&mut self, other: &Moments) {
+        let add = |a: &mut Vec<f64>, b: &Vec<f64>| {
+            for (x, y) in a.iter_mut().zip(b) {
+                *x += y;
+            }
+        };
+        add(&mut self.tt_ra, &other.tt_ra);
+        add(&mut self.rd_ra, &other.rd_ra);
+        add(&mut self.rd_x, &other.rd_x);
+        add(&mut self.a_rz, &other.a_rz);
+        self.rd += other.rd;
+        self.a += other.a;
+        self.tt += other.tt;
+    }
+}
+
+// Contributions of the current photon to one array, remembering which bins were hit.
+pub(crate) struct Score {
+    values: Vec<f64>,
+    touched: Vec<usize>
+}
+
+impl Score {
+    pub fn new(len: usize) -> Score {
+        Score { values: vec![0.0; len], touched: Vec::new() }
+    }
+
+    pub fn add(&mut self, index: usize, weight: f64) {
+        if weight == 0.0 {
+            return;
+        }
+        if self.values[index] == 0.0 {
+            self.touched.push(index);
+        }
+        self.values[index] += weight;
+    }
+
+    // Pass every bin that was hit with its contribution to f and clear them for the next photon.
+    pub fn drain(&mut self, mut f: impl FnMut(usize, f64)) {
+        for &index in &self.touched {
+            f(index, self.values[index]);
+            self.values[index] = 0.0;
+        }
+        self.touched.clear();
+    }
+
+    // Add the squared contributions to squares and clear them for the next photon.
+    fn flush(&mut self, squares: &mut [f64]) {
+        self.drain(|index, val| squares[index] += val * val);
+    }
+}
+
+// Contributions of the photon being traced.
+pub(crate) struct PhotonScores {
+    tt_ra: Score,
+    rd_ra: Score,
+    rd_x: Score,
+    a_rz: Score,
+    rd: f64,
+    a: f64,
+    tt: f64
+}
+
+impl PhotonScores {
+    pub fn new(run_config: &RunConfig) -> PhotonScores {
+        PhotonScores {
+            tt_ra: Score::new(run_config.na * run_config.nr),
+            rd_ra: Score::new(run_config.na * run_config.nr),
+            rd_x: Score::new(run_config.nr * 2),
+            a_rz: Score::new(run_config.nz * run_config.nr),
+            rd: 0.0,
+            a: 0.0,
+            tt: 0.0
+        }
+    }
+
+    pub fn add_rd(&mut self, ira: usize, ix: usize, weight: f64) {
+        self.rd_ra.add(ira, weight);
+        self.rd_x.add(ix, weight);
+        self.rd += weight;
+    }
+
+    pub fn add_tt(&mut self, ira: usize, weight: f64) {
+        self.tt_ra.add(ira, weight);
+        self.tt += weight;
+    }
+
+    pub fn add_a(&mut self, irz: usize, weight: f64) {
+        self.a_rz.add(irz, weight);
+        self.a += weight;
+    }
+
+    // Called when the photon is dead.
+    pub fn flush(&mut self, moments: &mut Moments) {
+        self.tt_ra.flush(&mut moments.tt_ra);
+        self.rd_ra.flush(&mut moments.rd_ra);
+        self.rd_x.flush(&mut moments.rd_x);
+        self.a_rz.flush(&mut moments.a_rz);
+        moments.rd += self.rd * self.rd;
+        moments.a += self.a * self.a;
+        moments.tt += self.tt * self.tt;
+        self.rd = 0.0;
+        self.a = 0.0;
+        self.tt = 0.0;
+    }
+}
+
+/***********************************************************
+ * Relative standard error of the mean of n photons, given
+ * the sum and the sum of squares of their contributions.
+ *
+ * NaN when it cannot be estimated, that is when fewer than
+ * two photons were launched or none reached the bin.
+ ****/
+pub fn relative_error(sum: f64, sum_sq: f64, n_photons: usize) -> f64 {
+    if n_photons < 2 || sum == 0.0 {
+        return f64::NAN;
+    }
+
+    let n = n_photons as f64;
+    let mean = sum / n;
+    let variance = (sum_sq / n - mean * mean).max(0.0);
+    (variance / (n - 1.0)).sqrt() / mean
+}
+
+pub fn relative_errors(sums: &[f64], sums_sq: &[f64], n_photons: usize) -> Vec<f64> {
+    sums.iter().zip(sums_sq).map(|(&sum, &sum_sq)| relative_error(sum, sum_sq, n_photons)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MonteCarloConfig::sample_run;
+    use crate::SimulationRunner;
+
+    #[test]
+    fn relative_error_of_known_samples() {
+        // Contributions 1, 0, 1, 0: mean 0.5, variance 0.25.
+        let expected = (0.25_f64 / 3.0).sqrt() / 0.5;
+        assert!((relative_error(2.0, 2.0, 4) - expected).abs() < 1E-12);
+        assert!(relative_error(2.0, 2.0, 1).is_nan());
+        assert!(relative_error(0.0, 0.0, 4).is_nan());
+    }
+
+    #[test]
+    fn errors_shrink_with_more_photons() {
+        let mut run_config = sample_run();
+        run_config.track_uncertainty = true;
+        let error = |n_photons| {
+            let results = SimulationRunner::run_parallel(&run_config, n_photons, 3, 2, &|_| {}).unwrap();
+            results.finalize(&run_config, n_photons)
+        };
+
+        let (small, large) = (error(1000), error(16000));
+        for (a, b) in [(small.rd_error, large.rd_error), (small.a_error, large.a_error)] {
+            assert!(a > 0.0 && b > 0.0, "errors {} {}", a, b);
+            assert!((a / b - 4.0).abs() < 1.0, "errors {} {}", a, b);
+        }
+        assert_eq!(large.a_rz_error.len(), large.a_rz.len());
+
+        // Tracking the moments does not change the tallies.
+        let mut untracked = run_config.clone();
+        untracked.track_uncertainty = false;
+        let a = SimulationRunner::run_parallel(&run_config, 500, 3, 1, &|_| {}).unwrap();
+        let b = SimulationRunner::run_parallel(&untracked, 500, 3, 1, &|_| {}).unwrap();
+        assert_eq!(a.a_rz, b.a_rz);
+        assert!(b.finalize(&untracked, 500).rd_error.is_nan());
+    }
+}
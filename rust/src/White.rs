@@ -0,0 +1,220 @@
+// White Monte Carlo: reflectance for any absorption and scattering from one run
+//
+// The run simulates a single layer without absorption, with scattering mus0.
+// For scattering mus the same random walks hold with every length multiplied by
+// s = mus0 / mus, so a photon that left at radius r after a pathlength L leaves
+// at s * r after s * L. Absorption mua then multiplies its weight by
+// exp(-mua * s * L). The layer is s times as thick, so results are exact for a
+// semi-infinite medium, or one that is thick enough for its bottom not to matter.
+
+use std::fmt;
+
+use js_sys::Float64Array;
+use wasm_bindgen::prelude::*;
+
+use crate::Go;
+use crate::Results;
+
+// Mean free paths a photon may travel before it is dropped, unless set otherwise.
+pub const DEFAULT_MAX_PATH: f64 = 1E4;
+
+// A photon that left through the top after scattering.
+#[derive(Clone)]
+pub struct WhiteExit {
+    pub r: f64,                 // Radius where it left [cm]
+    pub path_length: f64,       // Pathlength in the layer [cm]
+    pub weight: f64             // Weight that left the tissue
+}
+
+#[derive(Clone)]
+pub struct WhiteExits {
+    pub exits: Vec<WhiteExit>
+}
+
+impl WhiteExits {
+    pub fn new() -> WhiteExits {
+        WhiteExits { exits: Vec::new() }
+    }
+
+    pub fn append(&mut self, other: &WhiteExits) {
+        self.exits.extend_from_slice(&other.exits);
+    }
+}
+
+impl Default for WhiteExits {
+    fn default() -> WhiteExits {
+        WhiteExits::new()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WhiteError {
+    NoExits,
+    InvalidAbsorption(f64),
+    InvalidScattering(f64)
+}
+
+impl fmt::Display for WhiteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WhiteError::NoExits => write!(f, "the run was not a white Monte Carlo run, enable it before initializing"),
+            WhiteError::InvalidAbsorption(mua) => write!(f, "mua = {} must not be negative", mua),
+            WhiteError::InvalidScattering(mus) => write!(f, "mus = {} must be positive", mus)
+        }
+    }
+}
+
+impl std::error::Error for WhiteError {}
+
+// Reflectance for the scaled optical properties, scaled like FinalResults.
+#[wasm_bindgen]
+pub struct WhiteResults {
+    pub rd: f64,                // Diffuse reflectance [-]
+
+    #[wasm_bindgen(skip)]
+    pub rd_r: Vec<f64>,         // [ir], [1/cm2]
+    #[wasm_bindgen(skip)]
+    pub rd_rt: Vec<f64>,        // [ir * nt + it], [1/(cm2 ps)]
+    #[wasm_bindgen(skip)]
+    pub rd_t: Vec<f64>          // [it], [1/ps]
+}
+
+#[wasm_bindgen]
+impl WhiteResults {
+    pub fn get_rd_r(&self) -> Float64Array {
+        Float64Array::from(self.rd_r.as_slice())
+    }
+
+    pub fn get_rd_rt(&self) -> Float64Array {
+        Float64Array::from(self.rd_rt.as_slice())
+    }
+
+    pub fn get_rd_t(&self) -> Float64Array {
+        Float64Array::from(self.rd_t.as_slice())
+    }
+}
+
+impl Results {
+    pub fn white_exits(&self) -> Option<&WhiteExits> {
+        self.white.as_ref()
+    }
+
+    /***********************************************************
+     * Reflectance of the layer with absorption mua and
+     * scattering mus, on the radial and time grid of the run,
+     * from the exits of a white Monte Carlo run. Radii and
+     * times beyond the grid are collected in the last bin.
+     ****/
+    pub fn scale_white(&self, mua: f64, mus: f64) -> Result<WhiteResults, WhiteError> {
+        let white = self.white.as_ref().ok_or(WhiteError::NoExits)?;
+        if mua.is_nan() || mua < 0.0 {
+            return Err(WhiteError::InvalidAbsorption(mua));
+        }
+        if mus.is_nan() || mus <= 0.0 {
+            return Err(WhiteError::InvalidScattering(mus));
+        }
+
+        let run_config = &self.run_config;
+        let nr = run_config.nr;
+        let nt = run_config.nt;
+        let dr = run_config.dr;
+        let layer = &run_config.layers[1];
+        let s = layer.mus / mus;
+
+        let mut rd_r = vec![0.0; nr];
+        let mut rd_rt = vec![0.0; nr * nt];
+        let mut rd = 0.0;
+
+        for exit in &white.exits {
+            let path_length = s * exit.path_length;
+            let weight = exit.weight * (-mua * path_length).exp();
+            let ir = ((s * exit.r / dr) as usize).min(nr - 1);
+
+            rd_r[ir] += weight;
+            rd += weight;
+            if let Some(it) = Go::exit_time_bin(run_config, path_length * layer.n / Go::C_VACUUM) {
+                rd_rt[ir * nt + it] += weight;
+            }
+        }
+
+        let n_photons = self.n_photons as f64;
+        let pi = std::f64::consts::PI;
+        let mut rd_t = vec![0.0; nt];
+        for ir in 0..nr {
+            /* area is 2*PI*[(ir+0.5)*dr]*dr. */
+            let area = 2.0 * pi * (ir as f64 + 0.5) * dr * dr;
+            rd_r[ir] /= area * n_photons;
+            for it in 0..nt {
+                rd_t[it] += rd_rt[ir * nt + it] / (run_config.dt * n_photons);
+                rd_rt[ir * nt + it] /= area * run_config.dt * n_photons;
+            }
+        }
+
+        Ok(WhiteResults { rd: rd / n_photons, rd_r, rd_rt, rd_t })
+    }
+}
+
+// A white run of one thick layer with the scattering of the first layer of sample.mci,
+// dropping photons early enough for the tests to be quick.
+#[cfg(test)]
+pub(crate) fn white_run() -> crate::RunConfig::RunConfig {
+    let mut run_config = crate::MonteCarloConfig::sample_run();
+    run_config.layers.drain(2..4);
+    run_config.layers[1].mua = 0.0;
+    run_config.layers[1].d = 100.0;
+    run_config.white = true;
+    run_config.white_max_path = 1E3;
+    run_config.update_layer_boundaries();
+    run_config.update_cos_crit();
+    run_config
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Simulation;
+
+    fn simulate(run_config: crate::RunConfig::RunConfig, n_photons: usize) -> Simulation {
+        let mut simulation = Simulation::new();
+        simulation.set_seed(3);
+        simulation.configure(run_config).unwrap();
+        simulation.launch_photons(n_photons);
+        simulation
+    }
+
+    #[test]
+    fn unscaled_matches_simulation() {
+        let simulation = simulate(white_run(), 2000);
+        let mus0 = simulation.run_config().layers[1].mus;
+        let white = simulation.results().scale_white(0.0, mus0).unwrap();
+        let result = simulation.finalize(2000);
+
+        assert!((white.rd - result.rd).abs() < 1E-12 * result.rd);
+        for (a, b) in white.rd_r.iter().zip(&result.rd_r) {
+            assert!((a - b).abs() <= 1E-12 * b);
+        }
+    }
+
+    #[test]
+    fn scaled_matches_new_simulation() {
+        let white = simulate(white_run(), 20000).results().scale_white(1.0, 50.0).unwrap();
+
+        let mut run_config = white_run();
+        run_config.white = false;
+        run_config.layers[1].mua = 1.0;
+        run_config.layers[1].mus = 50.0;
+        let expected = simulate(run_config, 20000).finalize(20000);
+        assert!((white.rd - expected.rd).abs() < 0.05 * expected.rd, "{} vs {}", white.rd, expected.rd);
+    }
+
+    #[test]
+    fn rejects_invalid_properties() {
+        let simulation = simulate(crate::MonteCarloConfig::sample_run(), 10);
+        assert_eq!(simulation.results().scale_white(1.0, 10.0).err(), Some(WhiteError::NoExits));
+
+        let simulation = simulate(white_run(), 10);
+        let results = simulation.results();
+        assert_eq!(results.scale_white(-1.0, 10.0).err(), Some(WhiteError::InvalidAbsorption(-1.0)));
+        assert_eq!(results.scale_white(1.0, 0.0).err(), Some(WhiteError::InvalidScattering(0.0)));
+    }
+}
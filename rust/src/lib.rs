@@ -1,58 +1,226 @@
 #![allow(non_snake_case)]
-use rand_mt::Mt64;
 use wasm_bindgen::prelude::*;
 use js_sys::Float64Array;
 
+mod Checkpoint;
+pub mod Convergence;
+pub mod Convolution;
+pub mod Fluorescence;
 mod Go;
+pub mod Mie;
+pub mod Inverse;
 mod PhotonPacket;
-mod RunConfig;
+mod Sensitivity;
+mod Twister;
+pub mod Uncertainty;
+pub mod MonteCarloConfig;
+pub mod OutputCalc;
+pub mod OutputWriter;
+pub mod Perturbation;
+pub mod PhaseFunction;
+pub mod Polarization;
+pub mod ResultsIO;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod SimulationRunner;
+pub mod RunConfig;
+pub mod Source;
+pub mod White;
 
 pub struct Results {
+    run_config: RunConfig::RunConfig,   // Config the tallies were recorded with
+    n_photons: usize,                   // Number of photons launched
+
     tt_ra: Vec<f64>,
     rd_ra: Vec<f64>,
     rd_x: Vec<f64>,
     a_rz: Vec<f64>,
     w_txz: Vec<f64>,
+    rd_rt: Vec<f64>,
+    tt_rt: Vec<f64>,
+    rd_r_fd: Vec<f64>,                  // [(ifreq * nr + ir) * 2], real and imaginary parts
+    rd_path: Vec<f64>,                  // [ir * nl + il], weighted sum of partial pathlengths, empty if npath is 0
+    rd_path_hist: Vec<f64>,             // [(ir * nl + il) * npath + ip]
+    jacobian: Vec<f64>,                 // [(idet * nr + ir) * nz + iz], reflected weight times pathlength
+    stokes_xy: Vec<f64>,                // [(iy * nr * 2 + ix) * 4 + k], weighted Stokes vectors
     rd_unscattered: f64,
     tt_unscattered: f64,
+
+    moments: Option<Uncertainty::Moments>,  // Only if run_config.track_uncertainty
+    histories: Option<Perturbation::Histories>, // Only if run_config.record_histories
+    white: Option<White::WhiteExits>,   // Only if run_config.white
+    fluorescence: Option<Fluorescence::FluorescenceTallies>,   // Only if a layer has fluorescence
+}
+
+impl Results {
+    pub fn new(run_config: &RunConfig::RunConfig) -> Results {
+        let n_tissue_layers = run_config.layers.len().saturating_sub(2);
+        Results {
+            run_config: run_config.clone(),
+            n_photons: 0,
+            tt_ra: vec![0.0; run_config.na * run_config.nr],
+            rd_ra: vec![0.0; run_config.na * run_config.nr],
+            rd_x: vec![0.0; run_config.nr * 2],
+            a_rz: vec![0.0; run_config.nz * run_config.nr],
+            w_txz: vec![0.0; run_config.nz * run_config.nr * 2 * run_config.nt],
+            rd_rt: vec![0.0; run_config.nr * run_config.nt],
+            tt_rt: vec![0.0; run_config.nr * run_config.nt],
+            rd_r_fd: vec![0.0; run_config.frequencies.len() * run_config.nr * 2],
+            rd_path: vec![0.0; run_config.nr * n_tissue_layers * run_config.npath.min(1)],
+            rd_path_hist: vec![0.0; run_config.nr * n_tissue_layers * run_config.npath],
+            jacobian: vec![0.0; run_config.detectors.len() * run_config.nr * run_config.nz],
+            stokes_xy: vec![0.0; if run_config.polarized { 4 * run_config.nr * run_config.nr * 4 } else { 0 }],
+            rd_unscattered: 0.0,
+            tt_unscattered: 0.0,
+            moments: run_config.track_uncertainty.then(|| Uncertainty::Moments::new(run_config)),
+            histories: run_config.record_histories.then(Perturbation::Histories::new),
+            white: run_config.white.then(White::WhiteExits::new),
+            fluorescence: run_config.fluorescence().then(|| Fluorescence::FluorescenceTallies::new(run_config)),
+        }
+    }
+
+    pub fn run_config(&self) -> &RunConfig::RunConfig {
+        &self.run_config
+    }
+
+    pub fn n_photons(&self) -> usize {
+        self.n_photons
+    }
+
+    pub fn moments(&self) -> Option<&Uncertainty::Moments> {
+        self.moments.as_ref()
+    }
+
+    pub fn fluorescence(&self) -> Option<&Fluorescence::FluorescenceTallies> {
+        self.fluorescence.as_ref()
+    }
+
+    // Add the tallies of another simulation of the same run config.
+    pub(crate) fn accumulate(&mut self, other: &Results) {
+        let add = |a: &mut Vec<f64>, b: &Vec<f64>| {
+            for (x, y) in a.iter_mut().zip(b) {
+                *x += y;
+            }
+        };
+        add(&mut self.tt_ra, &other.tt_ra);
+        add(&mut self.rd_ra, &other.rd_ra);
+        add(&mut self.rd_x, &other.rd_x);
+        add(&mut self.a_rz, &other.a_rz);
+        add(&mut self.w_txz, &other.w_txz);
+        add(&mut self.rd_rt, &other.rd_rt);
+        add(&mut self.tt_rt, &other.tt_rt);
+        add(&mut self.rd_r_fd, &other.rd_r_fd);
+        add(&mut self.rd_path, &other.rd_path);
+        add(&mut self.rd_path_hist, &other.rd_path_hist);
+        add(&mut self.jacobian, &other.jacobian);
+        add(&mut self.stokes_xy, &other.stokes_xy);
+        self.rd_unscattered += other.rd_unscattered;
+        self.tt_unscattered += other.tt_unscattered;
+        self.n_photons += other.n_photons;
+        if let (Some(moments), Some(other)) = (self.moments.as_mut(), other.moments.as_ref()) {
+            moments.accumulate(other);
+        }
+        if let (Some(histories), Some(other)) = (self.histories.as_mut(), other.histories.as_ref()) {
+            histories.append(other);
+        }
+        if let (Some(white), Some(other)) = (self.white.as_mut(), other.white.as_ref()) {
+            white.append(other);
+        }
+        if let (Some(fluorescence), Some(other)) = (self.fluorescence.as_mut(), other.fluorescence.as_ref()) {
+            fluorescence.accumulate(other);
+        }
+    }
 }
 
 #[wasm_bindgen]
 pub struct Simulation {
     run_config: RunConfig::RunConfig,
     r_specular: f64,
-    rng: Mt64,
+    rng: Twister::Twister,
     results: Results,
+    scores: Option<Uncertainty::PhotonScores>,
+    time_bins: Option<Go::TimeBins>,    // Only if run_config.nt > 0
+    track_paths: bool,                  // Photons keep their partial pathlengths
+    photon: PhotonPacket::PhotonPacket, // Reused for every photon
+    voxel_paths: Option<Sensitivity::VoxelPaths>, // Only if run_config.detectors is not empty
+    emissions: Vec<Fluorescence::Emission>,       // Fluorescence packets waiting to be traced
+    polarization: Option<Polarization::Tables>,   // Only if run_config.polarized
 }
 
 impl Simulation {
     fn initialize_results(&mut self) {
-        self.results.tt_ra = vec![0.0; self.run_config.na * self.run_config.nr];
-        self.results.rd_ra = vec![0.0; self.run_config.na * self.run_config.nr];
-        self.results.rd_x = vec![0.0; self.run_config.nr * 2];
-        self.results.a_rz = vec![0.0; self.run_config.nz * self.run_config.nr];
-        self.results.w_txz = vec![0.0; self.run_config.nz * self.run_config.nr * 2 * self.run_config.nt];
-        self.results.rd_unscattered = 0.0;
-        self.results.tt_unscattered = 0.0;
+        self.results = Results::new(&self.run_config);
+        self.scores = self.run_config.track_uncertainty.then(|| Uncertainty::PhotonScores::new(&self.run_config));
+        self.time_bins = (self.run_config.nt > 0).then(|| Go::TimeBins::new(&self.run_config));
+        self.track_paths = self.run_config.npath > 0 || self.run_config.record_histories || self.run_config.white;
+        self.voxel_paths = (!self.run_config.detectors.is_empty()).then(|| Sensitivity::VoxelPaths::new(&self.run_config));
+        self.polarization = self.run_config.polarized.then(|| Polarization::Tables::new(&self.run_config));
+    }
+
+    pub fn run_config(&self) -> &RunConfig::RunConfig {
+        &self.run_config
+    }
+
+    pub fn results(&self) -> &Results {
+        &self.results
+    }
+
+    pub fn into_results(self) -> Results {
+        self.results
+    }
+
+    // Validate the run config and prepare the derived layer data and the tallies.
+    pub fn try_initialize(&mut self) -> Result<(), Vec<RunConfig::ConfigError>> {
+        self.run_config.validate()?;
+
+        self.run_config.update_layer_boundaries();
+        self.run_config.update_cos_crit();
+        self.run_config.update_scatterer_g();
+        self.initialize_results();
+
+        self.r_specular = Go::calculate_r_specular(&self.run_config);
+        Ok(())
+    }
+
+    // Use a run parsed from an .mci file and initialize the simulation with it.
+    pub fn configure(&mut self, run_config: RunConfig::RunConfig) -> Result<(), Vec<RunConfig::ConfigError>> {
+        self.run_config = run_config;
+        self.try_initialize()
     }
 }
 
+fn config_errors_to_js(errors: Vec<RunConfig::ConfigError>) -> JsError {
+    let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+    JsError::new(&format!("Invalid run configuration: {}", messages.join("; ")))
+}
+
+// Number of runs in the body of an .mci file.
+#[wasm_bindgen]
+pub fn mci_run_count(body: &str) -> Result<usize, JsError> {
+    let config = MonteCarloConfig::MonteCarloConfig::parse(body).map_err(|e| JsError::new(&e.to_string()))?;
+    Ok(config.runs.len())
+}
+
+// Optical properties of spheres in a medium with refractive index n_medium.
+#[wasm_bindgen]
+pub fn compute_mie(spheres: &Mie::Spheres, n_medium: f64) -> Result<Mie::MieResult, JsError> {
+    spheres.calculate(n_medium).map_err(|e| JsError::new(&e.to_string()))
+}
+
 #[wasm_bindgen]
 impl Simulation {
     pub fn new() -> Simulation {
         Simulation {
             run_config: RunConfig::RunConfig::new(),
             r_specular: 0.0,
-            rng: Mt64::new(0),
-            results: Results {
-                tt_ra: Vec::new(),
-                rd_ra: Vec::new(),
-                rd_x: Vec::new(),
-                a_rz: Vec::new(),
-                w_txz: Vec::new(),
-                rd_unscattered: 0.0,
-                tt_unscattered: 0.0,
-            },
+            rng: Twister::Twister::new(0),
+            results: Results::new(&RunConfig::RunConfig::new()),
+            scores: None,
+            time_bins: None,
+            track_paths: false,
+            photon: PhotonPacket::PhotonPacket::new(),
+            voxel_paths: None,
+            emissions: Vec::new(),
+            polarization: None,
         }
     }
 
@@ -83,8 +251,111 @@ impl Simulation {
         self.initialize_results();
     }
 
+    // Parse the body of an .mci file and configure the simulation with one of its runs.
+    pub fn load_mci(&mut self, body: &str, run: usize) -> Result<(), JsError> {
+        let config = MonteCarloConfig::MonteCarloConfig::parse(body).map_err(|e| JsError::new(&e.to_string()))?;
+        let run_count = config.runs.len();
+        match config.runs.into_iter().nth(run) {
+            Some(run_config) => self.configure(run_config).map_err(config_errors_to_js),
+            None => Err(JsError::new(&format!("run {} does not exist, the input has {} runs", run, run_count)))
+        }
+    }
+
+    pub fn get_number_of_photons(&self) -> usize {
+        self.run_config.number_of_photons
+    }
+
+    // Tally second moments for relative errors, takes effect on initialize.
+    pub fn set_track_uncertainty(&mut self, track: bool) {
+        self.run_config.track_uncertainty = track;
+    }
+
+    // Width and start of the W_txz time bins in ps, takes effect on initialize.
+    pub fn set_time_bins(&mut self, dt: f64, t0: f64) {
+        self.run_config.dt = dt;
+        self.run_config.t0 = t0;
+    }
+
+    // Modulation frequency in MHz for the frequency-domain reflectance, takes effect on initialize.
+    pub fn add_frequency(&mut self, frequency: f64) {
+        self.run_config.frequencies.push(frequency);
+    }
+
+    pub fn clear_frequencies(&mut self) {
+        self.run_config.frequencies.clear();
+    }
+
+    // Radial bin of the reflectance to map the absorption sensitivity for, takes effect on initialize.
+    pub fn add_detector(&mut self, ir: usize) {
+        self.run_config.detectors.push(ir);
+    }
+
+    pub fn clear_detectors(&mut self) {
+        self.run_config.detectors.clear();
+    }
+
+    // Keep the histories of the photons leaving the tissue for perturb, takes effect on initialize.
+    pub fn set_record_histories(&mut self, record: bool) {
+        self.run_config.record_histories = record;
+    }
+
+    // Record the exits for white Monte Carlo scaling, dropping photons after max_path
+    // mean free paths. Takes effect on initialize.
+    pub fn set_white(&mut self, white: bool, max_path: f64) {
+        self.run_config.white = white;
+        self.run_config.white_max_path = max_path;
+    }
+
+    // Fluorophore at the excitation wavelength and optical properties at the emission wavelength of
+    // a layer, counting the medium above as 0. Every layer between the ambient media needs emission
+    // properties once one has them. Takes effect on initialize.
+    pub fn set_layer_fluorescence(&mut self, layer: usize, mua_f: f64, quantum_yield: f64, mua_em: f64, mus_em: f64, g_em: f64) -> Result<(), JsError> {
+        let count = self.run_config.layers.len();
+        let Some(layer) = self.run_config.layers.get_mut(layer) else {
+            return Err(JsError::new(&format!("layer {} does not exist, there are {} layers", layer, count)));
+        };
+        layer.fluorescence = Some(Fluorescence::LayerFluorescence { mua_f, quantum_yield, mua_em, mus_em, g_em });
+        Ok(())
+    }
+
+    // Trace Stokes vectors at the vacuum wavelength in um, for incident light with Stokes vector
+    // [i, q, u, v] relative to the x axis. Takes effect on initialize.
+    pub fn set_polarization(&mut self, polarized: bool, wavelength: f64, i: f64, q: f64, u: f64, v: f64) {
+        self.run_config.polarized = polarized;
+        self.run_config.wavelength = wavelength;
+        self.run_config.stokes = [i, q, u, v];
+    }
+
+    // Spheres of radius in um and refractive index n_sphere scattering in a layer, counting the
+    // medium above as 0. Used by polarized runs, takes effect on initialize, which sets the g of
+    // the layer to that of the spheres.
+    pub fn set_layer_scatterer(&mut self, layer: usize, radius: f64, n_sphere: f64) -> Result<(), JsError> {
+        let count = self.run_config.layers.len();
+        let Some(layer) = self.run_config.layers.get_mut(layer) else {
+            return Err(JsError::new(&format!("layer {} does not exist, there are {} layers", layer, count)));
+        };
+        layer.scatterer = Some(Polarization::Scatterer { radius, n_sphere });
+        Ok(())
+    }
+
+    // Source of the given kind with parameters [] for a pencil beam, [radius] at 1/e^2 for a
+    // Gaussian beam, [radius] for a flat beam, [width, height] for a rectangle, [inner, outer]
+    // for an annulus, [length] for a line along x, [radius, na] for a fiber, [radius, depth]
+    // for a focused beam, [depth] for an isotropic point and [radius] for diffuse light, in cm.
+    // Takes effect on initialize.
+    pub fn set_source(&mut self, kind: Source::SourceKind, parameters: &[f64]) -> Result<(), JsError> {
+        self.run_config.source = Source::Source::from_parameters(kind, parameters).map_err(|e| JsError::new(&e.to_string()))?;
+        Ok(())
+    }
+
+    // Width in cm and number of the pathlength histogram bins, takes effect on initialize.
+    pub fn set_path_bins(&mut self, dl: f64, npath: usize) {
+        self.run_config.dl = dl;
+        self.run_config.npath = npath;
+    }
+
     pub fn set_seed(&mut self, seed: u64) {
-        self.rng = Mt64::new(seed);
+        self.rng = Twister::Twister::new(seed);
     }
 
     pub fn clear_layers(&mut self) {
@@ -95,34 +366,52 @@ impl Simulation {
         self.run_config.add_layer(n, mua, mus, g, d);
     }
 
-    pub fn initialize(&mut self) {
-        self.run_config.update_layer_boundaries();
-        self.run_config.update_cos_crit();
-        self.initialize_results();
+    // Layer with a phase function of the given kind, with parameters [g] for Henyey-Greenstein,
+    // [g, beta] for modified Henyey-Greenstein, [g1, g2, f] for two-term Henyey-Greenstein,
+    // [g, alpha] for the Gegenbauer kernel, [] for Rayleigh and [radius, wavelength, n_sphere]
+    // in um for Mie spheres in the layer.
+    pub fn add_layer_phase(&mut self, n: f64, mua: f64, mus: f64, d: f64, kind: PhaseFunction::PhaseKind, parameters: &[f64]) -> Result<(), JsError> {
+        self.run_config.add_layer_phase(n, mua, mus, d, kind, parameters).map_err(|e| JsError::new(&e.to_string()))
+    }
 
-        self.r_specular = Go::calculate_r_specular(&self.run_config);
+    // Layer of spheres in a medium with refractive index n and absorption mua, with the mus, mua and
+    // g of the spheres, or their tabulated phase function. Returns the properties of the spheres.
+    pub fn add_layer_spheres(&mut self, n: f64, mua: f64, d: f64, spheres: &Mie::Spheres, tabulated: bool) -> Result<Mie::MieResult, JsError> {
+        self.run_config.add_layer_spheres(n, mua, d, spheres, tabulated).map_err(|e| JsError::new(&e.to_string()))
+    }
+
+    // Layer with the phase function p tabulated at the increasing cosines mu, linear in between.
+    pub fn add_layer_tabulated(&mut self, n: f64, mua: f64, mus: f64, d: f64, mu: &[f64], p: &[f64]) -> Result<(), JsError> {
+        self.run_config.add_layer_tabulated(n, mua, mus, d, mu, p).map_err(|e| JsError::new(&e.to_string()))
+    }
+
+    pub fn initialize(&mut self) -> Result<(), JsError> {
+        self.try_initialize().map_err(config_errors_to_js)
     }
 
     pub fn launch_photon(&mut self) {
-        let mut photon = PhotonPacket::PhotonPacket::new();
+        let mut photon = std::mem::replace(&mut self.photon, PhotonPacket::PhotonPacket::new());
 
         // Launch
-        Go::launch_photon(&self, &mut photon);
+        Go::launch_photon(self, &mut photon);
 
-        let mut tick = 0;
         while !photon.dead {
             Go::hop_drop_spin(self, &mut photon);
+        }
 
-            if tick < self.run_config.nt {
-                let ix: i64 = ((photon.x / self.run_config.dr).round() as i64) + (self.run_config.nr as i64);
-                let iz: usize = (photon.z / self.run_config.dz) as usize;
-                if ix >= 0 && ix < ((self.run_config.nr * 2) as i64) && iz < self.run_config.nz {
-                    self.results.w_txz[tick * self.run_config.nz * self.run_config.nr * 2 + (ix as usize) * self.run_config.nz + iz] += photon.weight;
-                }
+        while let Some(emission) = self.emissions.pop() {
+            Fluorescence::launch_emission(self, &mut photon, &emission);
+            while !photon.dead {
+                Go::hop_drop_spin(self, &mut photon);
             }
+        }
 
-            tick += 1;
+        if let (Some(scores), Some(moments)) = (self.scores.as_mut(), self.results.moments.as_mut()) {
+            scores.flush(moments);
         }
+        Sensitivity::clear(self);
+        self.results.n_photons += 1;
+        self.photon = photon;
     }
 
     pub fn launch_photons(&mut self, n: usize) {
@@ -131,6 +420,16 @@ impl Simulation {
         }
     }
 
+    // Launch photons until the relative errors are small enough or a budget is used up.
+    pub fn launch_until(&mut self, criteria: &Convergence::StopCriteria) -> Result<Convergence::ConvergenceReport, JsError> {
+        self.try_launch_until(criteria).map_err(|e| JsError::new(&e.to_string()))
+    }
+
+    // Fit layer properties of the configured run to a measurement, launching photons for every forward model.
+    pub fn fit_layers(&self, problem: &Inverse::InverseProblem, options: &Inverse::FitOptions) -> Result<Inverse::FitResult, JsError> {
+        Inverse::fit(&self.run_config, &problem.parameters, &problem.measurement, options).map_err(|e| JsError::new(&e.to_string()))
+    }
+
     pub fn get_tt_ra(&self) -> Float64Array {
         Float64Array::from(self.results.tt_ra.as_slice())
     }
@@ -151,6 +450,89 @@ impl Simulation {
         Float64Array::from(self.results.w_txz.as_slice())
     }
 
+    pub fn get_rd_rt(&self) -> Float64Array {
+        Float64Array::from(self.results.rd_rt.as_slice())
+    }
+
+    pub fn get_tt_rt(&self) -> Float64Array {
+        Float64Array::from(self.results.tt_rt.as_slice())
+    }
+
+    pub fn get_rd_r_fd(&self) -> Float64Array {
+        Float64Array::from(self.results.rd_r_fd.as_slice())
+    }
+
+    pub fn get_rd_path(&self) -> Float64Array {
+        Float64Array::from(self.results.rd_path.as_slice())
+    }
+
+    pub fn get_rd_path_hist(&self) -> Float64Array {
+        Float64Array::from(self.results.rd_path_hist.as_slice())
+    }
+
+    pub fn get_jacobian(&self) -> Float64Array {
+        Float64Array::from(self.results.jacobian.as_slice())
+    }
+
+    pub fn get_stokes_xy(&self) -> Float64Array {
+        Float64Array::from(self.results.stokes_xy.as_slice())
+    }
+
+    // Raw fluorescence tallies, empty without fluorescence.
+    pub fn get_rd_ra_fl(&self) -> Float64Array {
+        Float64Array::from(self.results.fluorescence.as_ref().map_or(&[][..], |f| f.rd_ra.as_slice()))
+    }
+
+    pub fn get_a_rz_fl(&self) -> Float64Array {
+        Float64Array::from(self.results.fluorescence.as_ref().map_or(&[][..], |f| f.a_rz.as_slice()))
+    }
+
+    pub fn get_photons_launched(&self) -> usize {
+        self.results.n_photons
+    }
+
+    // Raw tallies in the binary format of ResultsIO, to be merged elsewhere.
+    pub fn serialize_results(&self) -> Vec<u8> {
+        self.results.to_bytes()
+    }
+
+    // Add serialized tallies of another simulation of the same run config.
+    pub fn merge_results(&mut self, bytes: &[u8]) -> Result<(), JsError> {
+        let other = Results::from_bytes(bytes).map_err(|e| JsError::new(&e.to_string()))?;
+        self.results.merge(&other).map_err(|e| JsError::new(&e.to_string()))
+    }
+
+    // Full state of the simulation, see Checkpoint.
+    pub fn snapshot(&self) -> Vec<u8> {
+        self.to_snapshot()
+    }
+
+    // Continue a simulation from a snapshot.
+    pub fn restore(bytes: &[u8]) -> Result<Simulation, JsError> {
+        Simulation::from_snapshot(bytes).map_err(|e| JsError::new(&e.to_string()))
+    }
+
+    // Reflectance and transmittance for new mua and mus of the layers between the ambient media.
+    pub fn perturb(&self, mua: &[f64], mus: &[f64]) -> Result<Perturbation::PerturbedResults, JsError> {
+        self.results.perturb(mua, mus).map_err(|e| JsError::new(&e.to_string()))
+    }
+
+    // Reflectance for any mua and mus of the layer from the exits of a white Monte Carlo run.
+    pub fn scale_white(&self, mua: f64, mus: f64) -> Result<White::WhiteResults, JsError> {
+        self.results.scale_white(mua, mus).map_err(|e| JsError::new(&e.to_string()))
+    }
+
+    // Reflectance, absorption and fluence for a Gaussian or flat beam, from the pencil beam results so far.
+    pub fn convolve(&self, kind: Convolution::BeamKind, radius: f64, power: f64) -> Result<Convolution::ConvolvedResults, JsError> {
+        let results = self.finalize(self.results.n_photons);
+        Convolution::convolve(&self.run_config, &results, self.results.n_photons, &Convolution::Beam::new(kind, radius, power)).map_err(|e| JsError::new(&e.to_string()))
+    }
+
+    // Scaled results for the given number of launched photons.
+    pub fn finalize(&self, n_photons: usize) -> OutputCalc::FinalResults {
+        self.results.finalize(&self.run_config, n_photons)
+    }
+
     pub fn get_r_specular(&self) -> f64 {
         self.r_specular
     }
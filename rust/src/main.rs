@@ -0,0 +1,412 @@
+// Command-line driver that runs every run of an .mci file natively.
+//
+// Usage: mcml [--seed N] [--threads N] [--save-results] [--checkpoint SECONDS] [--uncertainty]
+//             [--target-error E] [--max-seconds S] [--source KIND[:P,...]]
+//             [--convolve gaussian|flat:RADIUS,POWER] input.mci
+//        mcml --merge output.mco results.mcr...
+
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use MonteCarloRS::Convergence::{StopCriteria, StopReason};
+use MonteCarloRS::Convolution::{self, Beam, BeamKind};
+use MonteCarloRS::MonteCarloConfig::MonteCarloConfig;
+use MonteCarloRS::OutputWriter::OutputWriter;
+use MonteCarloRS::Results;
+use MonteCarloRS::ResultsIO::check_compatible;
+use MonteCarloRS::RunConfig::{OutputFormat, RunConfig};
+use MonteCarloRS::SimulationRunner::{self, ParallelRun};
+use MonteCarloRS::Source::{Source, SourceKind};
+
+// Extension of the raw results saved next to each output file.
+const RESULTS_EXTENSION: &str = "mcr";
+
+// Extension of the checkpoint written next to each output file while it runs.
+const CHECKPOINT_EXTENSION: &str = "chk";
+
+// Extension of the convolved output written next to each output file by --convolve.
+const CONVOLUTION_EXTENSION: &str = "conv";
+
+// Photons launched by each thread before the first checkpoint interval is timed.
+const FIRST_STEP: usize = 10000;
+
+struct Options {
+    seed: Option<u64>,
+    threads: Option<usize>,
+    save_results: bool,
+    checkpoint: Option<Duration>,
+    uncertainty: bool,
+    target_error: Option<f64>,
+    max_seconds: Option<f64>,
+    merge_output: Option<String>,
+    source: Option<Source>,
+    convolve: Option<Beam>,
+    files: Vec<String>
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: mcml [--seed N] [--threads N] [--save-results] [--checkpoint SECONDS] [--uncertainty]");
+    eprintln!("            [--target-error E] [--max-seconds S] [--source KIND[:P,...]]");
+    eprintln!("            [--convolve gaussian|flat:RADIUS,POWER] input.mci");
+    eprintln!("       KIND is pencil, gaussian:RADIUS, flat:RADIUS, rectangle:WIDTH,HEIGHT,");
+    eprintln!("       annulus:INNER,OUTER, line:LENGTH, fiber:RADIUS,NA, focused:RADIUS,DEPTH,");
+    eprintln!("       isotropic:DEPTH or lambertian:RADIUS, in cm");
+    eprintln!("       --convolve writes the results for a beam of RADIUS cm and POWER W to output.mco.conv");
+    eprintln!("       mcml --merge output.mco results.mcr...");
+    std::process::exit(2);
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
+
+fn parse_args() -> Options {
+    let mut options = Options {
+        seed: None,
+        threads: None,
+        save_results: false,
+        checkpoint: None,
+        uncertainty: false,
+        target_error: None,
+        max_seconds: None,
+        merge_output: None,
+        source: None,
+        convolve: None,
+        files: Vec::new()
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--seed" => {
+                let value = args.next().unwrap_or_else(|| usage());
+                options.seed = Some(value.parse().unwrap_or_else(|_| usage()));
+            }
+            "--threads" => {
+                let value = args.next().unwrap_or_else(|| usage());
+                options.threads = Some(value.parse().unwrap_or_else(|_| usage()));
+            }
+            "--save-results" => options.save_results = true,
+            "--uncertainty" => options.uncertainty = true,
+            "--target-error" => {
+                let value = args.next().unwrap_or_else(|| usage());
+                options.target_error = Some(value.parse().unwrap_or_else(|_| usage()));
+            }
+            "--max-seconds" => {
+                let value = args.next().unwrap_or_else(|| usage());
+                options.max_seconds = Some(value.parse().unwrap_or_else(|_| usage()));
+            }
+            "--checkpoint" => {
+                let value = args.next().unwrap_or_else(|| usage());
+                let seconds: f64 = value.parse().unwrap_or_else(|_| usage());
+                options.checkpoint = Some(Duration::try_from_secs_f64(seconds).unwrap_or_else(|_| usage()));
+            }
+            "--source" => {
+                let value = args.next().unwrap_or_else(|| usage());
+                options.source = Some(parse_source(&value).unwrap_or_else(|| usage()));
+            }
+            "--convolve" => {
+                let value = args.next().unwrap_or_else(|| usage());
+                options.convolve = Some(parse_beam(&value).unwrap_or_else(|| usage()));
+            }
+            "--merge" => options.merge_output = Some(args.next().unwrap_or_else(|| usage())),
+            "-h" | "--help" => usage(),
+            _ => options.files.push(arg)
+        }
+    }
+
+    options
+}
+
+// A source such as gaussian:0.05, with its parameters after the colon.
+fn parse_source(spec: &str) -> Option<Source> {
+    let (name, values) = spec.split_once(':').unwrap_or((spec, ""));
+    let kind = match name {
+        "pencil" => SourceKind::Pencil,
+        "gaussian" => SourceKind::Gaussian,
+        "flat" => SourceKind::Flat,
+        "rectangle" => SourceKind::Rectangular,
+        "annulus" => SourceKind::Annular,
+        "line" => SourceKind::Line,
+        "fiber" => SourceKind::Fiber,
+        "focused" => SourceKind::Focused,
+        "isotropic" => SourceKind::Isotropic,
+        "lambertian" => SourceKind::Lambertian,
+        _ => return None
+    };
+    let parameters: Vec<f64> = values.split(',').filter(|v| !v.is_empty()).map(|v| v.parse().ok()).collect::<Option<_>>()?;
+    Source::from_parameters(kind, &parameters).ok()
+}
+
+// A beam such as gaussian:0.1,1 with its radius and power after the colon.
+fn parse_beam(spec: &str) -> Option<Beam> {
+    let (name, values) = spec.split_once(':')?;
+    let kind = match name {
+        "gaussian" => BeamKind::Gaussian,
+        "flat" => BeamKind::Flat,
+        _ => return None
+    };
+    let (radius, power) = values.split_once(',')?;
+    let beam = Beam::new(kind, radius.parse().ok()?, power.parse().ok()?);
+    beam.check().ok()?;
+    Some(beam)
+}
+
+fn write_output(output_file: &str, results: &Results, simulation_time: f64, beam: Option<&Beam>) {
+    let mut run_config = results.run_config().clone();
+    run_config.output_file = output_file.to_string();
+    run_config.number_of_photons = results.n_photons();
+
+    let result = results.finalize(&run_config, results.n_photons());
+    let mut output = OutputWriter::new();
+    output.write_result(&run_config, &result, simulation_time);
+
+    if let Err(e) = std::fs::write(output_file, output.build()) {
+        fail(format!("Cannot write {}: {}", output_file, e));
+    }
+
+    if let Some(beam) = beam {
+        let convolved = Convolution::convolve(&run_config, &result, results.n_photons(), beam).unwrap_or_else(|e| fail(format!("{}: {}", output_file, e)));
+        let mut output = OutputWriter::new();
+        output.write_convolution(&run_config, beam, &convolved);
+
+        let convolution_file = format!("{}.{}", output_file, CONVOLUTION_EXTENSION);
+        if let Err(e) = std::fs::write(&convolution_file, output.build()) {
+            fail(format!("Cannot write {}: {}", convolution_file, e));
+        }
+    }
+
+    if results.moments().is_some() {
+        eprintln!(
+            "Relative errors: Rd {:.3}%, A {:.3}%, Tt {:.3}%",
+            result.rd_error * 100.0,
+            result.a_error * 100.0,
+            result.tt_error * 100.0
+        );
+    }
+}
+
+// Combine raw results saved by --save-results, possibly on other machines, into one output file.
+fn merge(output_file: &str, files: &[String], beam: Option<&Beam>) {
+    if files.is_empty() {
+        usage();
+    }
+
+    let mut merged: Option<Results> = None;
+    for file in files {
+        let bytes = std::fs::read(file).unwrap_or_else(|e| fail(format!("Cannot read {}: {}", file, e)));
+        let results = Results::from_bytes(&bytes).unwrap_or_else(|e| fail(format!("{}: {}", file, e)));
+
+        match merged.as_mut() {
+            Some(merged) => merged.merge(&results).unwrap_or_else(|e| fail(format!("{}: {}", file, e))),
+            None => merged = Some(results)
+        }
+    }
+
+    let merged = merged.unwrap();
+    eprintln!("Merged {} photons from {} files", merged.n_photons(), files.len());
+    write_output(output_file, &merged, 0.0, beam);
+}
+
+// Resume from the checkpoint of an earlier, interrupted run if there is one for this run.
+fn load_checkpoint(checkpoint_file: &str, run_config: &RunConfig) -> Option<ParallelRun> {
+    let bytes = std::fs::read(checkpoint_file).ok()?;
+    let run = match ParallelRun::from_checkpoint(&bytes) {
+        Ok(run) => run,
+        Err(e) => {
+            eprintln!("Ignoring {}: {}", checkpoint_file, e);
+            return None;
+        }
+    };
+
+    if let Err(e) = check_compatible(run.run_config(), run_config) {
+        eprintln!("Ignoring {}: {}", checkpoint_file, e);
+        return None;
+    }
+    if run.photons_total() != run_config.number_of_photons {
+        eprintln!("Ignoring {}: it launches {} photons instead of {}", checkpoint_file, run.photons_total(), run_config.number_of_photons);
+        return None;
+    }
+
+    eprintln!(
+        "Resuming from {} with {}/{} photons on {} threads",
+        checkpoint_file,
+        run.photons_launched(),
+        run.photons_total(),
+        run.threads()
+    );
+    Some(run)
+}
+
+/***********************************************************
+ * Run in steps that take about interval each, writing the
+ * state of every thread to checkpoint_file after each step.
+ * The checkpoint is removed once the run has finished.
+ ****/
+fn run_with_checkpoints(
+    mut run: ParallelRun,
+    checkpoint_file: &str,
+    interval: Duration,
+    progress: &(dyn Fn(usize) + Sync),
+) -> Results {
+    let mut step = FIRST_STEP;
+    while !run.is_done() {
+        let time_start = Instant::now();
+        run.advance(step, progress);
+        let elapsed = time_start.elapsed();
+
+        // Write to a temporary file first so an interruption never leaves a broken checkpoint.
+        let temporary_file = format!("{}.tmp", checkpoint_file);
+        let written = std::fs::write(&temporary_file, run.checkpoint()).and_then(|_| std::fs::rename(&temporary_file, checkpoint_file));
+        if let Err(e) = written {
+            fail(format!("Cannot write {}: {}", checkpoint_file, e));
+        }
+
+        let rate = step as f64 / elapsed.as_secs_f64().max(1E-3);
+        step = ((rate * interval.as_secs_f64()) as usize).max(1);
+    }
+
+    std::fs::remove_file(checkpoint_file).ok();
+    run.into_results()
+}
+
+fn run(input_file: &str, options: &Options) {
+    let seed = options.seed.unwrap_or_else(|| {
+        SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+    });
+
+    let body = std::fs::read_to_string(input_file).unwrap_or_else(|e| fail(format!("Cannot read {}: {}", input_file, e)));
+    let config = MonteCarloConfig::parse(&body).unwrap_or_else(|e| fail(format!("{}: {}", input_file, e)));
+
+    let number_of_runs = config.runs.len();
+    let mut runs = config.runs;
+    if let Some(source) = options.source {
+        for run_config in &mut runs {
+            run_config.source = source;
+        }
+    }
+
+    // Check every run before starting so a batch does not fail halfway.
+    let mut valid = true;
+    for (i, run_config) in runs.iter().enumerate() {
+        if let Err(errors) = run_config.validate() {
+            for error in errors {
+                eprintln!("{}: run {}: {}", input_file, i + 1, error);
+            }
+            valid = false;
+        }
+    }
+    if options.convolve.is_some() && runs.iter().any(|run_config| run_config.source != Source::Pencil) {
+        eprintln!("{}: --convolve needs the results of a pencil beam", input_file);
+        valid = false;
+    }
+    if !valid {
+        std::process::exit(1);
+    }
+
+    if options.checkpoint.is_some() && stop_criteria(options, 1).is_some() {
+        fail("--checkpoint cannot be combined with --target-error or --max-seconds".to_string());
+    }
+
+    let threads = options.threads.unwrap_or_else(|| {
+        std::thread::available_parallelism().map_or(1, |n| n.get())
+    });
+
+    eprintln!("Seed: {}, threads: {}", seed, threads);
+
+    for (i, mut run_config) in runs.into_iter().enumerate() {
+        let criteria = stop_criteria(options, run_config.number_of_photons);
+        run_config.track_uncertainty = options.uncertainty || options.target_error.is_some();
+        let output_file = run_config.output_file.clone();
+        let number_of_photons = run_config.number_of_photons;
+
+        if run_config.output_format == OutputFormat::Binary {
+            eprintln!("Binary output is not supported, writing {} as ASCII", output_file);
+        }
+
+        // Print whenever the percentage changes.
+        let last_percent = Mutex::new(None);
+        let progress = |launched: usize| {
+            let percent = launched * 100 / number_of_photons;
+            let mut last_percent = last_percent.lock().unwrap();
+            if *last_percent < Some(percent) {
+                *last_percent = Some(percent);
+                eprint!(
+                    "\rRun {}/{} {}: {:3}% ({}/{} photons)",
+                    i + 1,
+                    number_of_runs,
+                    output_file,
+                    percent,
+                    launched,
+                    number_of_photons
+                );
+                std::io::stderr().flush().ok();
+            }
+        };
+
+        let time_start = Instant::now();
+        let results = match options.checkpoint {
+            Some(interval) => {
+                let checkpoint_file = format!("{}.{}", output_file, CHECKPOINT_EXTENSION);
+                let run = load_checkpoint(&checkpoint_file, &run_config).unwrap_or_else(|| {
+                    ParallelRun::new(&run_config, number_of_photons, seed, threads).expect("run config was validated")
+                });
+                run_with_checkpoints(run, &checkpoint_file, interval, &progress)
+            }
+            None => match criteria {
+                Some(criteria) => {
+                    let mut run = ParallelRun::new(&run_config, number_of_photons, seed, threads).expect("run config was validated");
+                    let report = run.advance_until(&criteria, &progress).unwrap_or_else(|e| fail(e.to_string()));
+                    let reason = match report.reason {
+                        StopReason::Converged => "target error reached",
+                        StopReason::PhotonBudget => "all photons launched",
+                        StopReason::TimeBudget => "time budget used up"
+                    };
+                    eprint!(" stopped after {} photons, {}", report.photons, reason);
+                    run.into_results()
+                }
+                None => SimulationRunner::run_parallel(&run_config, number_of_photons, seed, threads, &progress)
+                    .expect("run config was validated")
+            }
+        };
+        let simulation_time = time_start.elapsed().as_secs_f64();
+        eprintln!(" in {:.3} seconds", simulation_time);
+
+        write_output(&output_file, &results, simulation_time, options.convolve.as_ref());
+
+        if options.save_results {
+            let results_file = format!("{}.{}", output_file, RESULTS_EXTENSION);
+            if let Err(e) = std::fs::write(&results_file, results.to_bytes()) {
+                fail(format!("Cannot write {}: {}", results_file, e));
+            }
+        }
+    }
+}
+
+fn main() {
+    let options = parse_args();
+
+    match &options.merge_output {
+        Some(output_file) => merge(output_file, &options.files, options.convolve.as_ref()),
+        None if options.files.len() == 1 => run(&options.files[0], &options),
+        None => usage()
+    }
+}
+
+// Criteria for --target-error and --max-seconds, with the photons of the run as the budget.
+fn stop_criteria(options: &Options, number_of_photons: usize) -> Option<StopCriteria> {
+    if options.target_error.is_none() && options.max_seconds.is_none() {
+        return None;
+    }
+
+    let mut criteria = StopCriteria::new();
+    criteria.rd_error = options.target_error.unwrap_or(0.0);
+    criteria.max_seconds = options.max_seconds.unwrap_or(0.0);
+    criteria.max_photons = number_of_photons;
+    if let Err(e) = criteria.validate() {
+        fail(e.to_string());
+    }
+    Some(criteria)
+}
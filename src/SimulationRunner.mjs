@@ -63,7 +63,7 @@ export class SimulationRunner {
     console.log('Sending config to workers')
     let now = performance.now()
     // Send config to workers
-    await Promise.all(this.workers.map(worker => {
+    const configResponses = await Promise.all(this.workers.map(worker => {
       return worker.emit('config', {
         runConfig,
         seed: random.genrand_int31()
@@ -71,6 +71,12 @@ export class SimulationRunner {
     }))
     checkCancel()
 
+    const failed = configResponses.find(response => response && response.error)
+    if (failed) {
+      this.cancelSimulation()
+      throw new Error(failed.error)
+    }
+
     console.log('Config sent to workers in', performance.now() - now, 'ms')
     console.log('Launching photons')
     const taskDivision = 5000
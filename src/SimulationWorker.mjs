@@ -36,7 +36,12 @@ messageHandler.on('config', async (config) => {
     monteCarloSimulator.add_layer(layer.n, layer.mua, layer.mus, layer.g, layer.d)
   })
 
-  monteCarloSimulator.initialize()
+  // initialize throws when the run config is invalid
+  try {
+    monteCarloSimulator.initialize()
+  } catch (error) {
+    return { error: error.message }
+  }
 })
 
 messageHandler.on('launch', (numPhotons) => {